@@ -0,0 +1,10 @@
+//! Addresses as users type them, in the debugger and on command lines.
+
+/// Parses a hex address, with or without a `0x` or `$` prefix.
+pub fn parse_address(s: &str) -> Option<usize> {
+    let digits = s
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+    usize::from_str_radix(digits, 16).ok()
+}
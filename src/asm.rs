@@ -0,0 +1,1059 @@
+//! An assembler for the mnemonics of Cowgod's technical reference, the same
+//! syntax `chip8-dis --syntax cowgod` prints.
+//!
+//! ```
+//! use chip8::{assemble, Platform};
+//!
+//! let source = "
+//! ; comments start with a semicolon
+//! speed   equ 2               ; constants
+//! main:   LD V0, speed        ; labels end with a colon
+//!         LD I, ball
+//!         DRW V0, V1, 2
+//!         JP main
+//! ball:   sprite .##....., #..#....   ; rows of '#' and '.', 8 or 16 wide
+//!         db %0110, #FF, 12   ; bytes in binary, hex or decimal
+//!         dw 0x1234, ball     ; big-endian words
+//! ";
+//! let rom = assemble(source, Platform::Chip8).unwrap();
+//! assert_eq!(rom[..8], [0x60, 0x02, 0xA2, 0x08, 0xD0, 0x12, 0x12, 0x00]);
+//! assert_eq!(rom[8..], [0x60, 0x90, 0x06, 0xFF, 12, 0x12, 0x34, 0x02, 0x08]);
+//! ```
+//!
+//! Besides the instructions, the directives are `db`, `dw`, `sprite`, `org`,
+//! `equ` and `include "FILE"`, which reads a file relative to the one
+//! including it. Operands may add and subtract numbers, labels and
+//! constants. SCHIP and XO-CHIP mnemonics are rejected unless the target
+//! platform has them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+use crate::DEFAULT_LOAD_ADDRESS;
+
+/// An assembly error and where in the source it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column of the offending token.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    file: usize,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    location: Location,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Instruction(Token, Vec<Token>),
+    Bytes(Vec<Token>),
+    Words(Vec<Token>),
+    Sprite(Vec<u8>),
+}
+
+#[derive(Debug)]
+struct Statement {
+    addr: usize,
+    kind: Kind,
+}
+
+// How deeply includes may nest.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+struct Assembler {
+    platform: Platform,
+    files: Vec<String>,
+    // The files being included, outermost first, to catch cycles.
+    including: Vec<PathBuf>,
+    depth: usize,
+    symbols: HashMap<String, usize>,
+    statements: Vec<Statement>,
+    addr: usize,
+}
+
+/// Assembles `source` for `platform`. Includes are resolved relative to the
+/// current directory.
+pub fn assemble(source: &str, platform: Platform) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new(platform);
+    assembler.parse("<input>", source, Path::new("."))?;
+    assembler.emit()
+}
+
+/// Assembles the file at `path` for `platform`.
+pub fn assemble_file<P: AsRef<Path>>(path: P, platform: Platform) -> Result<Vec<u8>, AsmError> {
+    let path = path.as_ref();
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|error| AsmError {
+        file: name.clone(),
+        line: 0,
+        column: 0,
+        message: error.to_string(),
+    })?;
+
+    let mut assembler = Assembler::new(platform);
+    assembler
+        .including
+        .push(fs::canonicalize(path).unwrap_or_else(|_| path.into()));
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    assembler.parse(&name, &source, dir)?;
+    assembler.emit()
+}
+
+impl Assembler {
+    fn new(platform: Platform) -> Self {
+        Self {
+            platform,
+            files: vec![],
+            including: vec![],
+            depth: 0,
+            symbols: HashMap::new(),
+            statements: vec![],
+            addr: DEFAULT_LOAD_ADDRESS,
+        }
+    }
+
+    fn error(&self, location: Location, message: String) -> AsmError {
+        AsmError {
+            file: self.files[location.file].clone(),
+            line: location.line,
+            column: location.column,
+            message,
+        }
+    }
+
+    // Pass one: tokenizes, defines labels and constants, and lays out
+    // every statement so that pass two can resolve forward references.
+    fn parse(&mut self, name: &str, source: &str, dir: &Path) -> Result<(), AsmError> {
+        let file = self.files.len();
+        self.files.push(name.to_string());
+
+        for (i, line) in source.lines().enumerate() {
+            let mut tokens = tokenize(line, file, i + 1);
+            if tokens.is_empty() {
+                continue;
+            }
+
+            if tokens[0].text.ends_with(':') {
+                let label = tokens.remove(0);
+                let name = &label.text[..label.text.len() - 1];
+                self.define(name, self.addr, &label)?;
+            }
+            if tokens.is_empty() {
+                continue;
+            }
+
+            let mnemonic = tokens.remove(0);
+            let location = mnemonic.location;
+            if tokens.first().map(|t| t.text.to_ascii_lowercase()) == Some("equ".to_string()) {
+                let equ = tokens.remove(0);
+                let value = self.value_of(&equ, &tokens)?;
+                self.define(&mnemonic.text, value as usize, &mnemonic)?;
+                continue;
+            }
+
+            let operands = split_operands(tokens);
+            let kind = match mnemonic.text.to_ascii_lowercase().as_str() {
+                "db" => Kind::Bytes(operands),
+                "dw" => Kind::Words(operands),
+                "sprite" => Kind::Sprite(self.sprite(&mnemonic, &operands)?),
+                "org" => {
+                    let value = self.value_of(&mnemonic, &operands)?;
+                    let memory = self.platform.memory_size();
+                    if value < 0 || value >= memory as i64 {
+                        return Err(self.error(
+                            mnemonic.location,
+                            format!(
+                                "org {} is outside memory, the maximum is {:#X}",
+                                value,
+                                memory - 1
+                            ),
+                        ));
+                    }
+                    let addr = value as usize;
+                    if addr < self.addr {
+                        return Err(self.error(
+                            mnemonic.location,
+                            format!("org {:#05X} is before the current address", addr),
+                        ));
+                    }
+                    self.addr = addr;
+                    continue;
+                }
+                "include" => {
+                    let path = match operands.as_slice() {
+                        [path]
+                            if path.text.len() >= 2
+                                && path.text.starts_with('"')
+                                && path.text.ends_with('"') =>
+                        {
+                            path.text.trim_matches('"').to_string()
+                        }
+                        _ => {
+                            return Err(self
+                                .error(mnemonic.location, "usage: include \"FILE\"".to_string()))
+                        }
+                    };
+                    self.include(&dir.join(path), &operands[0])?;
+                    continue;
+                }
+                _ => Kind::Instruction(mnemonic, operands),
+            };
+
+            let size = match &kind {
+                Kind::Bytes(operands) => operands.len(),
+                Kind::Words(operands) => operands.len() * 2,
+                Kind::Sprite(bytes) => bytes.len(),
+                Kind::Instruction(_, operands) if is_long_load(operands) => 4,
+                Kind::Instruction(..) => 2,
+            };
+            if self.addr + size > self.platform.memory_size() {
+                return Err(self.error(
+                    location,
+                    format!(
+                        "runs past the end of memory at {:#X}",
+                        self.platform.memory_size()
+                    ),
+                ));
+            }
+            self.statements.push(Statement {
+                addr: self.addr,
+                kind,
+            });
+            self.addr += size;
+        }
+
+        Ok(())
+    }
+
+    fn include(&mut self, path: &Path, token: &Token) -> Result<(), AsmError> {
+        let source = fs::read_to_string(path).map_err(|error| {
+            self.error(
+                token.location,
+                format!("can't include {}: {}", path.display(), error),
+            )
+        })?;
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.into());
+        if self.including.contains(&canonical) {
+            return Err(self.error(
+                token.location,
+                format!("{} includes itself", path.display()),
+            ));
+        }
+        if self.depth == MAX_INCLUDE_DEPTH {
+            return Err(self.error(
+                token.location,
+                format!("includes are nested more than {} deep", MAX_INCLUDE_DEPTH),
+            ));
+        }
+
+        let dir: PathBuf = path.parent().unwrap_or_else(|| Path::new(".")).into();
+        self.including.push(canonical);
+        self.depth += 1;
+        let result = self.parse(&path.display().to_string(), &source, &dir);
+        self.depth -= 1;
+        self.including.pop();
+        result
+    }
+
+    fn define(&mut self, name: &str, value: usize, token: &Token) -> Result<(), AsmError> {
+        let valid = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid || register(name).is_some() {
+            return Err(self.error(token.location, format!("invalid name '{}'", name)));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(self.error(token.location, format!("'{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn sprite(&self, mnemonic: &Token, rows: &[Token]) -> Result<Vec<u8>, AsmError> {
+        if rows.is_empty() {
+            return Err(self.error(mnemonic.location, "sprite needs rows".to_string()));
+        }
+
+        let mut bytes = vec![];
+        let width = rows[0].text.len();
+        for row in rows {
+            if row.text.len() != width || (width != 8 && width != 16) {
+                return Err(self.error(
+                    row.location,
+                    "sprite rows must all be 8 or all be 16 pixels wide".to_string(),
+                ));
+            }
+
+            let mut bits = 0u16;
+            for c in row.text.chars() {
+                bits = bits << 1
+                    | match c {
+                        '#' | '1' => 1,
+                        '.' | '0' => 0,
+                        _ => {
+                            return Err(self.error(
+                                row.location,
+                                format!("sprite pixels are '#' or '.', not '{}'", c),
+                            ))
+                        }
+                    };
+            }
+            if width == 16 {
+                bytes.push((bits >> 8) as u8);
+            }
+            bytes.push(bits as u8);
+        }
+
+        Ok(bytes)
+    }
+
+    // Pass two: evaluates operands and writes out the image.
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        let mut image = vec![];
+        for statement in self.statements.iter() {
+            let mut bytes = vec![];
+            match &statement.kind {
+                Kind::Bytes(operands) => {
+                    for operand in operands {
+                        bytes.push(self.byte(operand)?);
+                    }
+                }
+                Kind::Words(operands) => {
+                    for operand in operands {
+                        let word = self.number(operand, 0xFFFF)? as u16;
+                        bytes.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+                Kind::Sprite(rows) => bytes.extend_from_slice(rows),
+                Kind::Instruction(mnemonic, operands) => {
+                    let instruction = self.instruction(mnemonic, operands)?;
+                    if !self.platform.includes(instruction.platform()) {
+                        return Err(self.error(
+                            mnemonic.location,
+                            format!(
+                                "{} needs the {} platform",
+                                mnemonic.text,
+                                instruction.platform()
+                            ),
+                        ));
+                    }
+                    bytes.extend_from_slice(&instruction.encode().to_be_bytes());
+                    if let Instruction::LongLoadIndex = instruction {
+                        let operand = Token {
+                            text: operands[1].text[5..].to_string(),
+                            location: operands[1].location,
+                        };
+                        let addr = self.number(&operand, 0xFFFF)? as u16;
+                        bytes.extend_from_slice(&addr.to_be_bytes());
+                    }
+                }
+            }
+
+            let offset = statement.addr - DEFAULT_LOAD_ADDRESS;
+            if image.len() < offset + bytes.len() {
+                image.resize(offset + bytes.len(), 0);
+            }
+            image[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        }
+
+        Ok(image)
+    }
+
+    fn instruction(&self, mnemonic: &Token, operands: &[Token]) -> Result<Instruction, AsmError> {
+        use Instruction::*;
+
+        let name = mnemonic.text.to_ascii_uppercase();
+        let texts: Vec<String> = operands
+            .iter()
+            .map(|t| t.text.to_ascii_uppercase())
+            .collect();
+        let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+        let reg = |i: usize| self.register(&operands[i]);
+
+        let instruction = match (name.as_str(), texts.as_slice()) {
+            ("CLS", []) => Clear,
+            ("RET", []) => Return,
+            ("SCD", [_]) => ScrollDown {
+                n: self.nibble(&operands[0])?,
+            },
+            ("SCU", [_]) => ScrollUp {
+                n: self.nibble(&operands[0])?,
+            },
+            ("SCR", []) => ScrollRight,
+            ("SCL", []) => ScrollLeft,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => Lores,
+            ("HIGH", []) => Hires,
+            ("SYS", [_]) => System {
+                nnn: self.address(&operands[0])?,
+            },
+            ("JP", ["V0", _]) => JumpOffset {
+                nnn: self.address(&operands[1])?,
+            },
+            ("JP", [_]) => Jump {
+                nnn: self.address(&operands[0])?,
+            },
+            ("CALL", [_]) => Call {
+                nnn: self.address(&operands[0])?,
+            },
+
+            ("SE", [_, y]) if register(y).is_some() => SkipEq {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SE", [_, _]) => SkipEqImm {
+                x: reg(0)?,
+                nn: self.byte(&operands[1])?,
+            },
+            ("SNE", [_, y]) if register(y).is_some() => SkipNe {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SNE", [_, _]) => SkipNeImm {
+                x: reg(0)?,
+                nn: self.byte(&operands[1])?,
+            },
+
+            ("SAVE", [_]) => {
+                let (x, y) = self.register_range(&operands[0])?;
+                SaveRange { x, y }
+            }
+            ("LOAD", [_]) => {
+                let (x, y) = self.register_range(&operands[0])?;
+                LoadRange { x, y }
+            }
+
+            ("LD", ["I", long]) if is_long(long) => LongLoadIndex,
+            ("LD", ["I", _]) => LoadIndex {
+                nnn: self.address(&operands[1])?,
+            },
+            ("LD", ["DT", _]) => SetDelay { x: reg(1)? },
+            ("LD", ["ST", _]) => SetSound { x: reg(1)? },
+            ("LD", ["F", _]) => Font { x: reg(1)? },
+            ("LD", ["HF", _]) => BigFont { x: reg(1)? },
+            ("LD", ["B", _]) => Bcd { x: reg(1)? },
+            ("LD", ["[I]", _]) => Store { x: reg(1)? },
+            ("LD", ["R", _]) => SaveFlags { x: reg(1)? },
+            ("LD", [_, "DT"]) => GetDelay { x: reg(0)? },
+            ("LD", [_, "K"]) => WaitKey { x: reg(0)? },
+            ("LD", [_, "[I]"]) => Load { x: reg(0)? },
+            ("LD", [_, "R"]) => LoadFlags { x: reg(0)? },
+            ("LD", [_, y]) if register(y).is_some() => Move {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("LD", [_, _]) => LoadImm {
+                x: reg(0)?,
+                nn: self.byte(&operands[1])?,
+            },
+
+            ("ADD", ["I", _]) => AddIndex { x: reg(1)? },
+            ("ADD", [_, y]) if register(y).is_some() => Add {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("ADD", [_, _]) => AddImm {
+                x: reg(0)?,
+                nn: self.byte(&operands[1])?,
+            },
+            ("OR", [_, _]) => Or {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("AND", [_, _]) => And {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("XOR", [_, _]) => Xor {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SUB", [_, _]) => Sub {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SUBN", [_, _]) => SubReverse {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SHR", [_]) => ShiftRight {
+                x: reg(0)?,
+                y: reg(0)?,
+            },
+            ("SHR", [_, _]) => ShiftRight {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SHL", [_]) => ShiftLeft {
+                x: reg(0)?,
+                y: reg(0)?,
+            },
+            ("SHL", [_, _]) => ShiftLeft {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("RND", [_, _]) => Random {
+                x: reg(0)?,
+                nn: self.byte(&operands[1])?,
+            },
+            ("DRW", [_, _, _]) => Draw {
+                x: reg(0)?,
+                y: reg(1)?,
+                n: self.nibble(&operands[2])?,
+            },
+            ("SKP", [_]) => SkipKey { x: reg(0)? },
+            ("SKNP", [_]) => SkipNotKey { x: reg(0)? },
+            ("PLANE", [_]) => Plane {
+                n: self.nibble(&operands[0])?,
+            },
+            ("AUDIO", []) => LoadAudio,
+            ("PITCH", [_]) => Pitch { x: reg(0)? },
+
+            _ => {
+                let known = [
+                    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP",
+                    "CALL", "SE", "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB",
+                    "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+                ];
+                let message = if known.contains(&name.as_str()) {
+                    format!("invalid operands for {}", mnemonic.text)
+                } else {
+                    format!("unknown instruction '{}'", mnemonic.text)
+                };
+                return Err(self.error(mnemonic.location, message));
+            }
+        };
+
+        Ok(instruction)
+    }
+
+    fn register(&self, token: &Token) -> Result<usize, AsmError> {
+        register(&token.text).ok_or_else(|| {
+            self.error(
+                token.location,
+                format!("expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    fn register_range(&self, token: &Token) -> Result<(usize, usize), AsmError> {
+        let mut parts = token.text.splitn(2, '-').map(str::trim);
+        match (
+            parts.next().and_then(register),
+            parts.next().and_then(register),
+        ) {
+            (Some(x), Some(y)) => Ok((x, y)),
+            _ => Err(self.error(
+                token.location,
+                format!(
+                    "expected a register range like V1 - V4, found '{}'",
+                    token.text
+                ),
+            )),
+        }
+    }
+
+    fn nibble(&self, token: &Token) -> Result<u8, AsmError> {
+        self.number(token, 0xF).map(|n| n as u8)
+    }
+
+    fn address(&self, token: &Token) -> Result<u16, AsmError> {
+        self.number(token, 0xFFF).map(|n| n as u16)
+    }
+
+    // Bytes may also be negative, e.g. `ADD V0, -1`.
+    fn byte(&self, token: &Token) -> Result<u8, AsmError> {
+        let value = self.evaluate(token)?;
+        if (-0x80..=0xFF).contains(&value) {
+            Ok(value as u8)
+        } else {
+            Err(self.error(token.location, format!("{} doesn't fit in a byte", value)))
+        }
+    }
+
+    fn number(&self, token: &Token, max: i64) -> Result<i64, AsmError> {
+        let value = self.evaluate(token)?;
+        if (0..=max).contains(&value) {
+            Ok(value)
+        } else {
+            Err(self.error(
+                token.location,
+                format!("{} is out of range, the maximum is {:#X}", value, max),
+            ))
+        }
+    }
+
+    fn value_of(&self, directive: &Token, operands: &[Token]) -> Result<i64, AsmError> {
+        match split_operands(operands.to_vec()).as_slice() {
+            [operand] => self.evaluate(operand),
+            _ => Err(self.error(
+                directive.location,
+                format!("{} takes one value", directive.text),
+            )),
+        }
+    }
+
+    // Evaluates terms joined by + and -, each a number or a symbol.
+    fn evaluate(&self, token: &Token) -> Result<i64, AsmError> {
+        let mut total = 0i64;
+        let mut sign = 1;
+        let mut term = String::new();
+        for c in token.text.chars().chain(std::iter::once('+')) {
+            match c {
+                '+' | '-' if !term.trim().is_empty() => {
+                    total = self
+                        .term(term.trim(), token)?
+                        .checked_mul(sign)
+                        .and_then(|value| total.checked_add(value))
+                        .ok_or_else(|| {
+                            self.error(token.location, format!("{} overflows", token.text))
+                        })?;
+                    term.clear();
+                    sign = if c == '-' { -1 } else { 1 };
+                }
+                '-' => sign = -sign,
+                '+' => (),
+                c => term.push(c),
+            }
+        }
+        if token.text.trim().is_empty() {
+            return Err(self.error(token.location, "expected a value".to_string()));
+        }
+        Ok(total)
+    }
+
+    fn term(&self, term: &str, token: &Token) -> Result<i64, AsmError> {
+        if let Some(value) = parse_number(term) {
+            return Ok(value);
+        }
+        match self.symbols.get(term) {
+            Some(&value) => Ok(value as i64),
+            None => Err(self.error(token.location, format!("undefined symbol '{}'", term))),
+        }
+    }
+}
+
+fn register(text: &str) -> Option<usize> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+            digit.to_digit(16).map(|x| x as usize)
+        }
+        _ => None,
+    }
+}
+
+fn is_long(text: &str) -> bool {
+    text.len() > 5
+        && text
+            .get(..5)
+            .is_some_and(|p| p.eq_ignore_ascii_case("long "))
+}
+
+fn is_long_load(operands: &[Token]) -> bool {
+    operands.len() == 2 && is_long(&operands[1].text)
+}
+
+/// Parses `#2A`, `$2A`, `0x2A`, `%101`, `0b101` or decimal.
+fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) =
+        if let Some(hex) = text.strip_prefix('#').or_else(|| text.strip_prefix('$')) {
+            (hex, 16)
+        } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            (hex, 16)
+        } else if let Some(bin) = text.strip_prefix('%').or_else(|| text.strip_prefix("0b")) {
+            (bin, 2)
+        } else {
+            (text, 10)
+        };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+// Splits a line into whitespace separated tokens, dropping the comment.
+// Commas are kept as tokens of their own, and quoted strings are one token.
+fn tokenize(line: &str, file: usize, number: usize) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let location = Location {
+            file,
+            line: number,
+            column: line[..start].chars().count() + 1,
+        };
+        let text = match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            ',' => {
+                chars.next();
+                ",".to_string()
+            }
+            '"' => {
+                let mut text = String::new();
+                text.push(c);
+                chars.next();
+                for (_, c) in chars.by_ref() {
+                    text.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+                text
+            }
+            _ => {
+                let mut text = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || c == ',' || c == ';' {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                text
+            }
+        };
+        tokens.push(Token { text, location });
+    }
+    tokens
+}
+
+// Joins the tokens between commas, so `LONG label + 2` is one operand.
+fn split_operands(tokens: Vec<Token>) -> Vec<Token> {
+    let mut operands: Vec<Token> = vec![];
+    let mut joining = false;
+    for token in tokens {
+        if token.text == "," {
+            joining = false;
+        } else if joining {
+            let operand = operands.last_mut().unwrap();
+            operand.text.push(' ');
+            operand.text.push_str(&token.text);
+        } else {
+            operands.push(token);
+            joining = true;
+        }
+    }
+    operands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip8(source: &str) -> Result<Vec<u8>, AsmError> {
+        assemble(source, Platform::Chip8)
+    }
+
+    fn error(source: &str, platform: Platform) -> (usize, usize, String) {
+        let error = assemble(source, platform).unwrap_err();
+        (error.line, error.column, error.message)
+    }
+
+    // A fresh directory for include tests.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn labels_resolve_forwards_and_backwards() {
+        let source = "
+            start: JP end
+            loop:  CALL start
+            end:   JP loop + 2
+        ";
+        assert_eq!(chip8(source).unwrap(), [0x12, 0x04, 0x22, 0x00, 0x12, 0x04]);
+        assert_eq!(
+            error("a: CLS\na: CLS", Platform::Chip8),
+            (2, 1, "'a' is already defined".to_string())
+        );
+        assert_eq!(
+            error("V1: CLS", Platform::Chip8),
+            (1, 1, "invalid name 'V1'".to_string())
+        );
+    }
+
+    #[test]
+    fn equ_defines_constants() {
+        let source = "
+            two   equ 2
+            three equ two + 1
+                  LD V0, three - two
+                  ADD V1, -two
+        ";
+        assert_eq!(chip8(source).unwrap(), [0x60, 0x01, 0x71, 0xFE]);
+        assert_eq!(
+            error("x equ 1, 2", Platform::Chip8),
+            (1, 3, "equ takes one value".to_string())
+        );
+    }
+
+    #[test]
+    fn db_and_dw() {
+        let source = "
+            db 1, #02, $03, 0x04, %101, 0b11, -1
+            dw #1234, here
+            here: db 0
+        ";
+        assert_eq!(
+            chip8(source).unwrap(),
+            [1, 2, 3, 4, 5, 3, 0xFF, 0x12, 0x34, 0x02, 0x0B, 0]
+        );
+        assert_eq!(
+            error("db 256", Platform::Chip8),
+            (1, 4, "256 doesn't fit in a byte".to_string())
+        );
+    }
+
+    #[test]
+    fn sprites() {
+        let source = "
+            sprite #......#, .#....#.
+            sprite ################, 1..............1
+        ";
+        assert_eq!(chip8(source).unwrap(), [0x81, 0x42, 0xFF, 0xFF, 0x80, 0x01]);
+        assert_eq!(
+            error("sprite ##, ##..", Platform::Chip8),
+            (
+                1,
+                8,
+                "sprite rows must all be 8 or all be 16 pixels wide".to_string()
+            )
+        );
+        assert_eq!(
+            error("sprite ....x...", Platform::Chip8),
+            (1, 8, "sprite pixels are '#' or '.', not 'x'".to_string())
+        );
+    }
+
+    #[test]
+    fn org_moves_forward_only() {
+        assert_eq!(
+            chip8("CLS\norg #206\nRET").unwrap(),
+            [0x00, 0xE0, 0, 0, 0, 0, 0x00, 0xEE]
+        );
+        assert_eq!(
+            error("CLS\norg #200", Platform::Chip8),
+            (2, 1, "org 0x200 is before the current address".to_string())
+        );
+    }
+
+    #[test]
+    fn org_must_stay_within_memory() {
+        assert_eq!(
+            error("org -1\nCLS", Platform::Chip8),
+            (
+                1,
+                1,
+                "org -1 is outside memory, the maximum is 0xFFF".to_string()
+            )
+        );
+        assert_eq!(
+            error("org 0xFFFFFFFFF\nCLS", Platform::XoChip),
+            (
+                1,
+                1,
+                "org 68719476735 is outside memory, the maximum is 0xFFFF".to_string()
+            )
+        );
+        assert_eq!(
+            error("org 0x20000\nCLS", Platform::XoChip),
+            (
+                1,
+                1,
+                "org 131072 is outside memory, the maximum is 0xFFFF".to_string()
+            )
+        );
+        assert_eq!(
+            error("org 0xFFF\nCLS", Platform::Chip8),
+            (2, 1, "runs past the end of memory at 0x1000".to_string())
+        );
+        assert_eq!(
+            assemble("org 0xFFFE\nCLS", Platform::XoChip).unwrap().len(),
+            0x10000 - 0x200
+        );
+    }
+
+    #[test]
+    fn expressions_that_overflow_are_errors() {
+        assert_eq!(
+            error("db 0x7FFFFFFFFFFFFFFF + 1", Platform::Chip8),
+            (1, 4, "0x7FFFFFFFFFFFFFFF + 1 overflows".to_string())
+        );
+        assert_eq!(
+            error("db 1 - 0x7FFFFFFFFFFFFFFF - 3", Platform::Chip8),
+            (1, 4, "1 - 0x7FFFFFFFFFFFFFFF - 3 overflows".to_string())
+        );
+    }
+
+    #[test]
+    fn long_loads_need_xochip() {
+        let source = "LD I, LONG data + 1\ndata: db 7";
+        assert_eq!(
+            assemble(source, Platform::XoChip).unwrap(),
+            [0xF0, 0x00, 0x02, 0x05, 7]
+        );
+        let (line, column, message) = error(source, Platform::Chip8);
+        assert_eq!((line, column), (1, 1));
+        assert!(message.contains("needs the"), "{}", message);
+    }
+
+    #[test]
+    fn non_ascii_operands_are_errors() {
+        // Byte 5 falls inside a character, which used to panic in is_long
+        let (line, column, _) = error("LD I, aé€xx", Platform::XoChip);
+        assert_eq!((line, column), (1, 7));
+        assert!(chip8("LD I, ü").is_err());
+    }
+
+    #[test]
+    fn instructions_from_other_platforms_are_rejected() {
+        for &(source, platform) in [
+            ("SCR", Platform::SuperChip),
+            ("HIGH", Platform::SuperChip),
+            ("LD HF, V1", Platform::SuperChip),
+            ("PLANE 1", Platform::XoChip),
+            ("SAVE V1 - V2", Platform::XoChip),
+            ("AUDIO", Platform::XoChip),
+        ]
+        .iter()
+        {
+            assert!(assemble(source, platform).is_ok(), "{}", source);
+            let (_, column, message) = error(source, Platform::Chip8);
+            assert_eq!(column, 1, "{}", source);
+            assert!(message.contains("needs the"), "{}: {}", source, message);
+        }
+        assert!(assemble("PLANE 1", Platform::SuperChip).is_err());
+    }
+
+    #[test]
+    fn errors_point_at_the_token() {
+        assert_eq!(
+            error("CLS\n  main: LD V0, nowhere", Platform::Chip8),
+            (2, 16, "undefined symbol 'nowhere'".to_string())
+        );
+        assert_eq!(
+            error("\tFOO V1", Platform::Chip8),
+            (1, 2, "unknown instruction 'FOO'".to_string())
+        );
+        assert_eq!(
+            error("LD V0 ; comment", Platform::Chip8),
+            (1, 1, "invalid operands for LD".to_string())
+        );
+        assert_eq!(
+            error("DRW V0, VG, 1", Platform::Chip8),
+            (1, 9, "expected a register, found 'VG'".to_string())
+        );
+        assert_eq!(
+            error("JP #1000", Platform::Chip8),
+            (
+                1,
+                4,
+                "4096 is out of range, the maximum is 0xFFF".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn include_is_relative_to_the_including_file() {
+        let dir = scratch("include");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.asm"), "CALL draw\ninclude \"lib/draw.asm\"").unwrap();
+        fs::write(dir.join("lib/draw.asm"), "draw: include \"ret.asm\"").unwrap();
+        fs::write(dir.join("lib/ret.asm"), "RET").unwrap();
+        fs::write(dir.join("lib/bad.asm"), "CLS\nBAD").unwrap();
+        fs::write(dir.join("uses_bad.asm"), "include \"lib/bad.asm\"").unwrap();
+
+        assert_eq!(
+            assemble_file(dir.join("main.asm"), Platform::Chip8).unwrap(),
+            [0x22, 0x02, 0x00, 0xEE]
+        );
+        let error = assemble_file(dir.join("uses_bad.asm"), Platform::Chip8).unwrap_err();
+        assert!(error.file.ends_with("bad.asm"), "{}", error.file);
+        assert_eq!((error.line, error.column), (2, 1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_and_deep_nesting_are_errors() {
+        let dir = scratch("cycle");
+        fs::write(dir.join("self.asm"), "CLS\ninclude \"self.asm\"").unwrap();
+        fs::write(dir.join("a.asm"), "include \"b.asm\"").unwrap();
+        fs::write(dir.join("b.asm"), "include \"a.asm\"").unwrap();
+
+        let error = assemble_file(dir.join("self.asm"), Platform::Chip8).unwrap_err();
+        assert_eq!((error.line, error.column), (2, 9));
+        assert!(
+            error.message.ends_with("includes itself"),
+            "{}",
+            error.message
+        );
+        let error = assemble_file(dir.join("a.asm"), Platform::Chip8).unwrap_err();
+        assert!(error.file.ends_with("b.asm"), "{}", error.file);
+        assert!(
+            error.message.ends_with("includes itself"),
+            "{}",
+            error.message
+        );
+
+        // A chain of distinct files, one deeper than allowed
+        for depth in 0..=MAX_INCLUDE_DEPTH {
+            fs::write(
+                dir.join(format!("{}.asm", depth)),
+                format!("include \"{}.asm\"", depth + 1),
+            )
+            .unwrap();
+        }
+        fs::write(dir.join(format!("{}.asm", MAX_INCLUDE_DEPTH + 1)), "CLS").unwrap();
+        let error = assemble_file(dir.join("0.asm"), Platform::Chip8).unwrap_err();
+        assert!(error.message.contains("nested"), "{}", error.message);
+        assert!(assemble_file(dir.join("1.asm"), Platform::Chip8).is_ok());
+
+        // Including the same file twice in a row isn't a cycle
+        fs::write(
+            dir.join("twice.asm"),
+            "include \"16.asm\"\ninclude \"16.asm\"",
+        )
+        .unwrap();
+        assert_eq!(
+            assemble_file(dir.join("twice.asm"), Platform::Chip8).unwrap(),
+            [0x00, 0xE0, 0x00, 0xE0]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,26 @@
+/// Something that can play the CHIP-8 buzzer.
+///
+/// Frontends call `set_beeping` once per frame with the result of
+/// [`Chip8::beeping`](crate::Chip8::beeping). The output should sound for as
+/// long as the last call passed `true`.
+pub trait AudioOutput {
+    fn set_beeping(&mut self, beeping: bool);
+
+    /// Switches the buzzer to an XO-CHIP 1-bit sample pattern, played back
+    /// at `pattern_rate(pitch)` bits per second. Outputs that only support a
+    /// plain tone can ignore this.
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) {}
+}
+
+/// The playback rate of an XO-CHIP audio pattern, in bits per second.
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+/// An output that discards all sound, for headless use.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullAudio;
+
+impl AudioOutput for NullAudio {
+    fn set_beeping(&mut self, _beeping: bool) {}
+}
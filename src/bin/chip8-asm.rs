@@ -0,0 +1,40 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use chip8::{asm, Platform};
+
+const USAGE: &str = "usage: chip8-asm [--platform chip8|schip|xochip] [-o OUTPUT] SOURCE";
+
+fn main() -> Result<(), String> {
+    let mut source = None;
+    let mut output = None;
+    let mut platform = Platform::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                let value = args.next().ok_or(USAGE)?;
+                platform = value.parse().map_err(|e| format!("{}", e))?;
+            }
+            "-o" | "--output" => output = Some(args.next().ok_or(USAGE)?),
+            _ if source.is_none() && !arg.starts_with('-') => source = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let source = source.ok_or(USAGE)?;
+    let output = match output {
+        Some(output) => output,
+        None => Path::new(&source)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    let rom = asm::assemble_file(&source, platform).map_err(|e| e.to_string())?;
+    fs::write(&output, &rom).map_err(|e| format!("Error writing {}: {}", output, e))?;
+
+    Ok(())
+}
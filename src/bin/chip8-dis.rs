@@ -0,0 +1,36 @@
+use std::env;
+use std::fs;
+
+use chip8::address::parse_address;
+use chip8::disasm::{self, Syntax};
+
+const USAGE: &str = "usage: chip8-dis [--syntax octo|cowgod] [--base ADDR] ROM";
+
+fn main() -> Result<(), String> {
+    let mut rom = None;
+    let mut syntax = Syntax::default();
+    let mut base = chip8::DEFAULT_LOAD_ADDRESS;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => {
+                let value = args.next().ok_or(USAGE)?;
+                syntax = value.parse().map_err(|e| format!("{}", e))?;
+            }
+            "--base" => {
+                let value = args.next().ok_or(USAGE)?;
+                base = parse_address(&value)
+                    .ok_or_else(|| format!("invalid --base value '{}'", value))?;
+            }
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    let rom = rom.ok_or(USAGE)?;
+    let bytes = fs::read(&rom).map_err(|e| format!("Error reading {}: {}", rom, e))?;
+    print!("{}", disasm::disassemble(&bytes, base).format(syntax));
+
+    Ok(())
+}
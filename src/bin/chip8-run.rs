@@ -0,0 +1,404 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::BufWriter;
+
+use chip8::address::parse_address;
+use chip8::trace::{self, DEFAULT_TRACE_SIZE};
+use chip8::{octo, Chip8, Movie, Palette, Platform, Quirks, TraceFormat, Tracer};
+
+const USAGE: &str = "usage: chip8-run [--frames N] [--until-pc ADDR] [--until-mem ADDR=VALUE] \
+                     [--press FRAME:KEY[:FRAMES]] [--keys FILE] [--ipf INSTRUCTIONS_PER_FRAME] \
+                     [--platform chip8|schip|xochip] [--quirks vip|chip48|schip|xochip] \
+                     [--seed N] [--ascii] [--png FILE] [--scale N] [--palette NAME|RRGGBB,...] \
+                     [--registers FILE|-] \
+                     [--trace FILE] [--trace-format text|jsonl] [--trace-range START-END] \
+                     [--trace-only MNEMONIC,...] [--trace-size N] [--movie FILE] \
+                     [--record FILE] ROM|SOURCE.8o";
+
+// Ten seconds of emulated time.
+const DEFAULT_FRAMES: u64 = 600;
+
+/// Holds `key` down for `frames` frames, starting at frame `frame`.
+struct Press {
+    frame: u64,
+    key: usize,
+    frames: u64,
+}
+
+/// Why the run ended.
+enum Stop {
+    Frames,
+    Pc,
+    Memory,
+    Exit,
+}
+
+impl Stop {
+    fn name(&self) -> &'static str {
+        match self {
+            Stop::Frames => "frames",
+            Stop::Pc => "pc",
+            Stop::Memory => "memory",
+            Stop::Exit => "exit",
+        }
+    }
+}
+
+struct Options {
+    rom: String,
+    frames: Option<u64>,
+    until_pc: Option<usize>,
+    until_mem: Option<(usize, u8)>,
+    presses: Vec<Press>,
+    cycles_per_frame: u32,
+    platform: Option<Platform>,
+    quirks: Option<Quirks>,
+    seed: Option<u64>,
+    ascii: bool,
+    png: Option<String>,
+    scale: usize,
+    palette: Palette,
+    registers: Option<String>,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    trace_range: Option<(usize, usize)>,
+    trace_only: Vec<String>,
+    trace_size: usize,
+    movie: Option<String>,
+    record: Option<String>,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(USAGE)?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} value '{}'", flag, value))
+}
+
+/// Parses `FRAME KEY [FRAMES]`, split by `separator`. The key is a hex
+/// digit and frames are decimal.
+fn parse_press(s: &str, separator: char) -> Option<Press> {
+    let fields: Vec<&str> = s.split(separator).filter(|f| !f.is_empty()).collect();
+    let (frame, key, frames) = match fields.as_slice() {
+        [frame, key] => (frame, key, "1"),
+        [frame, key, frames] => (frame, key, *frames),
+        _ => return None,
+    };
+
+    let key = usize::from_str_radix(key, 16).ok().filter(|&k| k < 16)?;
+    Some(Press {
+        frame: frame.parse().ok()?,
+        key,
+        frames: frames.parse().ok()?,
+    })
+}
+
+/// Reads a key script: one `FRAME KEY [FRAMES]` press per line, with `#`
+/// starting a comment.
+fn read_keys(path: &str) -> Result<Vec<Press>, String> {
+    let script = fs::read_to_string(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+
+    let mut presses = vec![];
+    for (n, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let press = parse_press(line, ' ')
+            .ok_or_else(|| format!("{}:{}: expected FRAME KEY [FRAMES]", path, n + 1))?;
+        presses.push(press);
+    }
+    Ok(presses)
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        frames: None,
+        until_pc: None,
+        until_mem: None,
+        presses: vec![],
+        cycles_per_frame: chip8::DEFAULT_CYCLES_PER_FRAME,
+        platform: None,
+        quirks: None,
+        seed: None,
+        ascii: false,
+        png: None,
+        scale: 1,
+        palette: Palette::default(),
+        registers: None,
+        trace: None,
+        trace_format: TraceFormat::default(),
+        trace_range: None,
+        trace_only: vec![],
+        trace_size: DEFAULT_TRACE_SIZE,
+        movie: None,
+        record: None,
+    };
+    let mut rom = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => options.frames = Some(parse_number(&arg, args.next())?),
+            "--until-pc" => {
+                let value = args.next().ok_or(USAGE)?;
+                let addr = parse_address(&value)
+                    .ok_or_else(|| format!("invalid --until-pc value '{}'", value))?;
+                options.until_pc = Some(addr);
+            }
+            "--until-mem" => {
+                let value = args.next().ok_or(USAGE)?;
+                let condition = value.split_once('=').and_then(|(addr, byte)| {
+                    let byte = parse_address(byte).filter(|&b| b <= 0xFF)?;
+                    Some((parse_address(addr)?, byte as u8))
+                });
+                options.until_mem = Some(
+                    condition.ok_or_else(|| format!("invalid --until-mem value '{}'", value))?,
+                );
+            }
+            "--press" => {
+                let value = args.next().ok_or(USAGE)?;
+                let press = parse_press(&value, ':')
+                    .ok_or_else(|| format!("invalid --press value '{}'", value))?;
+                options.presses.push(press);
+            }
+            "--keys" => options
+                .presses
+                .extend(read_keys(&args.next().ok_or(USAGE)?)?),
+            "--ipf" => options.cycles_per_frame = parse_number(&arg, args.next())?,
+            "--platform" => {
+                let value = args.next().ok_or(USAGE)?;
+                options.platform = Some(value.parse().map_err(|e| format!("{}", e))?);
+            }
+            "--quirks" => {
+                let value = args.next().ok_or(USAGE)?;
+                options.quirks = Some(value.parse().map_err(|e| format!("{}", e))?);
+            }
+            "--seed" => options.seed = Some(parse_number(&arg, args.next())?),
+            "--ascii" => options.ascii = true,
+            "--png" => options.png = Some(args.next().ok_or(USAGE)?),
+            "--scale" => options.scale = parse_number(&arg, args.next())?,
+            "--palette" => {
+                let value = args.next().ok_or(USAGE)?;
+                options.palette = value.parse().map_err(|e| format!("{}", e))?;
+            }
+            "--registers" => options.registers = Some(args.next().ok_or(USAGE)?),
+            "--trace" => options.trace = Some(args.next().ok_or(USAGE)?),
+            "--trace-format" => {
+                let value = args.next().ok_or(USAGE)?;
+                options.trace_format = value.parse().map_err(|e| format!("{}", e))?;
+            }
+            "--trace-range" => {
+                let value = args.next().ok_or(USAGE)?;
+                let range = trace::parse_range(&value)
+                    .ok_or_else(|| format!("invalid --trace-range value '{}'", value))?;
+                options.trace_range = Some(range);
+            }
+            "--trace-only" => {
+                let value = args.next().ok_or(USAGE)?;
+                options.trace_only = value.split(',').map(|m| m.trim().to_string()).collect();
+            }
+            "--trace-size" => options.trace_size = parse_number(&arg, args.next())?,
+            "--movie" => options.movie = Some(args.next().ok_or(USAGE)?),
+            "--record" => options.record = Some(args.next().ok_or(USAGE)?),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    options.rom = rom.ok_or(USAGE)?;
+
+    // A movie brings its own configuration and input
+    let configured = !options.presses.is_empty()
+        || options.platform.is_some()
+        || options.quirks.is_some()
+        || options.seed.is_some();
+    if options.movie.is_some() && configured {
+        return Err(
+            "--movie can't be combined with --press, --keys, --platform, --quirks or --seed"
+                .to_string(),
+        );
+    }
+    Ok(options)
+}
+
+fn registers_json(chip8: &Chip8, frames: u64, stop: &Stop) -> String {
+    let list = |values: &mut dyn Iterator<Item = String>| values.collect::<Vec<_>>().join(", ");
+
+    let mut json = String::from("{\n");
+    let _ = writeln!(json, "  \"frames\": {},", frames);
+    let _ = writeln!(json, "  \"stop\": \"{}\",", stop.name());
+    let _ = writeln!(json, "  \"pc\": {},", chip8.pc());
+    let _ = writeln!(json, "  \"i\": {},", chip8.index());
+    let _ = writeln!(
+        json,
+        "  \"v\": [{}],",
+        list(&mut chip8.registers().iter().map(|v| v.to_string()))
+    );
+    let _ = writeln!(json, "  \"sp\": {},", chip8.sp());
+    let _ = writeln!(
+        json,
+        "  \"stack\": [{}],",
+        list(&mut chip8.stack()[..chip8.sp()].iter().map(|v| v.to_string()))
+    );
+    let _ = writeln!(json, "  \"delay_timer\": {},", chip8.delay_timer());
+    let _ = writeln!(json, "  \"sound_timer\": {}", chip8.sound_timer());
+    json.push_str("}\n");
+    json
+}
+
+fn main() -> Result<(), String> {
+    let options = parse_args()?;
+
+    // Octo sources run on XO-CHIP with Octo's quirks unless told otherwise
+    let octo = options.rom.ends_with(".8o");
+    let mut chip8 = Chip8::new();
+    chip8.set_cycles_per_frame(options.cycles_per_frame);
+    chip8.set_platform(match options.platform {
+        Some(platform) => platform,
+        None if octo => Platform::XoChip,
+        None => Platform::default(),
+    });
+    if let Some(quirks) = options.quirks {
+        chip8.set_quirks(quirks);
+    }
+    let seed = options.seed.unwrap_or(chip8::rng::DEFAULT_SEED);
+    chip8.seed(seed);
+
+    if options.trace.is_some() {
+        let mut tracer = Tracer::new(options.trace_size);
+        if let Some((start, end)) = options.trace_range {
+            tracer.set_range(start, end);
+        }
+        tracer.set_only(&options.trace_only);
+        chip8.set_tracer(Some(tracer));
+    }
+
+    let rom = if octo {
+        let source = fs::read_to_string(&options.rom)
+            .map_err(|e| format!("Error loading {}: {}", options.rom, e))?;
+        octo::compile(&source).map_err(|e| format!("{}:{}", options.rom, e))?
+    } else {
+        fs::read(&options.rom).map_err(|e| format!("Error loading {}: {}", options.rom, e))?
+    };
+
+    let movie = match &options.movie {
+        Some(path) => {
+            let bytes = fs::read(path).map_err(|e| format!("Error reading {}: {}", path, e))?;
+            let movie = Movie::from_bytes(&bytes)
+                .and_then(|movie| movie.check_rom(&rom).map(|_| movie))
+                .map_err(|e| format!("Error loading {}: {}", path, e))?;
+            movie.apply(&mut chip8);
+            Some(movie)
+        }
+        None => None,
+    };
+    let (seed, vip_rng) = movie
+        .as_ref()
+        .map_or((seed, false), |m| (m.seed, m.vip_rng));
+    let mut recording = options
+        .record
+        .as_ref()
+        .map(|_| Movie::new(&chip8, &rom, seed, vip_rng));
+    let frames = options.frames.unwrap_or_else(|| match &movie {
+        Some(movie) => movie.len() as u64,
+        None => DEFAULT_FRAMES,
+    });
+
+    chip8
+        .load_bytes(&rom)
+        .map_err(|e| format!("Error loading {}: {}", options.rom, e))?;
+
+    let until_pc = options.until_pc;
+    let until_mem = options.until_mem;
+    let condition = move |chip8: &Chip8| {
+        if until_pc == Some(chip8.pc()) {
+            Some(Stop::Pc)
+        } else {
+            match until_mem {
+                Some((addr, value)) if chip8.memory().get(addr) == Some(&value) => {
+                    Some(Stop::Memory)
+                }
+                _ => None,
+            }
+        }
+    };
+
+    let mut stop = Stop::Frames;
+    let mut error = None;
+    let mut frame = 0;
+    while frame < frames {
+        if let Some(movie) = &movie {
+            // Past the end of the movie every key is released
+            chip8.keypad = movie.keypad(frame as usize).unwrap_or([0; 16]);
+        } else {
+            for (key, state) in chip8.keypad.iter_mut().enumerate() {
+                let held = options
+                    .presses
+                    .iter()
+                    .any(|p| p.key == key && (p.frame..p.frame + p.frames).contains(&frame));
+                *state = held as u8;
+            }
+        }
+        if let Some(recording) = recording.as_mut() {
+            recording.record(&chip8.keypad);
+        }
+
+        match chip8.run_frame_until(|chip8| condition(chip8).is_some()) {
+            Ok(true) => {
+                stop = condition(&chip8).unwrap_or(Stop::Frames);
+                break;
+            }
+            Ok(false) => (),
+            Err(e) => {
+                error = Some(format!("{}", e));
+                break;
+            }
+        }
+        frame += 1;
+
+        if chip8.exited() {
+            stop = Stop::Exit;
+            break;
+        }
+    }
+
+    if options.ascii {
+        print!("{}", chip8.gfx.to_ascii());
+    }
+    if let Some(path) = &options.png {
+        let png = chip8.gfx.to_png(&options.palette.colours, options.scale);
+        fs::write(path, png).map_err(|e| format!("Error writing {}: {}", path, e))?;
+    }
+    if let Some(path) = &options.registers {
+        let json = registers_json(&chip8, frame, &stop);
+        if path == "-" {
+            print!("{}", json);
+        } else {
+            fs::write(path, json).map_err(|e| format!("Error writing {}: {}", path, e))?;
+        }
+    }
+
+    if let (Some(path), Some(tracer)) = (&options.trace, chip8.tracer()) {
+        let file = File::create(path).map_err(|e| format!("Error writing {}: {}", path, e))?;
+        tracer
+            .write(&mut BufWriter::new(file), options.trace_format)
+            .map_err(|e| format!("Error writing {}: {}", path, e))?;
+    }
+
+    if let (Some(path), Some(recording)) = (&options.record, &recording) {
+        fs::write(path, recording.to_bytes())
+            .map_err(|e| format!("Error writing {}: {}", path, e))?;
+    }
+
+    if let Some(error) = error {
+        return Err(error);
+    }
+    if matches!(stop, Stop::Frames) && (until_pc.is_some() || until_mem.is_some()) {
+        return Err(format!("condition not met after {} frames", frame));
+    }
+
+    Ok(())
+}
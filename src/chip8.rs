@@ -1,9 +1,19 @@
 use std::fs::File;
 use std::io::prelude::*;
-
-use rand::Rng;
-
-const chip8_fontset: [u8; 80] = [
+use std::path::Path;
+
+use crate::error::{ExecError, ExecErrorKind, LoadError, StateError};
+use crate::framebuffer::Framebuffer;
+use crate::instruction::{decode, Instruction};
+use crate::platform::Platform;
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::rng::{RandomSource, XorShift};
+use crate::savestate::{StateReader, StateWriter};
+use crate::trace::{Snapshot, Tracer};
+use crate::watch::{Operand, WatchHit, Watchpoint};
+
+/// The built-in 4x5 hex digit sprites, five bytes per digit.
+pub const CHIP8_FONTSET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, //0
     0x20, 0x60, 0x20, 0x20, 0x70, //1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, //2
@@ -22,10 +32,43 @@ const chip8_fontset: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, //F
 ];
 
+// The SUPER-CHIP 8x10 font, stored right after the small one.
+const SCHIP_FONTSET: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, //0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, //1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, //2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, //3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, //4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, //5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, //6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, //7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, //8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, //9
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, //A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, //B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, //C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, //D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, //E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, //F
+];
+
+/// The XO-CHIP pitch register's reset value, which plays patterns at 4000 Hz.
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// Where programs are loaded and start running.
+pub const DEFAULT_LOAD_ADDRESS: usize = 0x200;
+
+const FONT_ADDRESS: usize = 0x00;
+const BIG_FONT_ADDRESS: usize = 0x50;
+
+/// Instructions per 60 Hz frame, roughly 600 instructions per second.
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = 10;
+
 pub struct Chip8 {
     opcode: u16,
     pc: usize,
     index: usize,
+    load_address: usize,
 
     stack: [u16; 16],
     sp: usize,
@@ -35,448 +78,1715 @@ pub struct Chip8 {
 
     sound_timer: u8,
     delay_timer: u8,
-
-    rng: rand::rngs::ThreadRng,
-
-    pub gfx: Vec<Vec<u8>>,
+    cycles_per_frame: u32,
+    platform: Platform,
+    quirks: Quirks,
+    vblank_wait: bool,
+
+    // SUPER-CHIP RPL user flags, saved by FX75 and restored by FX85.
+    rpl: [u8; 16],
+    exited: bool,
+
+    // XO-CHIP bitplane selection (FN01), audio pattern (F002) and pitch (FX3A).
+    planes: u8,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+
+    rng: Box<dyn RandomSource>,
+
+    // Debugging aids. These are not part of the machine state.
+    watchpoints: Vec<Watchpoint>,
+    // The value each condition watchpoint's operand had after the last
+    // instruction, to detect the condition becoming true.
+    watch_values: Vec<u16>,
+    watch_hits: Vec<WatchHit>,
+    tracer: Option<Tracer>,
+
+    pub gfx: Framebuffer,
     pub keypad: [u8; 16],
     pub draw_flag: bool,
 }
 
+/// The registers from X to Y inclusive, counting down when X > Y.
+fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Chip8 {
     pub fn new() -> Self {
-        let mut memory: [u8; 4096] = [0; 4096];
-        for i in 0..80 {
-            memory[i] = chip8_fontset[i];
-        }
+        let mut memory = vec![0; Platform::default().memory_size()];
+        memory[FONT_ADDRESS..FONT_ADDRESS + 80].copy_from_slice(&CHIP8_FONTSET);
+        memory[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + 160].copy_from_slice(&SCHIP_FONTSET);
 
         Self {
             opcode: 0,
-            pc: 0x200,
+            pc: DEFAULT_LOAD_ADDRESS,
             index: 0,
+            load_address: DEFAULT_LOAD_ADDRESS,
 
             stack: [0; 16],
             sp: 0,
 
             registers: [0; 16],
-            memory: Box::new(memory),
+            memory: memory.into_boxed_slice(),
 
             delay_timer: 0,
             sound_timer: 0,
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            vblank_wait: false,
 
-            // gfx: Box::new([Box::new([0; 2048]); 2048]),
-            gfx: vec![vec!(0; 2048); 2048],
+            rpl: [0; 16],
+            exited: false,
+
+            planes: 1,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+
+            gfx: Framebuffer::new(64, 32),
             keypad: [0; 16],
             draw_flag: false,
 
-            rng: rand::thread_rng(),
+            rng: Box::new(XorShift::default()),
+
+            watchpoints: vec![],
+            watch_values: vec![],
+            watch_hits: vec![],
+            tracer: None,
         }
     }
 
-    pub fn load(&mut self, rom_path: &str) -> bool {
-        eprintln!("Loading ROM: {}", rom_path);
+    pub fn opcode(&self) -> u16 {
+        self.opcode
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn stack(&self) -> &[u16; 16] {
+        &self.stack
+    }
+
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Whether the buzzer should currently be sounding.
+    pub fn beeping(&self) -> bool {
+        self.sound_timer > 0
+    }
 
-        let rom = File::open(rom_path).expect("Failed to open rom!");
-        let rom_size = rom.metadata().expect("Failed to fetch metadata!").len();
-        println!("rom size: {}", rom_size);
+    pub fn load_address(&self) -> usize {
+        self.load_address
+    }
+
+    /// Sets where ROMs are copied to and where execution starts.
+    pub fn set_load_address(&mut self, addr: usize) {
+        self.load_address = addr;
+        self.pc = addr;
+    }
+
+    /// The largest ROM that fits between the load address and the end of memory.
+    pub fn max_rom_size(&self) -> usize {
+        self.memory.len().saturating_sub(self.load_address)
+    }
+
+    pub fn load<P: AsRef<Path>>(&mut self, rom_path: P) -> Result<(), LoadError> {
+        self.load_reader(File::open(rom_path)?)
+    }
 
+    pub fn load_reader<R: Read>(&mut self, rom: R) -> Result<(), LoadError> {
+        let max = self.max_rom_size();
+
+        // Read one byte past the limit so oversized ROMs are detected without
+        // buffering an unbounded stream.
         let mut rom_buffer: Vec<u8> = vec![];
-        for x in rom.bytes() {
-            if let Ok(y) = x {
-                rom_buffer.push(y);
+        rom.take(max as u64 + 1).read_to_end(&mut rom_buffer)?;
+
+        self.load_bytes(&rom_buffer)
+    }
+
+    pub fn load_bytes(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        let max = self.max_rom_size();
+        if rom.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if rom.len() > max {
+            return Err(LoadError::TooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+
+        self.memory[self.load_address..self.load_address + rom.len()].copy_from_slice(rom);
+        Ok(())
+    }
+
+    /// Serializes the complete machine: CPU, memory, timers, keypad,
+    /// display, configuration and RNG.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+
+        w.platform(self.platform);
+        w.quirks(&self.quirks);
+        w.u32(self.cycles_per_frame);
+        w.u32(self.load_address as u32);
+
+        w.u16(self.opcode);
+        w.u32(self.pc as u32);
+        w.u32(self.index as u32);
+        for &frame in self.stack.iter() {
+            w.u16(frame);
+        }
+        w.u8(self.sp as u8);
+        w.bytes(&self.registers);
+        w.u8(self.delay_timer);
+        w.u8(self.sound_timer);
+        w.blob(&self.memory);
+
+        w.bytes(&self.keypad);
+        w.u16(self.gfx.width() as u16);
+        w.u16(self.gfx.height() as u16);
+        w.blob(self.gfx.as_slice());
+        w.bool(self.draw_flag);
+        w.bool(self.vblank_wait);
+
+        w.bytes(&self.rpl);
+        w.bool(self.exited);
+        w.u8(self.planes);
+        w.bool(self.audio_pattern.is_some());
+        w.bytes(&self.audio_pattern.unwrap_or([0; 16]));
+        w.u8(self.pitch);
+
+        w.blob(&self.rng.state());
+
+        w.finish()
+    }
+
+    /// Restores a state written by `save_state`. On error the machine is
+    /// left untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(state)?;
+
+        let platform = r.platform()?;
+        let quirks = r.quirks()?;
+        let cycles_per_frame = r.u32()?;
+        let load_address = r.u32()? as usize;
+
+        let opcode = r.u16()?;
+        let pc = r.u32()? as usize;
+        let index = r.u32()? as usize;
+        let mut stack = [0; 16];
+        for frame in stack.iter_mut() {
+            *frame = r.u16()?;
+        }
+        let sp = r.u8()? as usize;
+        if sp > stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        let registers = r.array()?;
+        let delay_timer = r.u8()?;
+        let sound_timer = r.u8()?;
+        let memory = r.blob()?;
+        if memory.len() != platform.memory_size() {
+            return Err(StateError::Invalid("memory size"));
+        }
+
+        let keypad = r.array()?;
+        let width = r.u16()? as usize;
+        let height = r.u16()? as usize;
+        let gfx = match Framebuffer::from_pixels(width, height, r.blob()?.to_vec()) {
+            Some(gfx) => gfx,
+            None => return Err(StateError::Invalid("framebuffer")),
+        };
+        let draw_flag = r.bool()?;
+        let vblank_wait = r.bool()?;
+
+        let rpl = r.array()?;
+        let exited = r.bool()?;
+        let planes = r.u8()?;
+        let has_pattern = r.bool()?;
+        let pattern = r.array()?;
+        let pitch = r.u8()?;
+
+        let rng_state = r.blob()?;
+        r.finish()?;
+
+        // Restoring the generator is the last thing that can fail, so the
+        // machine is only modified once everything has been validated.
+        if !self.rng.set_state(rng_state) {
+            return Err(StateError::Invalid("RNG state"));
+        }
+
+        self.platform = platform;
+        self.quirks = quirks;
+        self.cycles_per_frame = cycles_per_frame;
+        self.load_address = load_address;
+        self.opcode = opcode;
+        self.pc = pc;
+        self.index = index;
+        self.stack = stack;
+        self.sp = sp;
+        self.registers = registers;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.memory = memory.into();
+        self.keypad = keypad;
+        self.gfx = gfx;
+        self.draw_flag = draw_flag;
+        self.vblank_wait = vblank_wait;
+        self.rpl = rpl;
+        self.exited = exited;
+        self.planes = planes;
+        self.audio_pattern = if has_pattern { Some(pattern) } else { None };
+        self.pitch = pitch;
+
+        Ok(())
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        let value = match watchpoint {
+            Watchpoint::Condition(lhs, _, _) => self.operand_value(lhs),
+            _ => 0,
+        };
+        self.watchpoints.push(watchpoint);
+        self.watch_values.push(value);
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        match self.watchpoints.iter().position(|w| w == watchpoint) {
+            Some(i) => {
+                self.watchpoints.remove(i);
+                self.watch_values.remove(i);
+                true
             }
+            None => false,
         }
+    }
 
-        if (4096 - 512) > rom_size {
-            for i in 0..rom_size {
-                self.memory[(i + 512) as usize] = rom_buffer[i as usize] as u8;
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+        self.watch_values.clear();
+    }
+
+    /// Returns and forgets the watchpoints that fired since the last call.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    /// Starts recording every instruction executed into `tracer`, or
+    /// stops tracing with `None`.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    fn operand_value(&self, operand: Operand) -> u16 {
+        match operand {
+            Operand::V(x) => self.registers[x & 0xF] as u16,
+            Operand::I => self.index as u16,
+            Operand::Memory(addr) => *self.memory.get(addr).unwrap_or(&0) as u16,
+        }
+    }
+
+    // Checks the register and condition watchpoints after an instruction,
+    // given the registers and I from before it.
+    fn check_watchpoints(&mut self, pc: usize, registers: &[u8; 16], index: usize) {
+        for i in 0..self.watchpoints.len() {
+            let watchpoint = self.watchpoints[i];
+            let (old, new) = match watchpoint {
+                Watchpoint::Register(x) => {
+                    (registers[x & 0xF] as u16, self.registers[x & 0xF] as u16)
+                }
+                Watchpoint::Index => (index as u16, self.index as u16),
+                Watchpoint::Condition(lhs, cmp, rhs) => {
+                    let old = self.watch_values[i];
+                    let new = self.operand_value(lhs);
+                    self.watch_values[i] = new;
+                    if cmp.test(old, rhs) || !cmp.test(new, rhs) {
+                        continue;
+                    }
+                    (old, new)
+                }
+                Watchpoint::Read(_) | Watchpoint::Write(_) => continue,
+            };
+
+            if old != new || matches!(watchpoint, Watchpoint::Condition(..)) {
+                self.watch_hits.push(WatchHit {
+                    pc,
+                    watchpoint,
+                    old,
+                    new,
+                });
             }
-            println!("rom successfully loaded from buffer to 4k wram");
+        }
+    }
+
+    /// Reads an instruction word or operand. Never triggers watchpoints.
+    fn fetch(&self, addr: usize) -> Result<u8, ExecErrorKind> {
+        match self.memory.get(addr) {
+            Some(&value) => Ok(value),
+            None => Err(ExecErrorKind::MemoryOutOfBounds { addr }),
+        }
+    }
+
+    /// Reads data on behalf of an instruction.
+    fn read(&mut self, addr: usize) -> Result<u8, ExecErrorKind> {
+        let value = self.fetch(addr)?;
+        if self.watchpoints.contains(&Watchpoint::Read(addr)) {
+            self.watch_hits.push(WatchHit {
+                pc: self.pc,
+                watchpoint: Watchpoint::Read(addr),
+                old: value as u16,
+                new: value as u16,
+            });
+        }
+        Ok(value)
+    }
+
+    /// Writes data on behalf of an instruction.
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), ExecErrorKind> {
+        let old = self.fetch(addr)?;
+        self.memory[addr] = value;
+        if self.watchpoints.contains(&Watchpoint::Write(addr)) {
+            self.watch_hits.push(WatchHit {
+                pc: self.pc,
+                watchpoint: Watchpoint::Write(addr),
+                old: old as u16,
+                new: value as u16,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    /// Sets how many instructions `run_frame` executes per 60 Hz frame.
+    pub fn set_cycles_per_frame(&mut self, cycles: u32) {
+        self.cycles_per_frame = cycles;
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// Selects the instruction set, resizes memory to match and resets the
+    /// quirks to that platform's defaults. Call this before loading a ROM,
+    /// and call `set_quirks` afterwards to override the quirks.
+    pub fn set_platform(&mut self, platform: Platform) {
+        let mut memory = vec![0; platform.memory_size()];
+        let kept = memory.len().min(self.memory.len());
+        memory[..kept].copy_from_slice(&self.memory[..kept]);
+
+        self.memory = memory.into_boxed_slice();
+        self.platform = platform;
+        self.quirks = platform.default_quirks();
+    }
+
+    /// The XO-CHIP bitplanes selected for drawing, as a bitmask.
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// The 128-bit XO-CHIP audio pattern, once a program has loaded one
+    /// with F002. Until then the buzzer plays a plain tone.
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    /// The XO-CHIP pitch register set by FX3A.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Whether the display is in SUPER-CHIP 128x64 mode.
+    pub fn hires(&self) -> bool {
+        self.gfx.width() == 128
+    }
+
+    /// Whether the program has stopped itself with 00FD.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl
+    }
+
+    /// Replaces the generator CXNN draws from.
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    /// Reseeds CXNN with the default generator.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Box::new(XorShift::new(seed));
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Runs one 1/60 s frame: `cycles_per_frame` instructions followed by
+    /// a single timer tick. With the `display_wait` quirk the frame ends
+    /// early after the first sprite is drawn.
+    pub fn run_frame(&mut self) -> Result<(), ExecError> {
+        self.run_frame_until(|_| false).map(|_| ())
+    }
+
+    /// Like `run_frame`, but calls `stop` before every instruction. If it
+    /// returns true the frame is abandoned before that instruction runs,
+    /// the timers are not ticked, and this returns `Ok(true)`. The frame is
+    /// abandoned the same way right after an instruction that fires a
+    /// watchpoint; see `take_watch_hits`.
+    pub fn run_frame_until<F>(&mut self, mut stop: F) -> Result<bool, ExecError>
+    where
+        F: FnMut(&Chip8) -> bool,
+    {
+        self.vblank_wait = false;
+        for _ in 0..self.cycles_per_frame {
+            if stop(self) {
+                return Ok(true);
+            }
+            self.emulate_cycle()?;
+            if !self.watch_hits.is_empty() {
+                return Ok(true);
+            }
+            if self.vblank_wait {
+                break;
+            }
+        }
+        self.tick_timers();
+
+        Ok(false)
+    }
+
+    /// The instruction word at the program counter, which is the next one
+    /// `emulate_cycle` will execute.
+    pub fn next_opcode(&self) -> Option<u16> {
+        let hi = *self.memory.get(self.pc)?;
+        let lo = *self.memory.get(self.pc + 1)?;
+        Some((hi as u16) << 8 | lo as u16)
+    }
+
+    /// Decrements the delay and sound timers. Call this at 60 Hz when
+    /// driving the CPU with `emulate_cycle` directly.
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    /// Skips the next instruction if `condition` holds. On XO-CHIP that
+    /// may be the four-byte F000 NNNN.
+    fn skip_if(&mut self, condition: bool) {
+        if !condition {
+            self.pc += 2;
+        } else if !self.platform.has_xochip() {
+            self.pc += 4;
         } else {
-            eprintln!("ROM too large to fit in memory!");
-            return false;
+            let next = self.memory.get(self.pc + 2..self.pc + 4);
+            let size = match next.map(|word| decode((word[0] as u16) << 8 | word[1] as u16)) {
+                Some(instruction) => instruction.size(),
+                None => 2,
+            };
+            self.pc += 2 + size;
         }
+    }
 
-        true
+    /// How far FX55 and FX65 move I, according to the quirks.
+    fn load_store_increment(&self, x: usize) -> usize {
+        match self.quirks.load_store {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => x,
+            IndexIncrement::ByXPlusOne => x + 1,
+        }
     }
 
-    pub fn emulate_cycle(&mut self) {
-        self.opcode = (self.memory[self.pc] as u16) << 8 | self.memory[self.pc + 1] as u16;
-        eprintln!("emulating cycle... {:X?}", self.opcode);
-        match self.opcode & 0xF000 {
-            0x0 => {
-                eprintln!("0x0...");
-                match self.opcode & 0x000F {
-                    // clear screen
-                    0x0 => {
-                        for y in 0..2048 {
-                            for x in 0..2048 {
-                                self.gfx[y][x] = 0;
-                            }
-                        }
+    /// Executes a single instruction. Timers are not touched.
+    pub fn emulate_cycle(&mut self) -> Result<(), ExecError> {
+        let pc = self.pc;
+        let registers = self.registers;
+        let index = self.index;
+        let before = match self.tracer {
+            Some(_) if !self.exited => Some(self.snapshot()),
+            _ => None,
+        };
+
+        let result = self.execute().map_err(|kind| ExecError {
+            pc,
+            opcode: self.opcode,
+            kind,
+        });
+
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(pc, &registers, index);
+        }
+        if let Some(before) = before {
+            let after = self.snapshot();
+            let operand = (self.fetch(pc + 2).unwrap_or(0) as u16) << 8
+                | self.fetch(pc + 3).unwrap_or(0) as u16;
+            let opcode = self.opcode;
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.record(pc, opcode, operand, &before, &after);
+            }
+        }
+        result
+    }
 
-                        self.draw_flag = true;
-                    }
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            index: self.index,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
 
-                    // return from subroutine
-                    0xE => {
-                        self.sp -= 1;
-                        self.pc = self.stack[self.sp] as usize;
-                    }
+    fn execute(&mut self) -> Result<(), ExecErrorKind> {
+        if self.exited {
+            return Ok(());
+        }
 
-                    x => panic!("unexpected instr at 0: {}", x),
-                };
+        self.opcode = (self.fetch(self.pc)? as u16) << 8 | self.fetch(self.pc + 1)? as u16;
+
+        let instruction = decode(self.opcode);
+        if !self.platform.includes(instruction.platform()) {
+            return Err(ExecErrorKind::UnknownOpcode);
+        }
 
+        match instruction {
+            // 00E0 - Clears the screen
+            Instruction::Clear => {
+                self.gfx.clear_planes(self.planes);
+                self.draw_flag = true;
                 self.pc += 2;
             }
 
-            // 0x1NNN - jump to address NNN
-            0x1000 => self.pc = (self.opcode & 0x0FFF) as usize,
+            // 00EE - Returns from a subroutine
+            Instruction::Return => {
+                if self.sp == 0 {
+                    return Err(ExecErrorKind::StackUnderflow);
+                }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp] as usize + 2;
+            }
 
-            // 0x2NNN - calls subroutine at NNN
-            0x2000 => {
-                self.stack[self.sp] = self.pc as u16;
-                self.sp += 1;
-                self.pc = (self.opcode & 0x0FFF) as usize;
+            // 00CN - Scrolls the display down by N lines (SCHIP)
+            Instruction::ScrollDown { n } => {
+                self.gfx.scroll_down(n as usize, self.planes);
+                self.draw_flag = true;
+                self.pc += 2;
             }
 
-            // 3XNN - Skips the next instruction if reg[X] equals NN.
-            0x3000 => {
-                if self.registers[((self.opcode & 0x0F00) >> 8) as usize]
-                    == (self.opcode & 0x00FF) as u8
-                {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
+            // 00DN - Scrolls the display up by N lines (XO-CHIP)
+            Instruction::ScrollUp { n } => {
+                self.gfx.scroll_up(n as usize, self.planes);
+                self.draw_flag = true;
+                self.pc += 2;
+            }
+
+            // 00FB - Scrolls the display right by 4 pixels (SCHIP)
+            Instruction::ScrollRight => {
+                self.gfx.scroll_right(4, self.planes);
+                self.draw_flag = true;
+                self.pc += 2;
+            }
+
+            // 00FC - Scrolls the display left by 4 pixels (SCHIP)
+            Instruction::ScrollLeft => {
+                self.gfx.scroll_left(4, self.planes);
+                self.draw_flag = true;
+                self.pc += 2;
+            }
+
+            // 00FD - Exits the interpreter (SCHIP)
+            Instruction::Exit => self.exited = true,
+
+            // 00FE - Switches to 64x32 low resolution (SCHIP)
+            Instruction::Lores => {
+                self.gfx.resize(64, 32);
+                self.draw_flag = true;
+                self.pc += 2;
+            }
+
+            // 00FF - Switches to 128x64 high resolution (SCHIP)
+            Instruction::Hires => {
+                self.gfx.resize(128, 64);
+                self.draw_flag = true;
+                self.pc += 2;
+            }
+
+            // 1NNN - Jumps to address NNN
+            Instruction::Jump { nnn } => self.pc = nnn as usize,
+
+            // 2NNN - Calls subroutine at NNN
+            Instruction::Call { nnn } => {
+                if self.sp == self.stack.len() {
+                    return Err(ExecErrorKind::StackOverflow);
                 }
+                self.stack[self.sp] = self.pc as u16;
+                self.sp += 1;
+                self.pc = nnn as usize;
             }
 
+            // 3XNN - Skips the next instruction if VX equals NN.
+            Instruction::SkipEqImm { x, nn } => self.skip_if(self.registers[x] == nn),
+
             // 4XNN - Skips the next instruction if VX does not equal NN.
-            0x4000 => {
-                if self.registers[((self.opcode & 0x0F00) >> 8) as usize]
-                    != (self.opcode & 0x00FF) as u8
-                {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
+            Instruction::SkipNeImm { x, nn } => self.skip_if(self.registers[x] != nn),
+
+            // 5XY0 - Skips the next instruction if VX equals VY.
+            Instruction::SkipEq { x, y } => self.skip_if(self.registers[x] == self.registers[y]),
+
+            // 5XY2 - Stores VX to VY, in either order, in memory starting
+            // at I. I is not changed (XO-CHIP)
+            Instruction::SaveRange { x, y } => {
+                for (offset, reg) in register_range(x, y).enumerate() {
+                    self.write(self.index + offset, self.registers[reg])?;
                 }
+                self.pc += 2;
             }
 
-            // 5XY0 - Skips the next instruction if VX equals VY.
-            0x5000 => {
-                if self.registers[((self.opcode & 0x0F00) >> 8) as usize]
-                    == self.registers[((self.opcode & 0x00F0) >> 4) as usize]
-                {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
+            // 5XY3 - Loads VX to VY, in either order, from memory starting
+            // at I. I is not changed (XO-CHIP)
+            Instruction::LoadRange { x, y } => {
+                for (offset, reg) in register_range(x, y).enumerate() {
+                    self.registers[reg] = self.read(self.index + offset)?;
                 }
+                self.pc += 2;
             }
 
             // 6XNN - Sets VX to NN.
-            0x6000 => {
-                self.registers[((self.opcode & 0xF00) >> 8) as usize] =
-                    (self.opcode & 0x00FF) as u8;
+            Instruction::LoadImm { x, nn } => {
+                self.registers[x] = nn;
                 self.pc += 2;
             }
 
             // 7XNN - Adds NN to VX.
-            0x7000 => {
-                self.registers[((self.opcode & 0xF00) >> 8) as usize] +=
-                    (self.opcode & 0x00FF) as u8;
+            Instruction::AddImm { x, nn } => {
+                self.registers[x] = self.registers[x].wrapping_add(nn);
                 self.pc += 2;
             }
 
-            // 8XY_ - Data processing
-            0x8000 => {
-                match self.opcode & 0x000F {
-                    // 8XY0 - Sets VX to the value of VY.
-                    0 => {
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] =
-                            self.registers[((self.opcode & 0x00F0) >> 4) as usize]
-                    }
-                    // 8XY1 - Sets VX to (VX OR VY).
-                    1 => {
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] |=
-                            self.registers[((self.opcode & 0x00F0) >> 4) as usize]
-                    }
-                    // 8XY2 - Sets VX to (VX AND VY).
-                    2 => {
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] &=
-                            self.registers[((self.opcode & 0x00F0) >> 4) as usize]
-                    }
-                    // 8XY3 - Sets VX to (VX XOR VY).
-                    3 => {
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] ^=
-                            self.registers[((self.opcode & 0x00F0) >> 4) as usize]
-                    }
+            // 8XY0 - Sets VX to the value of VY.
+            Instruction::Move { x, y } => {
+                self.registers[x] = self.registers[y];
+                self.pc += 2;
+            }
 
-                    // 8XY4 - Adds VY to VX. VF is set to 1 when there's a carry,
-                    // and to 0 when there isn't.
-                    4 => {
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] +=
-                            self.registers[((self.opcode & 0x00F0) >> 4) as usize];
-
-                        if self.registers[((self.opcode & 0x00F0) >> 4) as usize]
-                            > (0xFF - self.registers[((self.opcode & 0x0F00) >> 8) as usize])
-                        {
-                            self.registers[0xF] = 1; // carry
-                        } else {
-                            self.registers[0xF] = 0;
-                        }
-                    }
+            // 8XY1 - Sets VX to (VX OR VY).
+            Instruction::Or { x, y } => {
+                self.registers[x] |= self.registers[y];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
+                self.pc += 2;
+            }
 
-                    // 8XY5 - VY is subtracted from VX. VF is set to 0 when
-                    // there's a borrow, and 1 when there isn't.
-                    5 => {
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] -=
-                            self.registers[((self.opcode & 0x00F0) >> 4) as usize];
-
-                        if self.registers[((self.opcode & 0x00F0) >> 4) as usize]
-                            > self.registers[((self.opcode & 0x0F00) >> 8) as usize]
-                        {
-                            self.registers[0xF] = 0; // there is a borrow
-                        } else {
-                            self.registers[0xF] = 1;
-                        }
-                    }
+            // 8XY2 - Sets VX to (VX AND VY).
+            Instruction::And { x, y } => {
+                self.registers[x] &= self.registers[y];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
+                self.pc += 2;
+            }
 
-                    // 0x8XY6 - Shifts VX right by one. VF is set to the value of
-                    // the least significant bit of VX before the shift.
-                    6 => {
-                        self.registers[0xF] =
-                            self.registers[((self.opcode & 0x0F00) >> 8) as usize] & 0x1;
+            // 8XY3 - Sets VX to (VX XOR VY).
+            Instruction::Xor { x, y } => {
+                self.registers[x] ^= self.registers[y];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
+                self.pc += 2;
+            }
 
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] >>= 1;
-                    }
+            // 8XY4 - Adds VY to VX. VF is set to 1 when there's a carry,
+            // and to 0 when there isn't.
+            // VF is written last, so with X = F it holds the flag.
+            Instruction::Add { x, y } => {
+                let (sum, carry) = self.registers[x].overflowing_add(self.registers[y]);
+                self.registers[x] = sum;
+                self.registers[0xF] = carry as u8;
+                self.pc += 2;
+            }
 
-                    // 0x8XY7: Sets VX to VY minus VX. VF is set to 0 when there's
-                    // a borrow, and 1 when there isn't.
-                    7 => {
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] = self.registers
-                            [((self.opcode & 0x00F0) >> 4) as usize]
-                            - self.registers[((self.opcode & 0x0F00) >> 8) as usize];
-
-                        if self.registers[((self.opcode & 0x0F00) >> 8) as usize]
-                            > self.registers[((self.opcode & 0x00F0) >> 4) as usize]
-                        {
-                            self.registers[0xF] = 0; // there is a borrow
-                        } else {
-                            self.registers[0xF] = 1;
-                        }
-                    }
+            // 8XY5 - VY is subtracted from VX. VF is set to 0 when there's
+            // a borrow, and 1 when there isn't.
+            Instruction::Sub { x, y } => {
+                let (difference, borrow) = self.registers[x].overflowing_sub(self.registers[y]);
+                self.registers[x] = difference;
+                self.registers[0xF] = !borrow as u8;
+                self.pc += 2;
+            }
 
-                    // 0x8XYE: Shifts VX left by one. VF is set to the value of
-                    // the most significant bit of VX before the shift.
-                    0xE => {
-                        self.registers[0xF] =
-                            self.registers[((self.opcode & 0x0F00) >> 8) as usize] >> 7;
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] <<= 1;
-                    }
+            // 8XY6 - Shifts VX right by one. VF is set to the value of the
+            // least significant bit of VX before the shift.
+            Instruction::ShiftRight { x, y } => {
+                let source = if self.quirks.shift_uses_vy {
+                    self.registers[y]
+                } else {
+                    self.registers[x]
+                };
 
-                    x => panic!("unknown data processing instr: {}", x),
-                }
+                self.registers[x] = source >> 1;
+                self.registers[0xF] = source & 0x1;
                 self.pc += 2;
             }
 
-            // 9XY0 - Skips the next instruction if VX doesn't equal VY.
-            0x9000 => {
-                if self.registers[((self.opcode & 0x0F00) >> 8) as usize]
-                    != self.registers[((self.opcode & 0x00F0) >> 4) as usize]
-                {
-                    self.pc += 4;
+            // 8XY7 - Sets VX to VY minus VX. VF is set to 0 when there's a
+            // borrow, and 1 when there isn't.
+            Instruction::SubReverse { x, y } => {
+                let (difference, borrow) = self.registers[y].overflowing_sub(self.registers[x]);
+                self.registers[x] = difference;
+                self.registers[0xF] = !borrow as u8;
+                self.pc += 2;
+            }
+
+            // 8XYE - Shifts VX left by one. VF is set to the value of the
+            // most significant bit of VX before the shift.
+            Instruction::ShiftLeft { x, y } => {
+                let source = if self.quirks.shift_uses_vy {
+                    self.registers[y]
                 } else {
-                    self.pc += 2;
-                }
+                    self.registers[x]
+                };
+
+                self.registers[x] = source << 1;
+                self.registers[0xF] = source >> 7;
+                self.pc += 2;
             }
 
+            // 9XY0 - Skips the next instruction if VX doesn't equal VY.
+            Instruction::SkipNe { x, y } => self.skip_if(self.registers[x] != self.registers[y]),
+
             // ANNN - Sets I to the address NNN.
-            0xA000 => {
-                self.index = (self.opcode & 0x0FFF) as usize;
+            Instruction::LoadIndex { nnn } => {
+                self.index = nnn as usize;
                 self.pc += 2;
             }
 
             // BNNN - Jumps to the address NNN plus V0.
-            0xB000 => {
-                self.pc = ((self.opcode & 0x0FFF) as u8 + self.registers[0]) as usize;
+            // With the jump quirk this is BXNN, jumping to XNN plus VX.
+            Instruction::JumpOffset { nnn } => {
+                let offset = if self.quirks.jump_uses_vx {
+                    self.registers[(nnn >> 8) as usize]
+                } else {
+                    self.registers[0]
+                };
+                self.pc = nnn as usize + offset as usize;
             }
 
             // CXNN - Sets VX to a random number, masked by NN.
-            0xC000 => {
-                self.registers[((self.opcode & 0x0F00) >> 8) as usize] =
-                    ((self.rng.gen::<u16>() % (0xFF + 1)) & (self.opcode & 0x00FF)) as u8;
+            Instruction::Random { x, nn } => {
+                self.registers[x] = self.rng.next_u8() & nn;
                 self.pc += 2;
             }
 
-            // DXYN: Draws a sprite at coordinate (VX, VY) that has a width of 8
-            // pixels and a height of N pixels.
+            // DXYN - Draws a sprite at coordinate (VX, VY) that has a width
+            // of 8 pixels and a height of N pixels.
             // Each row of 8 pixels is read as bit-coded starting from memory
             // location I;
             // I value doesn't change after the execution of this instruction.
             // VF is set to 1 if any screen pixels are flipped from set to unset
             // when the sprite is drawn, and to 0 if that doesn't happen.
-            0xD000 => {
-                let x = self.registers[((self.opcode & 0x0F00) >> 8) as usize] as u16;
-                let y = self.registers[((self.opcode & 0x00F0) >> 4) as usize] as u16;
-                let height = self.opcode & 0x000F;
-                let mut pixel;
-
-                self.registers[0xF] = 0;
-
-                for yline in 0..height {
-                    pixel = self.memory[self.index + yline as usize];
-                    for xline in 0..8 {
-                        if (pixel & (0x80 >> xline)) != 0 {
-                            if self.gfx[((y as u16 + yline) * 64) as usize][(x + xline) as usize]
-                                == 1
-                            {
-                                self.registers[0xF] = 1;
-                            }
-                            self.gfx[((y + yline) * 64) as usize][(x + xline) as usize] ^= 1;
-                            eprintln!("xline: {}", xline);
-                        }
+            // On SCHIP, DXY0 draws a 16x16 sprite stored as 32 bytes.
+            // On XO-CHIP, one sprite is drawn to each selected bitplane,
+            // with the data for each plane following the previous one.
+            Instruction::Draw { x, y, n } => {
+                let x = self.registers[x] as usize;
+                let y = self.registers[y] as usize;
+                let height = n as usize;
+                let wide = height == 0 && self.platform.has_schip();
+                let len = if wide { 32 } else { height };
+                let wrap = !self.quirks.clip_sprites;
+
+                let mut collision = false;
+                let mut addr = self.index;
+                let planes = self.planes;
+                for plane in [1, 2].iter().filter(|&&plane| planes & plane != 0) {
+                    let mut sprite = [0; 32];
+                    for (offset, byte) in sprite.iter_mut().take(len).enumerate() {
+                        *byte = self.read(addr + offset)?;
                     }
+                    addr += len;
+
+                    collision |= if wide {
+                        self.gfx.draw_wide_sprite(x, y, *plane, &sprite, wrap)
+                    } else {
+                        self.gfx.draw_sprite(x, y, *plane, &sprite[..len], wrap)
+                    };
                 }
-                eprintln!("exited D000");
+                self.registers[0xF] = collision as u8;
 
                 self.draw_flag = true;
+                self.vblank_wait = self.quirks.display_wait;
                 self.pc += 2;
             }
 
-            0xE000 => {
-                match self.opcode & 0x00FF {
-                    // EX9E - Skips the next instruction if the key stored
-                    // in VX is pressed.
-                    0x9E => {
-                        if self.keypad
-                            [self.registers[((self.opcode & 0x0F00) >> 8) as usize] as usize]
-                            != 0
-                        {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    }
+            // EX9E - Skips the next instruction if the key stored in VX is
+            // pressed.
+            Instruction::SkipKey { x } => {
+                self.skip_if(self.keypad[(self.registers[x] & 0xF) as usize] != 0)
+            }
 
-                    // EXA1 - Skips the next instruction if the key stored
-                    // in VX isn't pressed.
-                    0xA1 => {
-                        if self.keypad
-                            [self.registers[((self.opcode & 0x0F00) >> 8) as usize] as usize]
-                            == 0
-                        {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    }
+            // EXA1 - Skips the next instruction if the key stored in VX
+            // isn't pressed.
+            Instruction::SkipNotKey { x } => {
+                self.skip_if(self.keypad[(self.registers[x] & 0xF) as usize] == 0)
+            }
 
-                    x => panic!("unexpected instr in keypad: {}", x),
+            // F000 NNNN - Sets I to the 16-bit address NNNN that follows
+            // the instruction (XO-CHIP)
+            Instruction::LongLoadIndex => {
+                self.index =
+                    (self.fetch(self.pc + 2)? as usize) << 8 | self.fetch(self.pc + 3)? as usize;
+                self.pc += 4;
+            }
+
+            // FN01 - Selects the bitplanes N to draw to (XO-CHIP)
+            Instruction::Plane { n } => {
+                self.planes = n & 0x3;
+                self.pc += 2;
+            }
+
+            // F002 - Loads the 16-byte audio pattern from I (XO-CHIP)
+            Instruction::LoadAudio => {
+                let mut pattern = [0; 16];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read(self.index + offset)?;
                 }
+                self.audio_pattern = Some(pattern);
+                self.pc += 2;
             }
 
-            0xF000 => {
-                match self.opcode & 0x00FF {
-                    // FX07 - Sets VX to the value of the delay timer
-                    0x07 => {
-                        self.registers[((self.opcode & 0x0F00) >> 8) as usize] = self.delay_timer;
-                        self.pc += 2;
-                    }
+            // FX07 - Sets VX to the value of the delay timer
+            Instruction::GetDelay { x } => {
+                self.registers[x] = self.delay_timer;
+                self.pc += 2;
+            }
 
-                    // FX0A - A key press is awaited, and then stored in VX
-                    0x0A => {
-                        let mut key_pressed = false;
-                        for i in 0..16 {
-                            if self.keypad[i] != 0 {
-                                self.registers[((self.opcode & 0x0F00) >> 8) as usize] = i as u8;
-                                key_pressed = true;
-                            }
-                        }
-
-                        if !key_pressed {
-                            return;
-                        }
-
-                        self.pc += 2;
+            // FX0A - A key press is awaited, and then stored in VX
+            Instruction::WaitKey { x } => {
+                let mut key_pressed = false;
+                for i in 0..16 {
+                    if self.keypad[i] != 0 {
+                        self.registers[x] = i as u8;
+                        key_pressed = true;
                     }
+                }
 
-                    // FX15 - Sets the delay timer to VX
-                    0x15 => {
-                        self.delay_timer = self.registers[((self.opcode & 0x0F00) >> 8) as usize];
-                        self.pc += 2;
-                    }
+                if key_pressed {
+                    self.pc += 2;
+                }
+            }
 
-                    // FX1E - Adds VX to I
-                    0x1E => {
-                        if self.index as u16
-                            + self.registers[((self.opcode & 0x0F00) >> 8) as usize] as u16
-                            > 0xFFF
-                        {
-                            self.registers[0xF] = 1;
-                        } else {
-                            self.registers[0xF] = 0;
-                        }
-
-                        self.index +=
-                            self.registers[((self.opcode & 0x0F00) >> 8) as usize] as usize;
-                        self.pc += 2;
-                    }
+            // FX15 - Sets the delay timer to VX
+            Instruction::SetDelay { x } => {
+                self.delay_timer = self.registers[x];
+                self.pc += 2;
+            }
 
-                    // FX29 - Sets I to the location of the sprite for the
-                    // character in VX. Characters 0-F (in hexadecimal) are
-                    // represented by a 4x5 font
-                    0x29 => {
-                        self.index =
-                            self.registers[((self.opcode & 0x0F00) >> 8) as usize] as usize * 5;
-                        self.pc += 2;
-                    }
+            // FX18 - Sets the sound timer to VX
+            Instruction::SetSound { x } => {
+                self.sound_timer = self.registers[x];
+                self.pc += 2;
+            }
 
-                    // FX33 - Stores the Binary-coded decimal representation of VX
-                    // at the addresses I, I plus 1, and I plus 2
-                    0x33 => {
-                        self.memory[self.index] =
-                            self.registers[((self.opcode & 0x0F00) >> 8) as usize] / 100;
-                        self.memory[self.index + 1] =
-                            (self.registers[((self.opcode & 0x0F00) >> 8) as usize] / 10) % 10;
-                        self.memory[self.index + 2] =
-                            self.registers[((self.opcode & 0x0F00) >> 8) as usize] % 10;
-                        self.pc += 2;
+            // FX1E - Adds VX to I, wrapping at the end of the platform's
+            // address space
+            Instruction::AddIndex { x } => {
+                let sum = self.index + self.registers[x] as usize;
+                if self.quirks.index_overflow_sets_vf {
+                    if sum > 0xFFF {
+                        self.registers[0xF] = 1;
+                    } else {
+                        self.registers[0xF] = 0;
                     }
+                }
 
-                    // FX55 - Stores V0 to VX in memory starting at address I
-                    0x55 => {
-                        for i in 0..((self.opcode & 0x0F00) >> 8) {
-                            self.memory[self.index as usize + i as usize] =
-                                self.registers[i as usize] as u8;
-                        }
+                self.index = sum & (self.platform.memory_size() - 1);
+                self.pc += 2;
+            }
 
-                        self.index += (((self.opcode & 0x0F00) >> 8) + 1) as usize;
-                        self.pc += 2;
-                    }
+            // FX29 - Sets I to the location of the sprite for the character
+            // in VX. Characters 0-F (in hexadecimal) are represented by a
+            // 4x5 font
+            Instruction::Font { x } => {
+                self.index = FONT_ADDRESS + self.registers[x] as usize * 5;
+                self.pc += 2;
+            }
 
-                    0x65 => {
-                        for i in 0..((self.opcode & 0x0F00) >> 8) {
-                            self.registers[i as usize] =
-                                self.memory[self.index as usize + i as usize] as u8;
-                        }
+            // FX30 - Sets I to the location of the 8x10 sprite for the digit
+            // in VX (SCHIP)
+            Instruction::BigFont { x } => {
+                let digit = self.registers[x] & 0xF;
+                self.index = BIG_FONT_ADDRESS + digit as usize * 10;
+                self.pc += 2;
+            }
 
-                        self.index += (((self.opcode & 0x0F00) >> 8) + 1) as usize;
-                        self.pc += 2;
-                    }
+            // FX33 - Stores the Binary-coded decimal representation of VX at
+            // the addresses I, I plus 1, and I plus 2
+            Instruction::Bcd { x } => {
+                let vx = self.registers[x];
+                self.write(self.index, vx / 100)?;
+                self.write(self.index + 1, (vx / 10) % 10)?;
+                self.write(self.index + 2, vx % 10)?;
+                self.pc += 2;
+            }
 
-                    x => panic!("unexpected instr in timer: {}", x),
+            // FX3A - Sets the audio pattern pitch to VX (XO-CHIP)
+            Instruction::Pitch { x } => {
+                self.pitch = self.registers[x];
+                self.pc += 2;
+            }
+
+            // FX55 - Stores V0 to VX in memory starting at address I
+            Instruction::Store { x } => {
+                for i in 0..=x {
+                    self.write(self.index + i, self.registers[i])?;
                 }
+
+                self.index += self.load_store_increment(x);
+                self.pc += 2;
             }
 
-            x => panic!("unexpected instr: {}", x),
+            // FX65 - Loads V0 to VX from memory starting at address I
+            Instruction::Load { x } => {
+                for i in 0..=x {
+                    self.registers[i] = self.read(self.index + i)?;
+                }
+
+                self.index += self.load_store_increment(x);
+                self.pc += 2;
+            }
+
+            // FX75 - Stores V0 to VX in the RPL user flags (SCHIP)
+            Instruction::SaveFlags { x } => {
+                self.rpl[..=x].copy_from_slice(&self.registers[..=x]);
+                self.pc += 2;
+            }
+
+            // FX85 - Loads V0 to VX from the RPL user flags (SCHIP)
+            Instruction::LoadFlags { x } => {
+                self.registers[..=x].copy_from_slice(&self.rpl[..=x]);
+                self.pc += 2;
+            }
+
+            Instruction::System { .. } | Instruction::Unknown(_) => {
+                return Err(ExecErrorKind::UnknownOpcode)
+            }
         }
-        // Update timers
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::Change;
+    use crate::watch::Comparison;
+
+    // A CHIP-8 machine with `program` loaded at 0x200.
+    fn machine(program: &[u16]) -> Chip8 {
+        machine_on(Platform::Chip8, program)
+    }
+
+    fn machine_on(platform: Platform, program: &[u16]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(platform);
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        chip8.load_bytes(&rom).unwrap();
+        chip8
+    }
+
+    fn step(chip8: &mut Chip8, instructions: usize) {
+        for _ in 0..instructions {
+            chip8.emulate_cycle().unwrap();
         }
+    }
 
-        if self.sound_timer > 0 {
-            self.sound_timer -= 1;
+    fn fault(chip8: &mut Chip8) -> ExecErrorKind {
+        chip8.emulate_cycle().unwrap_err().kind
+    }
+
+    #[test]
+    fn clear_00e0() {
+        let mut chip8 = machine(&[0x00E0]);
+        chip8.gfx.set(3, 4, 1);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.gfx.get(3, 4), 0);
+        assert!(chip8.draw_flag);
+    }
+
+    #[test]
+    fn call_and_return_2nnn_00ee() {
+        let mut chip8 = machine(&[0x2206, 0x0000, 0x0000, 0x00EE]);
+        step(&mut chip8, 1);
+        assert_eq!((chip8.pc, chip8.sp, chip8.stack[0]), (0x206, 1, 0x200));
+        step(&mut chip8, 1);
+        assert_eq!((chip8.pc, chip8.sp), (0x202, 0));
+    }
+
+    #[test]
+    fn stack_faults() {
+        let mut chip8 = machine(&[0x00EE]);
+        assert_eq!(fault(&mut chip8), ExecErrorKind::StackUnderflow);
+
+        let mut chip8 = machine(&[0x2200]);
+        step(&mut chip8, 16);
+        assert_eq!(fault(&mut chip8), ExecErrorKind::StackOverflow);
+    }
+
+    #[test]
+    fn jump_1nnn() {
+        let mut chip8 = machine(&[0x1ABC]);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0xABC);
+    }
+
+    #[test]
+    fn skips_3xnn_4xnn_5xy0_9xy0() {
+        let cases = [
+            (0x3142, 0x204),
+            (0x3143, 0x202),
+            (0x4142, 0x202),
+            (0x4143, 0x204),
+            (0x5120, 0x204),
+            (0x5130, 0x202),
+            (0x9120, 0x202),
+            (0x9130, 0x204),
+        ];
+        for &(opcode, pc) in cases.iter() {
+            let mut chip8 = machine(&[opcode]);
+            chip8.registers[1] = 0x42;
+            chip8.registers[2] = 0x42;
+            step(&mut chip8, 1);
+            assert_eq!(chip8.pc, pc, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn xochip_skips_over_long_load() {
+        let mut chip8 = machine_on(Platform::XoChip, &[0x3000, 0xF000, 0x1234]);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x206);
+
+        let mut chip8 = machine_on(Platform::Chip8, &[0x3000, 0x6000]);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x204);
+    }
+
+    #[test]
+    fn load_and_add_immediate_6xnn_7xnn() {
+        let mut chip8 = machine(&[0x6AFE, 0x7A03, 0x7A01]);
+        chip8.registers[0xF] = 9;
+        step(&mut chip8, 2);
+        assert_eq!(chip8.registers[0xA], 0x01);
+        assert_eq!(chip8.registers[0xF], 9, "7XNN doesn't touch VF");
+        step(&mut chip8, 1);
+        assert_eq!(chip8.registers[0xA], 0x02);
+    }
+
+    #[test]
+    fn move_and_logic_8xy0_to_8xy3() {
+        let cases = [
+            (0x8120, 0x0A),
+            (0x8121, 0x0E),
+            (0x8122, 0x08),
+            (0x8123, 0x06),
+        ];
+        for &(opcode, result) in cases.iter() {
+            for &vf_reset in [false, true].iter() {
+                let mut chip8 = machine(&[opcode]);
+                chip8.quirks.vf_reset = vf_reset;
+                chip8.registers[1] = 0x0C;
+                chip8.registers[2] = 0x0A;
+                chip8.registers[0xF] = 7;
+                step(&mut chip8, 1);
+                assert_eq!(chip8.registers[1], result, "{:04X}", opcode);
+
+                let vf = if vf_reset && opcode != 0x8120 { 0 } else { 7 };
+                assert_eq!(chip8.registers[0xF], vf, "{:04X}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn add_8xy4() {
+        // (VX, VY, sum, carry)
+        let cases = [
+            (10, 20, 30, 0),
+            (200, 100, 44, 1),
+            (0xFF, 1, 0, 1),
+            (0x80, 0x7F, 0xFF, 0),
+        ];
+        for &(vx, vy, sum, carry) in cases.iter() {
+            let mut chip8 = machine(&[0x8124]);
+            chip8.registers[1] = vx;
+            chip8.registers[2] = vy;
+            step(&mut chip8, 1);
+            assert_eq!((chip8.registers[1], chip8.registers[0xF]), (sum, carry));
+        }
+
+        // The flag is written last
+        let mut chip8 = machine(&[0x8F24]);
+        chip8.registers[0xF] = 10;
+        chip8.registers[2] = 20;
+        step(&mut chip8, 1);
+        assert_eq!(chip8.registers[0xF], 0);
+    }
+
+    #[test]
+    fn subtract_8xy5_8xy7() {
+        // (VX, VY, VX - VY, no borrow)
+        let cases = [(30, 10, 20, 1), (10, 30, 236, 0), (10, 10, 0, 1)];
+        for &(vx, vy, difference, flag) in cases.iter() {
+            let mut chip8 = machine(&[0x8125]);
+            chip8.registers[1] = vx;
+            chip8.registers[2] = vy;
+            step(&mut chip8, 1);
+            assert_eq!(
+                (chip8.registers[1], chip8.registers[0xF]),
+                (difference, flag)
+            );
+
+            // V2 = V1 - V2
+            let mut chip8 = machine(&[0x8217]);
+            chip8.registers[1] = vx;
+            chip8.registers[2] = vy;
+            step(&mut chip8, 1);
+            assert_eq!(
+                (chip8.registers[2], chip8.registers[0xF]),
+                (difference, flag)
+            );
+        }
+
+        let mut chip8 = machine(&[0x8F25]);
+        chip8.registers[0xF] = 30;
+        chip8.registers[2] = 10;
+        step(&mut chip8, 1);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn shifts_8xy6_8xye() {
+        // (opcode, shift_uses_vy, result, flag) with V1 = 0x81, V2 = 0x42
+        let cases = [
+            (0x8126, true, 0x21, 0),
+            (0x8126, false, 0x40, 1),
+            (0x812E, true, 0x84, 0),
+            (0x812E, false, 0x02, 1),
+        ];
+        for &(opcode, shift_uses_vy, result, flag) in cases.iter() {
+            let mut chip8 = machine(&[opcode]);
+            chip8.quirks.shift_uses_vy = shift_uses_vy;
+            chip8.registers[1] = 0x81;
+            chip8.registers[2] = 0x42;
+            step(&mut chip8, 1);
+            assert_eq!((chip8.registers[1], chip8.registers[0xF]), (result, flag));
+        }
+
+        let mut chip8 = machine(&[0x8FF6]);
+        chip8.registers[0xF] = 5;
+        step(&mut chip8, 1);
+        assert_eq!(chip8.registers[0xF], 1, "the flag is written last");
+    }
+
+    #[test]
+    fn index_annn_fx1e() {
+        let mut chip8 = machine(&[0xAFFE, 0xF11E]);
+        chip8.registers[1] = 3;
+        step(&mut chip8, 1);
+        assert_eq!(chip8.index, 0xFFE);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.index, 0x001);
+        assert_eq!(chip8.registers[0xF], 0);
+
+        let mut chip8 = machine(&[0xAFFE, 0xF11E]);
+        chip8.quirks.index_overflow_sets_vf = true;
+        chip8.registers[1] = 3;
+        step(&mut chip8, 2);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn fx1e_wraps_at_top_of_memory() {
+        let mut chip8 = machine_on(Platform::XoChip, &[0xF000, 0xFFFE, 0xF11E]);
+        chip8.quirks.index_overflow_sets_vf = true;
+        chip8.registers[1] = 0xFF;
+        step(&mut chip8, 2);
+        assert_eq!(chip8.index, 0x00FD);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn jump_with_offset_bnnn() {
+        let mut chip8 = machine(&[0xB3F0]);
+        chip8.registers[0] = 0x20;
+        chip8.registers[3] = 0x02;
+        step(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x410);
+
+        let mut chip8 = machine(&[0xB3F0]);
+        chip8.quirks.jump_uses_vx = true;
+        chip8.registers[0] = 0x20;
+        chip8.registers[3] = 0x02;
+        step(&mut chip8, 1);
+        assert_eq!(chip8.pc, 0x3F2);
+    }
+
+    #[test]
+    fn random_cxnn() {
+        let mut chip8 = machine(&[0xC100, 0xC20F]);
+        step(&mut chip8, 2);
+        assert_eq!(chip8.registers[1], 0);
+        assert_eq!(chip8.registers[2] & 0xF0, 0);
+    }
+
+    #[test]
+    fn draw_dxyn() {
+        // The font's "0" at (62, 30), twice
+        let mut chip8 = machine(&[0xA000, 0xD125, 0xD125]);
+        chip8.registers[1] = 62;
+        chip8.registers[2] = 30;
+        step(&mut chip8, 2);
+        assert_eq!(chip8.registers[0xF], 0);
+        assert_eq!(chip8.gfx.get(62, 30), 1);
+        assert_eq!(chip8.gfx.get(0, 0), 0, "VIP sprites are clipped");
+        step(&mut chip8, 1);
+        assert_eq!(chip8.registers[0xF], 1);
+        assert!(chip8.gfx.as_slice().iter().all(|&p| p == 0));
+
+        let mut chip8 = machine(&[0xA000, 0xD125]);
+        chip8.quirks.clip_sprites = false;
+        chip8.registers[1] = 62;
+        chip8.registers[2] = 30;
+        step(&mut chip8, 2);
+        assert_eq!(chip8.gfx.get(1, 0), 1);
+    }
+
+    #[test]
+    fn keys_ex9e_exa1() {
+        let cases = [
+            (0xE19E, true, 0x204),
+            (0xE19E, false, 0x202),
+            (0xE1A1, true, 0x202),
+            (0xE1A1, false, 0x204),
+        ];
+        for &(opcode, pressed, pc) in cases.iter() {
+            let mut chip8 = machine(&[opcode]);
+            chip8.registers[1] = 0xB;
+            chip8.keypad[0xB] = pressed as u8;
+            step(&mut chip8, 1);
+            assert_eq!(chip8.pc, pc, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn wait_for_key_fx0a() {
+        let mut chip8 = machine(&[0xF10A]);
+        step(&mut chip8, 3);
+        assert_eq!(chip8.pc, 0x200);
+        chip8.keypad[7] = 1;
+        step(&mut chip8, 1);
+        assert_eq!((chip8.pc, chip8.registers[1]), (0x202, 7));
+    }
+
+    #[test]
+    fn timers_fx07_fx15_fx18() {
+        let mut chip8 = machine(&[0xF115, 0xF218, 0xF307]);
+        chip8.registers[1] = 5;
+        chip8.registers[2] = 9;
+        step(&mut chip8, 2);
+        chip8.tick_timers();
+        step(&mut chip8, 1);
+        assert_eq!(chip8.registers[3], 4);
+        assert_eq!(chip8.sound_timer, 8);
+    }
+
+    #[test]
+    fn fonts_fx29_fx30() {
+        let mut chip8 = machine_on(Platform::SuperChip, &[0xF129, 0xF130]);
+        chip8.registers[1] = 0xA;
+        step(&mut chip8, 1);
+        assert_eq!(
+            &chip8.memory[chip8.index..chip8.index + 5],
+            &CHIP8_FONTSET[50..55]
+        );
+        step(&mut chip8, 1);
+        assert_eq!(chip8.index, BIG_FONT_ADDRESS + 100);
+    }
+
+    #[test]
+    fn bcd_fx33() {
+        let mut chip8 = machine(&[0xA300, 0xF133]);
+        chip8.registers[1] = 137;
+        step(&mut chip8, 2);
+        assert_eq!(&chip8.memory[0x300..0x303], &[1, 3, 7]);
+    }
+
+    #[test]
+    fn store_and_load_fx55_fx65() {
+        let cases = [
+            (IndexIncrement::Unchanged, 0x300),
+            (IndexIncrement::ByX, 0x303),
+            (IndexIncrement::ByXPlusOne, 0x304),
+        ];
+        for &(load_store, index) in cases.iter() {
+            let mut chip8 = machine(&[0xA300, 0xF355, 0xA300, 0xF365]);
+            chip8.quirks.load_store = load_store;
+            chip8.registers[..5].copy_from_slice(&[1, 2, 3, 4, 5]);
+            step(&mut chip8, 2);
+            assert_eq!(&chip8.memory[0x300..0x305], &[1, 2, 3, 4, 0]);
+            assert_eq!(chip8.index, index);
+
+            chip8.registers = [0; 16];
+            step(&mut chip8, 2);
+            assert_eq!(&chip8.registers[..5], &[1, 2, 3, 4, 0]);
+            assert_eq!(chip8.index, index);
+        }
+    }
+
+    #[test]
+    fn schip_display_instructions() {
+        let mut chip8 = machine_on(
+            Platform::SuperChip,
+            &[0x00FF, 0x00C2, 0x00FB, 0x00FC, 0x00FE],
+        );
+        step(&mut chip8, 1);
+        assert_eq!((chip8.gfx.width(), chip8.gfx.height()), (128, 64));
+
+        chip8.gfx.set(10, 10, 1);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.gfx.get(10, 12), 1);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.gfx.get(14, 12), 1);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.gfx.get(10, 12), 1);
+
+        step(&mut chip8, 1);
+        assert_eq!((chip8.gfx.width(), chip8.gfx.height()), (64, 32));
+    }
+
+    #[test]
+    fn schip_exit_and_flags_00fd_fx75_fx85() {
+        let mut chip8 = machine_on(Platform::SuperChip, &[0xF275, 0xF285, 0x00FD]);
+        chip8.registers[..3].copy_from_slice(&[7, 8, 9]);
+        step(&mut chip8, 1);
+        chip8.registers = [0; 16];
+        step(&mut chip8, 1);
+        assert_eq!(&chip8.registers[..3], &[7, 8, 9]);
+
+        step(&mut chip8, 2);
+        assert!(chip8.exited());
+        assert_eq!(chip8.pc, 0x204);
+    }
+
+    #[test]
+    fn xochip_ranges_5xy2_5xy3() {
+        let mut chip8 = machine_on(Platform::XoChip, &[0xA300, 0x5132, 0x5312, 0x5133]);
+        chip8.registers[1..4].copy_from_slice(&[1, 2, 3]);
+        step(&mut chip8, 2);
+        assert_eq!(&chip8.memory[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(chip8.index, 0x300);
+
+        step(&mut chip8, 1);
+        assert_eq!(&chip8.memory[0x300..0x303], &[3, 2, 1]);
+
+        chip8.registers = [0; 16];
+        step(&mut chip8, 1);
+        assert_eq!(&chip8.registers[1..4], &[3, 2, 1]);
+    }
+
+    #[test]
+    fn xochip_long_load_plane_audio_pitch() {
+        let program = [0xF000, 0x1234, 0xF201, 0xA300, 0xF002, 0x613F, 0xF13A];
+        let mut chip8 = machine_on(Platform::XoChip, &program);
+        step(&mut chip8, 1);
+        assert_eq!((chip8.index, chip8.pc), (0x1234, 0x204));
+        step(&mut chip8, 1);
+        assert_eq!(chip8.planes(), 2);
+
+        chip8.memory[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        step(&mut chip8, 2);
+        assert_eq!(chip8.audio_pattern(), Some(&[0xAA; 16]));
+        step(&mut chip8, 2);
+        assert_eq!(chip8.pitch(), 0x3F);
+    }
+
+    #[test]
+    fn instructions_outside_the_platform_are_unknown() {
+        for &opcode in [0x00FF, 0x5122, 0xF000, 0x0123, 0x8128].iter() {
+            let mut chip8 = machine(&[opcode]);
+            assert_eq!(
+                fault(&mut chip8),
+                ExecErrorKind::UnknownOpcode,
+                "{:04X}",
+                opcode
+            );
         }
     }
+
+    // Draws, calls and rolls random numbers so that most of the state moves.
+    fn busy_machine() -> Chip8 {
+        let program = [
+            0x6A05, 0xC1FF, 0xF029, 0xD015, 0x220C, 0x1200, 0x7001, 0x00EE,
+        ];
+        let mut chip8 = machine(&program);
+        chip8.keypad[3] = 1;
+        step(&mut chip8, 7);
+        chip8
+    }
+
+    // Replaces the checksum of a state whose body has been edited.
+    fn reseal(mut state: Vec<u8>) -> Vec<u8> {
+        state.truncate(state.len() - 4);
+        let crc = crate::savestate::crc32(&state);
+        state.extend_from_slice(&crc.to_le_bytes());
+        state
+    }
+
+    #[test]
+    fn save_run_load_restores_everything() {
+        let mut chip8 = busy_machine();
+        let saved = chip8.save_state();
+        step(&mut chip8, 40);
+        let ahead = chip8.save_state();
+        assert_ne!(ahead, saved);
+
+        chip8.load_state(&saved).unwrap();
+        assert_eq!(chip8.save_state(), saved);
+        // The RNG is restored too, so the same run follows
+        step(&mut chip8, 40);
+        assert_eq!(chip8.save_state(), ahead);
+    }
+
+    #[test]
+    fn bad_states_leave_the_machine_alone() {
+        let saved = busy_machine().save_state();
+        let header = 6;
+
+        let mut bad_magic = saved.clone();
+        bad_magic[0] = b'X';
+        let mut bad_version = saved.clone();
+        bad_version[4..6].copy_from_slice(&(crate::savestate::VERSION + 1).to_le_bytes());
+        let mut bad_checksum = saved.clone();
+        bad_checksum[header + 10] ^= 0xFF;
+        let truncated = reseal(saved[..saved.len() - 8].to_vec());
+        let mut trailing = saved.clone();
+        trailing.insert(saved.len() - 4, 0);
+        let trailing = reseal(trailing);
+
+        let cases = [
+            (bad_magic, StateError::BadMagic),
+            (
+                bad_version,
+                StateError::UnsupportedVersion(crate::savestate::VERSION + 1),
+            ),
+            (bad_checksum, StateError::ChecksumMismatch),
+            (truncated, StateError::Truncated),
+            (saved[..5].to_vec(), StateError::Truncated),
+            (trailing, StateError::Invalid("trailing data")),
+        ];
+        for (state, error) in cases.iter() {
+            let mut chip8 = machine(&[0x1200]);
+            let before = chip8.save_state();
+            assert_eq!(chip8.load_state(state).as_ref(), Err(error));
+            assert_eq!(chip8.save_state(), before, "{}", error);
+        }
+    }
+
+    // Runs the program until it reaches empty memory, returning the
+    // watchpoints each instruction fired.
+    fn watch(chip8: &mut Chip8, watchpoints: &[Watchpoint]) -> Vec<Vec<WatchHit>> {
+        for &watchpoint in watchpoints {
+            chip8.add_watchpoint(watchpoint);
+        }
+        let mut hits = vec![];
+        while chip8.memory[chip8.pc] != 0 {
+            step(chip8, 1);
+            hits.push(chip8.take_watch_hits());
+        }
+        hits
+    }
+
+    fn hit(pc: usize, watchpoint: Watchpoint, old: u16, new: u16) -> WatchHit {
+        WatchHit {
+            pc,
+            watchpoint,
+            old,
+            new,
+        }
+    }
+
+    #[test]
+    fn write_watchpoints_fx33_fx55() {
+        let mut chip8 = machine(&[0xA300, 0xF033, 0xF255]);
+        chip8.registers[..3].copy_from_slice(&[123, 4, 5]);
+        let hits = watch(
+            &mut chip8,
+            &[Watchpoint::Write(0x301), Watchpoint::Write(0x303)],
+        );
+        assert_eq!(
+            hits,
+            [
+                vec![],
+                vec![hit(0x202, Watchpoint::Write(0x301), 0, 2)],
+                vec![hit(0x204, Watchpoint::Write(0x301), 2, 4)],
+            ]
+        );
+    }
+
+    #[test]
+    fn read_watchpoints_fx65_dxyn() {
+        let mut chip8 = machine(&[0xA300, 0xF165, 0xA300, 0xD003]);
+        chip8.memory[0x300..0x303].copy_from_slice(&[0x80, 0x40, 0x20]);
+        let hits = watch(
+            &mut chip8,
+            &[Watchpoint::Read(0x300), Watchpoint::Read(0x302)],
+        );
+        assert_eq!(
+            hits,
+            [
+                vec![],
+                vec![hit(0x202, Watchpoint::Read(0x300), 0x80, 0x80)],
+                vec![],
+                vec![
+                    hit(0x206, Watchpoint::Read(0x300), 0x80, 0x80),
+                    hit(0x206, Watchpoint::Read(0x302), 0x20, 0x20),
+                ],
+            ]
+        );
+        // Fetching an instruction isn't a data read
+        let mut chip8 = machine(&[0x6001]);
+        assert_eq!(watch(&mut chip8, &[Watchpoint::Read(0x200)]), [vec![]]);
+    }
+
+    #[test]
+    fn register_watchpoints_fire_on_change() {
+        let mut chip8 = machine(&[0x6105, 0x6105, 0xA123, 0x7101]);
+        let hits = watch(&mut chip8, &[Watchpoint::Register(1), Watchpoint::Index]);
+        assert_eq!(
+            hits,
+            [
+                vec![hit(0x200, Watchpoint::Register(1), 0, 5)],
+                vec![],
+                vec![hit(0x204, Watchpoint::Index, 0, 0x123)],
+                vec![hit(0x206, Watchpoint::Register(1), 5, 6)],
+            ]
+        );
+    }
+
+    #[test]
+    fn tracer_records_executed_instructions() {
+        let mut chip8 = machine(&[0x6105, 0x2206, 0x1204, 0xA123, 0x00EE]);
+        chip8.set_tracer(Some(Tracer::new(4)));
+        step(&mut chip8, 5);
+        let entries: Vec<(u64, usize, u16, Vec<Change>)> = chip8
+            .tracer()
+            .unwrap()
+            .entries()
+            .map(|e| (e.cycle, e.pc, e.opcode, e.changes.clone()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (1, 0x202, 0x2206, vec![Change::Sp(0, 1)]),
+                (2, 0x206, 0xA123, vec![Change::I(0, 0x123)]),
+                (3, 0x208, 0x00EE, vec![Change::Sp(1, 0)]),
+                (4, 0x204, 0x1204, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn register_watchpoints_use_the_low_nibble() {
+        let mut chip8 = machine(&[0x6105]);
+        assert_eq!(
+            watch(&mut chip8, &[Watchpoint::Register(0x11)]),
+            [vec![hit(0x200, Watchpoint::Register(0x11), 0, 5)]]
+        );
+    }
+
+    #[test]
+    fn conditions_fire_on_becoming_true() {
+        let program = [0x7101, 0x7101, 0x7101, 0x6100, 0x7101, 0x7101];
+        let mut chip8 = machine(&program);
+        let condition = Watchpoint::Condition(Operand::V(1), Comparison::Ge, 2);
+        let hits = watch(&mut chip8, &[condition]);
+        assert_eq!(
+            hits,
+            [
+                vec![],
+                vec![hit(0x202, condition, 1, 2)],
+                vec![],
+                vec![],
+                vec![],
+                vec![hit(0x20A, condition, 1, 2)],
+            ]
+        );
+
+        // A condition already true when added waits for it to turn false
+        let mut chip8 = machine(&[0x6101, 0x6103, 0x6100]);
+        let condition = Watchpoint::Condition(Operand::V(1), Comparison::Lt, 2);
+        let hits = watch(&mut chip8, &[condition]);
+        assert_eq!(hits, [vec![], vec![], vec![hit(0x204, condition, 3, 0)]]);
+    }
 }
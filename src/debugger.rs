@@ -0,0 +1,576 @@
+//! An interactive debugger that sits between a frontend and the core.
+//!
+//! The frontend passes [`Debugger::check`] as the stop condition of
+//! [`Chip8::run_frame_until`]. When it stops, the frontend shows
+//! [`Debugger::take_stop`] and [`format_state`], reads commands from the user
+//! and hands them to [`Debugger::command`] until one resumes execution.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::address::parse_address;
+use crate::disasm::{self, Syntax};
+use crate::{decode, Chip8, ExecError, Instruction, WatchHit, Watchpoint};
+
+/// Matches instruction words against a pattern like `D01F`, `8XY6` or
+/// `F?55`. `X`, `Y`, `N`, `K`, `?` and `*` match any hex digit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    mask: u16,
+    value: u16,
+}
+
+impl OpcodePattern {
+    pub fn parse(pattern: &str) -> Option<Self> {
+        if pattern.len() != 4 {
+            return None;
+        }
+
+        let mut mask = 0;
+        let mut value = 0;
+        for c in pattern.chars() {
+            mask <<= 4;
+            value <<= 4;
+            match c.to_ascii_uppercase() {
+                'X' | 'Y' | 'N' | 'K' | '?' | '*' => (),
+                c => {
+                    value |= c.to_digit(16)? as u16;
+                    mask |= 0xF;
+                }
+            }
+        }
+
+        Some(Self { mask, value })
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for shift in [12, 8, 4, 0].iter() {
+            if (self.mask >> shift) & 0xF == 0 {
+                write!(f, "?")?;
+            } else {
+                write!(f, "{:X}", (self.value >> shift) & 0xF)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why the debugger stopped execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// A step, step-over or the initial pause finished.
+    Step,
+    Breakpoint(usize),
+    Opcode(OpcodePattern),
+    /// The subroutine being run by `finish` returned.
+    Returned,
+    /// The instruction at PC could not be executed.
+    Fault(ExecError),
+    Watch(WatchHit),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "stopped"),
+            StopReason::Breakpoint(addr) => write!(f, "breakpoint at {:#05X}", addr),
+            StopReason::Opcode(pattern) => write!(f, "opcode matching {}", pattern),
+            StopReason::Returned => write!(f, "returned from subroutine"),
+            StopReason::Fault(error) => write!(f, "fault: {}", error),
+            StopReason::Watch(hit) => write!(f, "{}", hit),
+        }
+    }
+}
+
+/// What the frontend should do after a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Print the output and keep reading commands.
+    Stay(String),
+    /// Resume emulation until the debugger stops again.
+    Resume,
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Paused,
+    Running,
+    /// Execute this many more instructions, then stop.
+    Step(u32),
+    /// Stop once execution is back at `pc` with the stack at `sp`.
+    StepOver {
+        pc: usize,
+        sp: usize,
+    },
+    /// Stop once the stack drops below `sp`.
+    Finish {
+        sp: usize,
+    },
+}
+
+pub const HELP: &str = "\
+commands:
+  s, step [N]        execute N instructions (default 1)
+  n, next            step, running 2NNN calls to completion
+  c, continue        run until a breakpoint
+  f, finish          run until the current subroutine returns
+  b, break ADDR      break when PC reaches ADDR (hex)
+  bo, breakop PAT    break on opcodes matching PAT, e.g. DXYN or 8??6
+  d, delete [ADDR]   delete a breakpoint, or all breakpoints and patterns
+  w, watch EXPR      stop on 'read ADDR', 'write ADDR', a change to 'VX' or
+                     'I', or when a condition like 'V3 == 10' becomes true
+  uw, unwatch [EXPR] delete a watchpoint, or all watchpoints
+  l, list            list breakpoints and watchpoints
+  r, regs            show registers
+  q, quit            exit the emulator";
+
+pub struct Debugger {
+    mode: Mode,
+    breakpoints: BTreeSet<usize>,
+    patterns: Vec<OpcodePattern>,
+    // Set when resuming, so the breakpoint we're sitting on doesn't fire
+    // again before its instruction has run.
+    resuming: bool,
+    stop: Option<StopReason>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// Creates a debugger that pauses before the first instruction.
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Paused,
+            breakpoints: BTreeSet::new(),
+            patterns: vec![],
+            resuming: false,
+            stop: Some(StopReason::Step),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    /// Returns the reason for the most recent stop, once.
+    pub fn take_stop(&mut self) -> Option<StopReason> {
+        self.stop.take()
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn add_pattern(&mut self, pattern: OpcodePattern) {
+        self.patterns.push(pattern);
+    }
+
+    /// Stops execution at the next instruction, e.g. when a watchpoint fires.
+    pub fn pause(&mut self, reason: StopReason) {
+        self.mode = Mode::Paused;
+        self.stop = Some(reason);
+    }
+
+    /// Decides whether to stop before the instruction at `chip8.pc()`.
+    /// Pass this to `Chip8::run_frame_until`.
+    pub fn check(&mut self, chip8: &Chip8) -> bool {
+        let resuming = self.resuming;
+        self.resuming = false;
+
+        let reason = match self.mode {
+            Mode::Paused => return true,
+            Mode::Step(0) => Some(StopReason::Step),
+            Mode::Step(n) => {
+                self.mode = Mode::Step(n - 1);
+                None
+            }
+            Mode::StepOver { pc, sp } if chip8.pc() == pc && chip8.sp() == sp => {
+                Some(StopReason::Step)
+            }
+            Mode::Finish { sp } if chip8.sp() < sp => Some(StopReason::Returned),
+            _ => None,
+        };
+
+        let reason = match reason {
+            Some(reason) => Some(reason),
+            None if resuming => None,
+            None => self.breakpoint_at(chip8),
+        };
+
+        match reason {
+            Some(reason) => {
+                self.pause(reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn breakpoint_at(&self, chip8: &Chip8) -> Option<StopReason> {
+        if self.breakpoints.contains(&chip8.pc()) {
+            return Some(StopReason::Breakpoint(chip8.pc()));
+        }
+
+        let opcode = chip8.next_opcode()?;
+        self.patterns
+            .iter()
+            .find(|pattern| pattern.matches(opcode))
+            .map(|&pattern| StopReason::Opcode(pattern))
+    }
+
+    fn resume(&mut self, mode: Mode) -> Action {
+        self.mode = mode;
+        self.resuming = true;
+        Action::Resume
+    }
+
+    /// Runs one command line typed by the user. Watchpoints live in the
+    /// core, so watch commands modify `chip8`.
+    pub fn command(&mut self, line: &str, chip8: &mut Chip8) -> Action {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Action::Stay(String::new()),
+        };
+        let arg = words.next();
+        let rest = line.trim_start()[command.len()..].trim();
+
+        match command {
+            "s" | "step" => match arg.map(str::parse::<u32>) {
+                None => self.resume(Mode::Step(1)),
+                Some(Ok(n)) if n > 0 => self.resume(Mode::Step(n)),
+                Some(_) => Action::Stay(format!("invalid step count: {}", arg.unwrap())),
+            },
+
+            "n" | "next" => match chip8.next_opcode().map(decode) {
+                Some(Instruction::Call { .. }) => self.resume(Mode::StepOver {
+                    pc: chip8.pc() + 2,
+                    sp: chip8.sp(),
+                }),
+                _ => self.resume(Mode::Step(1)),
+            },
+
+            "c" | "continue" => self.resume(Mode::Running),
+
+            "f" | "finish" => {
+                if chip8.sp() == 0 {
+                    Action::Stay("not in a subroutine".to_string())
+                } else {
+                    self.resume(Mode::Finish { sp: chip8.sp() })
+                }
+            }
+
+            "b" | "break" => match arg.and_then(parse_address) {
+                Some(addr) => {
+                    self.add_breakpoint(addr);
+                    Action::Stay(format!("breakpoint at {:#05X}", addr))
+                }
+                None => Action::Stay("usage: break ADDR".to_string()),
+            },
+
+            "bo" | "breakop" => match arg.and_then(OpcodePattern::parse) {
+                Some(pattern) => {
+                    self.add_pattern(pattern);
+                    Action::Stay(format!("breakpoint on opcode {}", pattern))
+                }
+                None => Action::Stay("usage: breakop PATTERN, e.g. DXYN".to_string()),
+            },
+
+            "d" | "delete" => match arg {
+                None => {
+                    self.breakpoints.clear();
+                    self.patterns.clear();
+                    Action::Stay("deleted all breakpoints".to_string())
+                }
+                Some(arg) => match parse_address(arg) {
+                    Some(addr) if self.remove_breakpoint(addr) => {
+                        Action::Stay(format!("deleted breakpoint at {:#05X}", addr))
+                    }
+                    _ => Action::Stay(format!("no breakpoint at {}", arg)),
+                },
+            },
+
+            "w" | "watch" => match rest.parse::<Watchpoint>() {
+                Ok(watchpoint) => {
+                    chip8.add_watchpoint(watchpoint);
+                    Action::Stay(format!("watching {}", watchpoint))
+                }
+                Err(error) => Action::Stay(error.to_string()),
+            },
+
+            "uw" | "unwatch" if rest.is_empty() => {
+                chip8.clear_watchpoints();
+                Action::Stay("deleted all watchpoints".to_string())
+            }
+
+            "uw" | "unwatch" => match rest.parse::<Watchpoint>() {
+                Ok(watchpoint) if chip8.remove_watchpoint(&watchpoint) => {
+                    Action::Stay(format!("deleted watchpoint {}", watchpoint))
+                }
+                Ok(watchpoint) => Action::Stay(format!("not watching {}", watchpoint)),
+                Err(error) => Action::Stay(error.to_string()),
+            },
+
+            "l" | "list" => Action::Stay(self.list(chip8)),
+            "r" | "regs" => Action::Stay(format_state(chip8)),
+            "h" | "help" | "?" => Action::Stay(HELP.to_string()),
+            "q" | "quit" => Action::Quit,
+            _ => Action::Stay(format!("unknown command '{}', try 'help'", command)),
+        }
+    }
+
+    fn list(&self, chip8: &Chip8) -> String {
+        let mut out = String::new();
+        for addr in self.breakpoints.iter() {
+            let _ = writeln!(out, "break at {:#05X}", addr);
+        }
+        for pattern in self.patterns.iter() {
+            let _ = writeln!(out, "break on {}", pattern);
+        }
+        for watchpoint in chip8.watchpoints() {
+            let _ = writeln!(out, "watch {}", watchpoint);
+        }
+        if out.is_empty() {
+            out.push_str("no breakpoints or watchpoints");
+        }
+        out.trim_end().to_string()
+    }
+}
+
+/// A multi-line dump of the CPU state.
+pub fn format_state(chip8: &Chip8) -> String {
+    let mut out = String::new();
+
+    let next = match chip8.next_opcode() {
+        Some(opcode) => {
+            let operand = match chip8.memory().get(chip8.pc() + 2..chip8.pc() + 4) {
+                Some(word) => (word[0] as u16) << 8 | word[1] as u16,
+                None => 0,
+            };
+            let text = disasm::mnemonic(decode(opcode), operand, Syntax::Octo);
+            format!("{:04X} {}", opcode, text)
+        }
+        None => "----".to_string(),
+    };
+    let _ = writeln!(
+        out,
+        "PC {:#05X} [{}]  I {:#05X}  DT {:02X}  ST {:02X}",
+        chip8.pc(),
+        next,
+        chip8.index(),
+        chip8.delay_timer(),
+        chip8.sound_timer()
+    );
+
+    for (i, v) in chip8.registers().iter().enumerate() {
+        let _ = write!(out, "V{:X} {:02X}", i, v);
+        out.push_str(if i % 8 == 7 { "\n" } else { "  " });
+    }
+
+    let _ = write!(out, "SP {}  stack", chip8.sp());
+    for frame in chip8.stack()[..chip8.sp()].iter() {
+        let _ = write!(out, " {:#05X}", frame);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(program: &[u16]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        chip8.load_bytes(&rom).unwrap();
+        chip8
+    }
+
+    // A debugger past its initial pause.
+    fn debugger() -> Debugger {
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.take_stop(), Some(StopReason::Step));
+        debugger
+    }
+
+    // Runs `command` and then frames the way the debug console does, and
+    // returns why the debugger stopped.
+    fn run(debugger: &mut Debugger, chip8: &mut Chip8, command: &str) -> StopReason {
+        assert_eq!(debugger.command(command, chip8), Action::Resume);
+        for _ in 0..10 {
+            chip8
+                .run_frame_until(|chip8| debugger.check(chip8))
+                .unwrap();
+            if let Some(&hit) = chip8.take_watch_hits().first() {
+                debugger.pause(StopReason::Watch(hit));
+            }
+            if debugger.is_paused() {
+                return debugger.take_stop().unwrap();
+            }
+        }
+        panic!("'{}' didn't stop", command);
+    }
+
+    fn output(debugger: &mut Debugger, chip8: &mut Chip8, command: &str) -> String {
+        match debugger.command(command, chip8) {
+            Action::Stay(output) => output,
+            action => panic!("'{}' gave {:?}", command, action),
+        }
+    }
+
+    // 0x200 calls a subroutine at 0x206 that sets V1, then loops at 0x204.
+    const CALLER: [u16; 5] = [0x2206, 0x6001, 0x1204, 0x6105, 0x00EE];
+
+    #[test]
+    fn starts_paused_and_stays_paused() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = machine(&[0x6001]);
+        assert!(debugger.is_paused());
+        assert_eq!(
+            chip8.run_frame_until(|chip8| debugger.check(chip8)),
+            Ok(true)
+        );
+        assert_eq!(chip8.pc(), 0x200);
+    }
+
+    #[test]
+    fn step_runs_n_instructions() {
+        let mut debugger = debugger();
+        let mut chip8 = machine(&[0x6001, 0x6002, 0x6003, 0x6004, 0x1208]);
+        assert_eq!(run(&mut debugger, &mut chip8, "step"), StopReason::Step);
+        assert_eq!((chip8.pc(), chip8.registers()[0]), (0x202, 1));
+        assert_eq!(run(&mut debugger, &mut chip8, "s 3"), StopReason::Step);
+        assert_eq!((chip8.pc(), chip8.registers()[0]), (0x208, 4));
+        assert_eq!(
+            output(&mut debugger, &mut chip8, "step 0"),
+            "invalid step count: 0"
+        );
+        assert_eq!(
+            output(&mut debugger, &mut chip8, "step x"),
+            "invalid step count: x"
+        );
+    }
+
+    #[test]
+    fn next_runs_calls_to_completion() {
+        let mut debugger = debugger();
+        let mut chip8 = machine(&CALLER);
+        assert_eq!(run(&mut debugger, &mut chip8, "next"), StopReason::Step);
+        assert_eq!((chip8.pc(), chip8.sp()), (0x202, 0));
+        assert_eq!(chip8.registers()[1], 5);
+        // Anything but a call is a single step
+        assert_eq!(run(&mut debugger, &mut chip8, "n"), StopReason::Step);
+        assert_eq!(chip8.pc(), 0x204);
+    }
+
+    #[test]
+    fn breakpoints_inside_a_call_stop_next() {
+        let mut debugger = debugger();
+        let mut chip8 = machine(&CALLER);
+        output(&mut debugger, &mut chip8, "break 208");
+        assert_eq!(
+            run(&mut debugger, &mut chip8, "next"),
+            StopReason::Breakpoint(0x208)
+        );
+        assert_eq!(chip8.sp(), 1);
+    }
+
+    #[test]
+    fn finish_runs_until_the_stack_drops() {
+        let mut debugger = debugger();
+        let mut chip8 = machine(&CALLER);
+        assert_eq!(
+            output(&mut debugger, &mut chip8, "finish"),
+            "not in a subroutine"
+        );
+        run(&mut debugger, &mut chip8, "step");
+        assert_eq!((chip8.pc(), chip8.sp()), (0x206, 1));
+        assert_eq!(run(&mut debugger, &mut chip8, "f"), StopReason::Returned);
+        assert_eq!((chip8.pc(), chip8.sp()), (0x202, 0));
+        assert_eq!(chip8.registers()[1], 5);
+    }
+
+    #[test]
+    fn continue_skips_the_breakpoint_it_stopped_at() {
+        let mut debugger = debugger();
+        // V0 counts up in a loop at 0x202
+        let mut chip8 = machine(&[0x6000, 0x7001, 0x1202]);
+        assert_eq!(
+            output(&mut debugger, &mut chip8, "b 0x202"),
+            "breakpoint at 0x202"
+        );
+        assert_eq!(
+            run(&mut debugger, &mut chip8, "continue"),
+            StopReason::Breakpoint(0x202)
+        );
+        assert_eq!(chip8.registers()[0], 0);
+        assert_eq!(
+            run(&mut debugger, &mut chip8, "c"),
+            StopReason::Breakpoint(0x202)
+        );
+        assert_eq!(chip8.registers()[0], 1);
+
+        assert_eq!(
+            output(&mut debugger, &mut chip8, "delete 202"),
+            "deleted breakpoint at 0x202"
+        );
+        assert_eq!(
+            output(&mut debugger, &mut chip8, "delete 202"),
+            "no breakpoint at 202"
+        );
+        assert_eq!(
+            output(&mut debugger, &mut chip8, "break"),
+            "usage: break ADDR"
+        );
+    }
+
+    #[test]
+    fn opcode_patterns_stop_before_matching_instructions() {
+        let mut debugger = debugger();
+        let mut chip8 = machine(&[0x6001, 0x8016, 0xA000, 0xD015, 0x1208]);
+        assert_eq!(
+            output(&mut debugger, &mut chip8, "breakop DXYN"),
+            "breakpoint on opcode D???"
+        );
+        let pattern = OpcodePattern::parse("DXYN").unwrap();
+        assert_eq!(
+            run(&mut debugger, &mut chip8, "continue"),
+            StopReason::Opcode(pattern)
+        );
+        assert_eq!(chip8.pc(), 0x206);
+
+        output(&mut debugger, &mut chip8, "delete");
+        output(&mut debugger, &mut chip8, "bo 8??6");
+        debugger.add_breakpoint(0x200);
+        assert_eq!(
+            output(&mut debugger, &mut chip8, "list"),
+            "break at 0x200\nbreak on 8??6"
+        );
+    }
+
+    #[test]
+    fn opcode_patterns_parse_hex_and_wildcards() {
+        let pattern = OpcodePattern::parse("f?55").unwrap();
+        assert!(pattern.matches(0xF355));
+        assert!(!pattern.matches(0xF365));
+        assert_eq!(pattern.to_string(), "F?55");
+        assert_eq!(OpcodePattern::parse("8XY"), None);
+        assert_eq!(OpcodePattern::parse("8XYG"), None);
+    }
+}
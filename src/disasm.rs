@@ -0,0 +1,580 @@
+//! Turns ROM images into readable listings.
+//!
+//! [`disassemble`] follows the code from the entry point, so only bytes that
+//! can actually run are shown as instructions. Bytes that DXYN draws after
+//! ANNN points at them are shown as sprite bitmaps, and everything else as
+//! data.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::instruction::{decode, Instruction};
+
+/// The mnemonic style of a listing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Octo's assembly language, e.g. `v0 += 0x01`.
+    #[default]
+    Octo,
+    /// The style of Cowgod's technical reference, e.g. `ADD V0, #01`.
+    Cowgod,
+}
+
+impl Syntax {
+    /// The named syntaxes accepted by `FromStr`.
+    pub const NAMES: [(&'static str, Syntax); 2] =
+        [("octo", Syntax::Octo), ("cowgod", Syntax::Cowgod)];
+}
+
+/// Returned when parsing a syntax name that doesn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownSyntax(pub String);
+
+impl fmt::Display for UnknownSyntax {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown syntax '{}', expected one of:", self.0)?;
+        for (name, _) in Syntax::NAMES.iter() {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnknownSyntax {}
+
+impl FromStr for Syntax {
+    type Err = UnknownSyntax;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalized = name.to_ascii_lowercase();
+        match Syntax::NAMES.iter().find(|(n, _)| *n == normalized) {
+            Some((_, syntax)) => Ok(*syntax),
+            None => Err(UnknownSyntax(name.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    /// An instruction reachable from the entry point.
+    Code(Instruction),
+    /// One row of a sprite drawn by DXYN.
+    Sprite,
+    /// Bytes of unknown purpose.
+    Data,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: usize,
+    pub bytes: Vec<u8>,
+    pub item: Item,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    labels: BTreeMap<usize, String>,
+}
+
+// Data lines are split at labels and at this many bytes.
+const DATA_PER_LINE: usize = 8;
+
+/// Disassembles a ROM that is loaded at `base`, which is also where
+/// execution starts.
+pub fn disassemble(rom: &[u8], base: usize) -> Listing {
+    let end = base + rom.len();
+    let word = |addr: usize| (rom[addr - base] as u16) << 8 | rom[addr - base + 1] as u16;
+    let in_rom = |addr: usize, len: usize| addr >= base && addr + len <= end;
+
+    let mut code = BTreeMap::new();
+    let mut sprites = BTreeSet::new();
+    let mut calls = BTreeSet::new();
+    let mut jumps = BTreeSet::new();
+    let mut data = BTreeSet::new();
+
+    // Each path carries the value of I, if it is known, to find sprites.
+    let mut pending = vec![(base, None)];
+    while let Some((mut addr, mut index)) = pending.pop() {
+        while in_rom(addr, 2) && !code.contains_key(&addr) {
+            let instruction = decode(word(addr));
+            let next = addr + instruction.size();
+            match instruction {
+                Instruction::System { .. } | Instruction::Unknown(_) => break,
+                _ if !in_rom(addr, instruction.size()) => break,
+                _ => code.insert(addr, instruction),
+            };
+
+            match instruction {
+                Instruction::Return | Instruction::Exit | Instruction::JumpOffset { .. } => break,
+
+                Instruction::Jump { nnn } => {
+                    jumps.insert(nnn as usize);
+                    addr = nnn as usize;
+                    continue;
+                }
+
+                Instruction::Call { nnn } => {
+                    calls.insert(nnn as usize);
+                    pending.push((nnn as usize, index));
+                }
+
+                Instruction::SkipEqImm { .. }
+                | Instruction::SkipNeImm { .. }
+                | Instruction::SkipEq { .. }
+                | Instruction::SkipNe { .. }
+                | Instruction::SkipKey { .. }
+                | Instruction::SkipNotKey { .. }
+                    if in_rom(next, 2) =>
+                {
+                    pending.push((next + decode(word(next)).size(), index));
+                }
+
+                Instruction::LoadIndex { nnn } => {
+                    data.insert(nnn as usize);
+                    index = Some(nnn as usize);
+                }
+
+                Instruction::LongLoadIndex => {
+                    let nnnn = word(addr + 2) as usize;
+                    data.insert(nnnn);
+                    index = Some(nnnn);
+                }
+
+                Instruction::Draw { n, .. } => {
+                    if let Some(index) = index {
+                        let len = if n == 0 { 32 } else { n as usize };
+                        sprites.extend(index..index + len);
+                    }
+                }
+
+                Instruction::AddIndex { .. }
+                | Instruction::Font { .. }
+                | Instruction::BigFont { .. }
+                | Instruction::Store { .. }
+                | Instruction::Load { .. } => index = None,
+
+                _ => (),
+            }
+            addr = next;
+        }
+    }
+
+    let mut lines = vec![];
+    let mut addr = base;
+    while addr < end {
+        let (item, len) = if let Some(&instruction) = code.get(&addr) {
+            (Item::Code(instruction), instruction.size())
+        } else if sprites.contains(&addr) {
+            (Item::Sprite, 1)
+        } else {
+            let len = (addr + 1..end)
+                .take(DATA_PER_LINE - 1)
+                .take_while(|a| {
+                    !code.contains_key(a)
+                        && !sprites.contains(a)
+                        && !calls.contains(a)
+                        && !jumps.contains(a)
+                        && !data.contains(a)
+                })
+                .count();
+            (Item::Data, len + 1)
+        };
+
+        lines.push(Line {
+            addr,
+            bytes: rom[addr - base..addr - base + len].to_vec(),
+            item,
+        });
+        addr += len;
+    }
+
+    // Only name targets that start a line, so every label gets defined.
+    let mut labels = BTreeMap::new();
+    for line in lines.iter() {
+        let addr = line.addr;
+        let name = if addr == base && !code.is_empty() {
+            "main".to_string()
+        } else if calls.contains(&addr) {
+            format!("sub_{:03X}", addr)
+        } else if jumps.contains(&addr) {
+            format!("label_{:03X}", addr)
+        } else if data.contains(&addr) && line.item == Item::Sprite {
+            format!("sprite_{:03X}", addr)
+        } else if data.contains(&addr) {
+            format!("data_{:03X}", addr)
+        } else {
+            continue;
+        };
+        labels.insert(addr, name);
+    }
+
+    Listing { lines, labels }
+}
+
+impl Listing {
+    /// The label generated for `addr`, if anything refers to it.
+    pub fn label(&self, addr: usize) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    /// Formats the listing with one line per instruction, sprite row or
+    /// run of data, each preceded by its address and raw bytes.
+    pub fn format(&self, syntax: Syntax) -> String {
+        let mut out = String::new();
+        let name = |addr: usize| match self.label(addr) {
+            Some(label) => label.to_string(),
+            None => address(addr, syntax),
+        };
+
+        for line in self.lines.iter() {
+            if let Some(label) = self.label(line.addr) {
+                let _ = match syntax {
+                    Syntax::Octo => writeln!(out, ": {}", label),
+                    Syntax::Cowgod => writeln!(out, "{}:", label),
+                };
+            }
+
+            let raw: String = match line.item {
+                Item::Data => String::new(),
+                _ => line.bytes.iter().map(|b| format!("{:02X}", b)).collect(),
+            };
+            let text = match line.item {
+                Item::Code(instruction) => {
+                    let operand = match line.bytes.get(2..4) {
+                        Some(word) => (word[0] as u16) << 8 | word[1] as u16,
+                        None => 0,
+                    };
+                    format_instruction(instruction, operand, syntax, &name)
+                }
+                Item::Sprite => {
+                    let bitmap: String = (0..8)
+                        .map(|bit| {
+                            if line.bytes[0] & (0x80 >> bit) != 0 {
+                                '#'
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect();
+                    match syntax {
+                        Syntax::Octo => format!("0x{:02X}  # {}", line.bytes[0], bitmap),
+                        Syntax::Cowgod => format!("DB #{:02X}  ; {}", line.bytes[0], bitmap),
+                    }
+                }
+                Item::Data => {
+                    let bytes: Vec<String> = line.bytes.iter().map(|&b| byte(b, syntax)).collect();
+                    match syntax {
+                        Syntax::Octo => bytes.join(" "),
+                        Syntax::Cowgod => format!("DB {}", bytes.join(", ")),
+                    }
+                }
+            };
+
+            let _ = writeln!(out, "{:04X}  {:<8}  {}", line.addr, raw, text);
+        }
+
+        out
+    }
+}
+
+/// Formats a single instruction without labels. `operand` is the word that
+/// follows F000 and is ignored for every other instruction.
+pub fn mnemonic(instruction: Instruction, operand: u16, syntax: Syntax) -> String {
+    format_instruction(instruction, operand, syntax, &|addr| address(addr, syntax))
+}
+
+fn address(addr: usize, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Octo => format!("0x{:03X}", addr),
+        Syntax::Cowgod => format!("#{:03X}", addr),
+    }
+}
+
+fn byte(value: u8, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Octo => format!("0x{:02X}", value),
+        Syntax::Cowgod => format!("#{:02X}", value),
+    }
+}
+
+fn format_instruction(
+    instruction: Instruction,
+    operand: u16,
+    syntax: Syntax,
+    name: &dyn Fn(usize) -> String,
+) -> String {
+    match syntax {
+        Syntax::Octo => octo(instruction, operand, name),
+        Syntax::Cowgod => cowgod(instruction, operand, name),
+    }
+}
+
+// Octo writes skips as the condition under which the next instruction runs,
+// so 3XNN, "skip if equal", becomes `if vX != NN then`.
+fn octo(instruction: Instruction, operand: u16, name: &dyn Fn(usize) -> String) -> String {
+    use Instruction::*;
+
+    let v = |x: usize| format!("v{:x}", x);
+    let nn = |nn: u8| byte(nn, Syntax::Octo);
+    match instruction {
+        Clear => "clear".to_string(),
+        Return => "return".to_string(),
+        ScrollDown { n } => format!("scroll-down {}", n),
+        ScrollUp { n } => format!("scroll-up {}", n),
+        ScrollRight => "scroll-right".to_string(),
+        ScrollLeft => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        Lores => "lores".to_string(),
+        Hires => "hires".to_string(),
+        System { nnn } => format!("0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+        Jump { nnn } => format!("jump {}", name(nnn as usize)),
+        Call { nnn } => format!(":call {}", name(nnn as usize)),
+        SkipEqImm { x, nn: b } => format!("if {} != {} then", v(x), nn(b)),
+        SkipNeImm { x, nn: b } => format!("if {} == {} then", v(x), nn(b)),
+        SkipEq { x, y } => format!("if {} != {} then", v(x), v(y)),
+        SaveRange { x, y } => format!("save {} - {}", v(x), v(y)),
+        LoadRange { x, y } => format!("load {} - {}", v(x), v(y)),
+        LoadImm { x, nn: b } => format!("{} := {}", v(x), nn(b)),
+        AddImm { x, nn: b } => format!("{} += {}", v(x), nn(b)),
+        Move { x, y } => format!("{} := {}", v(x), v(y)),
+        Or { x, y } => format!("{} |= {}", v(x), v(y)),
+        And { x, y } => format!("{} &= {}", v(x), v(y)),
+        Xor { x, y } => format!("{} ^= {}", v(x), v(y)),
+        Add { x, y } => format!("{} += {}", v(x), v(y)),
+        Sub { x, y } => format!("{} -= {}", v(x), v(y)),
+        ShiftRight { x, y } => format!("{} >>= {}", v(x), v(y)),
+        SubReverse { x, y } => format!("{} =- {}", v(x), v(y)),
+        ShiftLeft { x, y } => format!("{} <<= {}", v(x), v(y)),
+        SkipNe { x, y } => format!("if {} == {} then", v(x), v(y)),
+        LoadIndex { nnn } => format!("i := {}", name(nnn as usize)),
+        JumpOffset { nnn } => format!("jump0 {}", name(nnn as usize)),
+        Random { x, nn: b } => format!("{} := random {}", v(x), nn(b)),
+        Draw { x, y, n } => format!("sprite {} {} {}", v(x), v(y), n),
+        SkipKey { x } => format!("if {} -key then", v(x)),
+        SkipNotKey { x } => format!("if {} key then", v(x)),
+        LongLoadIndex => format!("i := long {}", name(operand as usize)),
+        Plane { n } => format!("plane {}", n),
+        LoadAudio => "audio".to_string(),
+        GetDelay { x } => format!("{} := delay", v(x)),
+        WaitKey { x } => format!("{} := key", v(x)),
+        SetDelay { x } => format!("delay := {}", v(x)),
+        SetSound { x } => format!("buzzer := {}", v(x)),
+        AddIndex { x } => format!("i += {}", v(x)),
+        Font { x } => format!("i := hex {}", v(x)),
+        BigFont { x } => format!("i := bighex {}", v(x)),
+        Bcd { x } => format!("bcd {}", v(x)),
+        Pitch { x } => format!("pitch := {}", v(x)),
+        Store { x } => format!("save {}", v(x)),
+        Load { x } => format!("load {}", v(x)),
+        SaveFlags { x } => format!("saveflags {}", v(x)),
+        LoadFlags { x } => format!("loadflags {}", v(x)),
+        Unknown(word) => format!("0x{:02X} 0x{:02X}", word >> 8, word & 0xFF),
+    }
+}
+
+fn cowgod(instruction: Instruction, operand: u16, name: &dyn Fn(usize) -> String) -> String {
+    use Instruction::*;
+
+    let v = |x: usize| format!("V{:X}", x);
+    let nn = |nn: u8| byte(nn, Syntax::Cowgod);
+    match instruction {
+        Clear => "CLS".to_string(),
+        Return => "RET".to_string(),
+        ScrollDown { n } => format!("SCD {}", n),
+        ScrollUp { n } => format!("SCU {}", n),
+        ScrollRight => "SCR".to_string(),
+        ScrollLeft => "SCL".to_string(),
+        Exit => "EXIT".to_string(),
+        Lores => "LOW".to_string(),
+        Hires => "HIGH".to_string(),
+        System { nnn } => format!("SYS {}", name(nnn as usize)),
+        Jump { nnn } => format!("JP {}", name(nnn as usize)),
+        Call { nnn } => format!("CALL {}", name(nnn as usize)),
+        SkipEqImm { x, nn: b } => format!("SE {}, {}", v(x), nn(b)),
+        SkipNeImm { x, nn: b } => format!("SNE {}, {}", v(x), nn(b)),
+        SkipEq { x, y } => format!("SE {}, {}", v(x), v(y)),
+        SaveRange { x, y } => format!("SAVE {} - {}", v(x), v(y)),
+        LoadRange { x, y } => format!("LOAD {} - {}", v(x), v(y)),
+        LoadImm { x, nn: b } => format!("LD {}, {}", v(x), nn(b)),
+        AddImm { x, nn: b } => format!("ADD {}, {}", v(x), nn(b)),
+        Move { x, y } => format!("LD {}, {}", v(x), v(y)),
+        Or { x, y } => format!("OR {}, {}", v(x), v(y)),
+        And { x, y } => format!("AND {}, {}", v(x), v(y)),
+        Xor { x, y } => format!("XOR {}, {}", v(x), v(y)),
+        Add { x, y } => format!("ADD {}, {}", v(x), v(y)),
+        Sub { x, y } => format!("SUB {}, {}", v(x), v(y)),
+        ShiftRight { x, y } => format!("SHR {}, {}", v(x), v(y)),
+        SubReverse { x, y } => format!("SUBN {}, {}", v(x), v(y)),
+        ShiftLeft { x, y } => format!("SHL {}, {}", v(x), v(y)),
+        SkipNe { x, y } => format!("SNE {}, {}", v(x), v(y)),
+        LoadIndex { nnn } => format!("LD I, {}", name(nnn as usize)),
+        JumpOffset { nnn } => format!("JP V0, {}", name(nnn as usize)),
+        Random { x, nn: b } => format!("RND {}, {}", v(x), nn(b)),
+        Draw { x, y, n } => format!("DRW {}, {}, {}", v(x), v(y), n),
+        SkipKey { x } => format!("SKP {}", v(x)),
+        SkipNotKey { x } => format!("SKNP {}", v(x)),
+        LongLoadIndex => format!("LD I, LONG {}", name(operand as usize)),
+        Plane { n } => format!("PLANE {}", n),
+        LoadAudio => "AUDIO".to_string(),
+        GetDelay { x } => format!("LD {}, DT", v(x)),
+        WaitKey { x } => format!("LD {}, K", v(x)),
+        SetDelay { x } => format!("LD DT, {}", v(x)),
+        SetSound { x } => format!("LD ST, {}", v(x)),
+        AddIndex { x } => format!("ADD I, {}", v(x)),
+        Font { x } => format!("LD F, {}", v(x)),
+        BigFont { x } => format!("LD HF, {}", v(x)),
+        Bcd { x } => format!("LD B, {}", v(x)),
+        Pitch { x } => format!("PITCH {}", v(x)),
+        Store { x } => format!("LD [I], {}", v(x)),
+        Load { x } => format!("LD {}, [I]", v(x)),
+        SaveFlags { x } => format!("LD R, {}", v(x)),
+        LoadFlags { x } => format!("LD {}, R", v(x)),
+        Unknown(word) => format!("DW #{:04X}", word),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::platform::Platform;
+
+    fn words(program: &[u16]) -> Vec<u8> {
+        program.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    // The listing's text column, as the assembler reads it.
+    fn source(listing: &Listing) -> String {
+        listing
+            .format(Syntax::Cowgod)
+            .lines()
+            .map(|line| match line.get(..4) {
+                Some(addr) if addr.chars().all(|c| c.is_ascii_hexdigit()) => &line[16..],
+                _ => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // Calls a subroutine, draws a sprite and loops, with stray data after.
+    const PROGRAM: [u16; 8] = [
+        0x2208, 0xA20C, 0xD012, 0x1206, 0x6105, 0x00EE, 0xF090, 0x1234,
+    ];
+
+    #[test]
+    fn finds_code_sprites_and_data() {
+        let listing = disassemble(&words(&PROGRAM), 0x200);
+        let items: Vec<(usize, Item)> = listing
+            .lines
+            .iter()
+            .map(|line| (line.addr, line.item))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                (0x200, Item::Code(Instruction::Call { nnn: 0x208 })),
+                (0x202, Item::Code(Instruction::LoadIndex { nnn: 0x20C })),
+                (0x204, Item::Code(Instruction::Draw { x: 0, y: 1, n: 2 })),
+                (0x206, Item::Code(Instruction::Jump { nnn: 0x206 })),
+                (0x208, Item::Code(Instruction::LoadImm { x: 1, nn: 5 })),
+                (0x20A, Item::Code(Instruction::Return)),
+                (0x20C, Item::Sprite),
+                (0x20D, Item::Sprite),
+                (0x20E, Item::Data),
+            ]
+        );
+        assert_eq!(listing.lines[8].bytes, [0x12, 0x34]);
+    }
+
+    #[test]
+    fn labels_jump_and_call_targets() {
+        let listing = disassemble(&words(&PROGRAM), 0x200);
+        assert_eq!(listing.label(0x200), Some("main"));
+        assert_eq!(listing.label(0x206), Some("label_206"));
+        assert_eq!(listing.label(0x208), Some("sub_208"));
+        assert_eq!(listing.label(0x20C), Some("sprite_20C"));
+        assert_eq!(listing.label(0x20E), None);
+
+        // A target inside an instruction can't be labelled
+        let listing = disassemble(&words(&[0x1201]), 0x200);
+        assert_eq!(listing.label(0x201), None);
+        assert!(listing.format(Syntax::Octo).contains("jump 0x201"));
+    }
+
+    #[test]
+    fn formats_octo() {
+        let listing = disassemble(&words(&PROGRAM), 0x200);
+        assert_eq!(
+            listing.format(Syntax::Octo),
+            ": main
+0200  2208      :call sub_208
+0202  A20C      i := sprite_20C
+0204  D012      sprite v0 v1 2
+: label_206
+0206  1206      jump label_206
+: sub_208
+0208  6105      v1 := 0x05
+020A  00EE      return
+: sprite_20C
+020C  F0        0xF0  # ####....
+020D  90        0x90  # #..#....
+020E            0x12 0x34
+"
+        );
+    }
+
+    #[test]
+    fn formats_cowgod() {
+        let listing = disassemble(&words(&PROGRAM), 0x200);
+        assert_eq!(
+            listing.format(Syntax::Cowgod),
+            "main:
+0200  2208      CALL sub_208
+0202  A20C      LD I, sprite_20C
+0204  D012      DRW V0, V1, 2
+label_206:
+0206  1206      JP label_206
+sub_208:
+0208  6105      LD V1, #05
+020A  00EE      RET
+sprite_20C:
+020C  F0        DB #F0  ; ####....
+020D  90        DB #90  ; #..#....
+020E            DB #12, #34
+"
+        );
+    }
+
+    #[test]
+    fn octo_skips_read_as_conditions() {
+        let skip = |opcode: u16| mnemonic(decode(opcode), 0, Syntax::Octo);
+        assert_eq!(skip(0x3A12), "if va != 0x12 then");
+        assert_eq!(skip(0x4A12), "if va == 0x12 then");
+        assert_eq!(skip(0xE19E), "if v1 -key then");
+        assert_eq!(
+            mnemonic(decode(0xF000), 0x1234, Syntax::Octo),
+            "i := long 0x1234"
+        );
+        assert_eq!(
+            mnemonic(decode(0xF000), 0x1234, Syntax::Cowgod),
+            "LD I, LONG #1234"
+        );
+    }
+
+    // Assembling the Cowgod listing of every game gives the game back.
+    #[test]
+    fn games_reassemble_to_the_same_bytes() {
+        for entry in std::fs::read_dir("roms").unwrap() {
+            let path = entry.unwrap().path();
+            let rom = std::fs::read(&path).unwrap();
+            let source = source(&disassemble(&rom, 0x200));
+            match assemble(&source, Platform::Chip8) {
+                Ok(bytes) => assert!(bytes == rom, "{}", path.display()),
+                Err(e) => panic!("{}: {}", path.display(), e),
+            }
+        }
+    }
+}
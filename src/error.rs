@@ -0,0 +1,167 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why an instruction could not be executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecErrorKind {
+    /// The fetched word does not decode to any supported instruction.
+    UnknownOpcode,
+    /// 2NNN was executed with all 16 stack slots in use.
+    StackOverflow,
+    /// 00EE was executed with an empty stack.
+    StackUnderflow,
+    /// The instruction touched an address outside of memory.
+    MemoryOutOfBounds { addr: usize },
+}
+
+/// A fault raised by [`Chip8::emulate_cycle`](crate::Chip8::emulate_cycle).
+///
+/// `pc` and `opcode` identify the faulting instruction. The machine is left
+/// as it was when the fault was detected, so a frontend can inspect it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecError {
+    pub pc: usize,
+    pub opcode: u16,
+    pub kind: ExecErrorKind,
+}
+
+impl fmt::Display for ExecErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecErrorKind::UnknownOpcode => write!(f, "unknown opcode"),
+            ExecErrorKind::StackOverflow => write!(f, "stack overflow"),
+            ExecErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            ExecErrorKind::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#05X}", addr)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (opcode {:04X} at {:#05X})",
+            self.kind, self.opcode, self.pc
+        )
+    }
+}
+
+impl Error for ExecError {}
+
+/// Why a ROM could not be loaded into memory.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The ROM contains no bytes.
+    Empty,
+    /// The ROM does not fit between the load address and the end of memory.
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Empty => write!(f, "ROM is empty"),
+            LoadError::TooLarge { size, max } => write!(
+                f,
+                "ROM is {} bytes, but only {} bytes fit in memory",
+                size, max
+            ),
+            LoadError::Io(error) => write!(f, "failed to read ROM: {}", error),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+/// Why a save state could not be restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The data doesn't start with the save state magic.
+    BadMagic,
+    /// The state was written by an incompatible version of the format.
+    UnsupportedVersion(u16),
+    /// The data is corrupt.
+    ChecksumMismatch,
+    /// The data ends before all of the machine state has been read.
+    Truncated,
+    /// A field holds a value that the machine can't be in.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::ChecksumMismatch => write!(f, "save state checksum mismatch"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Why a movie could not be read or played back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data doesn't start with the movie magic.
+    BadMagic,
+    /// The movie was written by an incompatible version of the format.
+    UnsupportedVersion(u16),
+    /// The data is truncated, fails its checksum or holds invalid values.
+    Corrupt,
+    /// The movie was recorded with a different ROM, identified by CRC-32.
+    RomMismatch { expected: u32, found: u32 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "unsupported movie version {}", version)
+            }
+            MovieError::Corrupt => write!(f, "movie is corrupt"),
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "movie was recorded with a different ROM (CRC {:08X}, this one is {:08X})",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(error: StateError) -> Self {
+        match error {
+            StateError::BadMagic => MovieError::BadMagic,
+            StateError::UnsupportedVersion(version) => MovieError::UnsupportedVersion(version),
+            _ => MovieError::Corrupt,
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::png;
+
+// Characters for the four pixel values in `to_ascii`.
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+/// A display buffer, one byte per pixel, stored row-major.
+///
+/// Each bit of a pixel belongs to one bitplane: bit 0 is the only plane on
+/// CHIP-8 and SUPER-CHIP, while XO-CHIP also draws to bit 1, giving up to
+/// four colours. Sprites are drawn by XOR, as on the original interpreter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    /// Rebuilds a framebuffer from raw row-major pixels, if the sizes agree.
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<u8>) -> Option<Self> {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return None;
+        }
+
+        Some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn clear(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = 0;
+        }
+    }
+
+    /// Clears only the bitplanes selected by `planes`.
+    pub fn clear_planes(&mut self, planes: u8) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= !planes;
+        }
+    }
+
+    /// Returns the pixel at `(x, y)`, or 0 if it is off screen.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x]
+        } else {
+            0
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: u8) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = value;
+        }
+    }
+
+    /// Discards the current contents and switches to a new resolution.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = Framebuffer::new(width, height);
+    }
+
+    /// XORs an 8-pixel-wide sprite onto the bitplane `plane` (a single bit)
+    /// and reports whether any lit pixel on it was turned off.
+    ///
+    /// The starting coordinate always wraps around the screen. The parts of
+    /// the sprite that run past the right or bottom edge wrap as well when
+    /// `wrap` is set, and are clipped otherwise.
+    pub fn draw_sprite(
+        &mut self,
+        x: usize,
+        y: usize,
+        plane: u8,
+        sprite: &[u8],
+        wrap: bool,
+    ) -> bool {
+        let rows = sprite.iter().map(|&row| (row as u16) << 8);
+        self.draw_rows(x, y, 8, plane, rows, wrap)
+    }
+
+    /// Like `draw_sprite`, but for the 16x16 SUPER-CHIP sprites, stored as
+    /// two bytes per row.
+    pub fn draw_wide_sprite(
+        &mut self,
+        x: usize,
+        y: usize,
+        plane: u8,
+        sprite: &[u8],
+        wrap: bool,
+    ) -> bool {
+        let rows = sprite
+            .chunks(2)
+            .map(|row| (row[0] as u16) << 8 | *row.get(1).unwrap_or(&0) as u16);
+        self.draw_rows(x, y, 16, plane, rows, wrap)
+    }
+
+    fn draw_rows<I>(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        plane: u8,
+        rows: I,
+        wrap: bool,
+    ) -> bool
+    where
+        I: Iterator<Item = u16>,
+    {
+        let x = x % self.width;
+        let y = y % self.height;
+        let mut collision = false;
+
+        for (yline, row) in rows.enumerate() {
+            let mut py = y + yline;
+            if py >= self.height {
+                if !wrap {
+                    break;
+                }
+                py %= self.height;
+            }
+
+            for xline in 0..width {
+                let mut px = x + xline;
+                if px >= self.width {
+                    if !wrap {
+                        break;
+                    }
+                    px %= self.width;
+                }
+
+                if row & (0x8000 >> xline) != 0 {
+                    let pixel = &mut self.pixels[py * self.width + px];
+                    if *pixel & plane != 0 {
+                        collision = true;
+                    }
+                    *pixel ^= plane;
+                }
+            }
+        }
+
+        collision
+    }
+
+    /// Moves the selected bitplanes down by `n` rows, blanking the rows
+    /// scrolled in.
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height);
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let from = if y >= n { self.get(x, y - n) } else { 0 };
+                self.blit(x, y, from, planes);
+            }
+        }
+    }
+
+    /// Moves the selected bitplanes up by `n` rows, blanking the rows
+    /// scrolled in.
+    pub fn scroll_up(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let from = self.get(x, y + n);
+                self.blit(x, y, from, planes);
+            }
+        }
+    }
+
+    /// Moves the selected bitplanes right by `n` columns, blanking the
+    /// columns scrolled in.
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width);
+        for y in 0..self.height {
+            for x in (0..self.width).rev() {
+                let from = if x >= n { self.get(x - n, y) } else { 0 };
+                self.blit(x, y, from, planes);
+            }
+        }
+    }
+
+    /// Moves the selected bitplanes left by `n` columns, blanking the
+    /// columns scrolled in.
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let from = self.get(x + n, y);
+                self.blit(x, y, from, planes);
+            }
+        }
+    }
+
+    /// Copies the `planes` bits of `from` into the pixel at `(x, y)`.
+    fn blit(&mut self, x: usize, y: usize, from: u8, planes: u8) {
+        let pixel = &mut self.pixels[y * self.width + x];
+        *pixel = (*pixel & !planes) | (from & planes);
+    }
+
+    /// Iterates over the rows of the screen, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    /// Iterates over every pixel as `(x, y, value)`.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, u8)> + '_ {
+        let width = self.width;
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(move |(i, &value)| (i % width, i / width, value))
+    }
+
+    /// The raw pixel data, row-major.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Renders the screen as text, one line per row, with `.` for unlit
+    /// pixels and `#` for lit ones (`+` and `@` for the other XO-CHIP
+    /// colours).
+    pub fn to_ascii(&self) -> String {
+        let mut out = String::with_capacity((self.width + 1) * self.height);
+        for row in self.rows() {
+            out.extend(row.iter().map(|&pixel| ASCII_PIXELS[pixel as usize & 3]));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Encodes the screen as a PNG image, mapping each pixel value to a
+    /// palette colour and scaling every pixel up to `scale` x `scale`.
+    pub fn to_png(&self, palette: &[[u8; 3]; 4], scale: usize) -> Vec<u8> {
+        let scale = scale.max(1);
+        let width = self.width * scale;
+        let mut pixels = Vec::with_capacity(width * self.height * scale);
+        for row in self.rows() {
+            let line: Vec<u8> = row
+                .iter()
+                .flat_map(|&pixel| std::iter::repeat_n(pixel & 3, scale))
+                .collect();
+            for _ in 0..scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+        png::encode_indexed(width, self.height * scale, &pixels, palette)
+    }
+}
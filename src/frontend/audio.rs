@@ -0,0 +1,100 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+
+use chip8::AudioOutput;
+
+pub const DEFAULT_TONE: f32 = 440.0;
+const VOLUME: f32 = 0.25;
+
+struct Buzzer {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    sample_rate: f32,
+
+    // An XO-CHIP pattern replaces the square wave once loaded. The phase then
+    // runs over the pattern's 128 bits instead of one square wave period.
+    pattern: Option<[u8; 16]>,
+    pattern_inc: f32,
+}
+
+impl AudioCallback for Buzzer {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            let high = match self.pattern {
+                Some(pattern) => {
+                    let bit = (self.phase * 128.0) as usize & 127;
+                    self.phase = (self.phase + self.pattern_inc) % 1.0;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => {
+                    self.phase = (self.phase + self.phase_inc) % 1.0;
+                    self.phase <= 0.5
+                }
+            };
+
+            *x = if high { self.volume } else { -self.volume };
+        }
+    }
+}
+
+/// Plays a square wave, or an XO-CHIP pattern, through SDL while the sound
+/// timer is running.
+pub struct SdlBeeper {
+    device: AudioDevice<Buzzer>,
+    beeping: bool,
+    pattern: Option<([u8; 16], u8)>,
+}
+
+impl SdlBeeper {
+    pub fn new(audio: &AudioSubsystem, tone: f32) -> Result<Self, String> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio.open_playback(None, &desired_spec, |spec| Buzzer {
+            phase_inc: tone / spec.freq as f32,
+            phase: 0.0,
+            volume: VOLUME,
+            sample_rate: spec.freq as f32,
+            pattern: None,
+            pattern_inc: 0.0,
+        })?;
+
+        Ok(Self {
+            device,
+            beeping: false,
+            pattern: None,
+        })
+    }
+}
+
+impl AudioOutput for SdlBeeper {
+    fn set_beeping(&mut self, beeping: bool) {
+        if beeping == self.beeping {
+            return;
+        }
+
+        self.beeping = beeping;
+        if beeping {
+            self.device.resume();
+        } else {
+            self.device.pause();
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        if self.pattern == Some((*pattern, pitch)) {
+            return;
+        }
+        self.pattern = Some((*pattern, pitch));
+
+        let mut buzzer = self.device.lock();
+        buzzer.pattern = Some(*pattern);
+        buzzer.pattern_inc = chip8::pattern_rate(pitch) / 128.0 / buzzer.sample_rate;
+    }
+}
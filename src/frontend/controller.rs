@@ -0,0 +1,169 @@
+use sdl2::controller::GameController;
+use sdl2::GameControllerSubsystem;
+
+/// The game controllers that are plugged in. SDL only reports button
+/// presses from controllers that are open, so each one is opened as it
+/// arrives, including the ones connected at startup.
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    open: Vec<GameController>,
+}
+
+impl Controllers {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Self {
+            subsystem,
+            open: vec![],
+        }
+    }
+
+    /// Opens the controller at joystick index `index`. Returns its name,
+    /// or None if it was already open.
+    pub fn connect(&mut self, index: u32) -> Result<Option<String>, String> {
+        let controller = match self.subsystem.open(index) {
+            Ok(controller) => controller,
+            Err(error) => return Err(format!("Error opening controller {}: {}", index, error)),
+        };
+        let id = controller.instance_id();
+        if self.open.iter().any(|c| c.instance_id() == id) {
+            return Ok(None);
+        }
+        let name = controller.name();
+        self.open.push(controller);
+        Ok(Some(name))
+    }
+
+    /// Closes the controller with instance id `id`, returning its name.
+    pub fn disconnect(&mut self, id: u32) -> Option<String> {
+        let i = self
+            .open
+            .iter()
+            .position(|c| c.instance_id() as u32 == id)?;
+        Some(self.open.remove(i).name())
+    }
+}
+
+// Plugs in an SDL virtual joystick, so this needs SDL 2.0.14 or later.
+#[cfg(test)]
+mod tests {
+    use std::os::raw::c_int;
+    use std::time::{Duration, Instant};
+
+    use sdl2::controller::Button;
+    use sdl2::event::Event;
+    use sdl2::sys::SDL_Joystick;
+    use sdl2::EventPump;
+
+    use chip8::keymap::BUTTONS;
+    use chip8::{Gamepads, Keymap};
+
+    use super::*;
+
+    // Not bound by sdl2-sys 0.33
+    extern "C" {
+        fn SDL_JoystickAttachVirtual(
+            kind: c_int,
+            axes: c_int,
+            buttons: c_int,
+            hats: c_int,
+        ) -> c_int;
+        fn SDL_JoystickDetachVirtual(device_index: c_int) -> c_int;
+        fn SDL_JoystickSetVirtualButton(
+            joystick: *mut SDL_Joystick,
+            button: c_int,
+            value: u8,
+        ) -> c_int;
+    }
+
+    const SDL_JOYSTICK_TYPE_GAMECONTROLLER: c_int = 1;
+
+    // Waits up to a second for an event that `select` picks out.
+    fn wait_for<T>(events: &mut EventPump, select: impl Fn(Event) -> Option<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline {
+            if let Some(found) = events.poll_iter().find_map(&select) {
+                return found;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("timed out waiting for a controller event");
+    }
+
+    fn set_button(id: u32, button: Button, pressed: bool) {
+        unsafe {
+            let joystick = sdl2::sys::SDL_JoystickFromInstanceID(id as i32);
+            assert!(!joystick.is_null());
+            assert_eq!(
+                SDL_JoystickSetVirtualButton(joystick, button as c_int, pressed as u8),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn virtual_controller_presses_keys_and_hot_plugs() {
+        let context = sdl2::init().unwrap();
+        let joysticks = context.joystick().unwrap();
+        let subsystem = context.game_controller().unwrap();
+        let mut events = context.event_pump().unwrap();
+        let mut controllers = Controllers::new(subsystem.clone());
+        let mut gamepads = Gamepads::new(Keymap::default().buttons(None));
+
+        // One virtual button for each of `BUTTONS`, which are in SDL's order
+        let buttons = BUTTONS.len() as c_int;
+        let index =
+            unsafe { SDL_JoystickAttachVirtual(SDL_JOYSTICK_TYPE_GAMECONTROLLER, 6, buttons, 0) };
+        assert!(index >= 0, "{}", sdl2::get_error());
+        let guid = joysticks.device_guid(index as u32).unwrap();
+        let mapping: Vec<String> = BUTTONS
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("{}:b{}", name, i))
+            .collect();
+        subsystem
+            .add_mapping(&format!("{},Virtual pad,{},", guid, mapping.join(",")))
+            .unwrap();
+
+        let which = wait_for(&mut events, |event| match event {
+            Event::ControllerDeviceAdded { which, .. } => Some(which),
+            _ => None,
+        });
+        assert!(controllers.connect(which).unwrap().is_some());
+        assert_eq!(controllers.connect(which).unwrap(), None);
+        let id = controllers.open[0].instance_id() as u32;
+
+        // A is bound to 6 and the d-pad to 5 7 8 9
+        set_button(id, Button::A, true);
+        let (pad, button) = wait_for(&mut events, |event| match event {
+            Event::ControllerButtonDown { which, button, .. } => Some((which, button)),
+            _ => None,
+        });
+        assert_eq!((pad, button), (id, Button::A));
+        assert_eq!(gamepads.press(pad, &button.string()), Some(0x6));
+
+        set_button(id, Button::A, false);
+        let (pad, button) = wait_for(&mut events, |event| match event {
+            Event::ControllerButtonUp { which, button, .. } => Some((which, button)),
+            _ => None,
+        });
+        assert_eq!(gamepads.release(pad, &button.string()), Some(0x6));
+
+        // Unplugging with a button held releases its key
+        set_button(id, Button::DPadUp, true);
+        let (pad, button) = wait_for(&mut events, |event| match event {
+            Event::ControllerButtonDown { which, button, .. } => Some((which, button)),
+            _ => None,
+        });
+        assert_eq!(gamepads.press(pad, &button.string()), Some(0x5));
+
+        assert_eq!(unsafe { SDL_JoystickDetachVirtual(index) }, 0);
+        let removed = wait_for(&mut events, |event| match event {
+            Event::ControllerDeviceRemoved { which, .. } => Some(which),
+            _ => None,
+        });
+        assert_eq!(removed, id);
+        assert!(controllers.disconnect(removed).is_some());
+        assert_eq!(controllers.disconnect(removed), None);
+        assert_eq!(gamepads.disconnect(removed), [0x5]);
+    }
+}
@@ -0,0 +1,78 @@
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use chip8::debugger::{self, Action, StopReason};
+use chip8::{Chip8, Debugger};
+
+/// Runs the debugger on the terminal while the window keeps rendering.
+///
+/// Commands are read on a separate thread so that SDL events are still
+/// pumped while the emulator is paused.
+pub struct DebugConsole {
+    pub debugger: Debugger,
+    lines: Receiver<String>,
+}
+
+impl DebugConsole {
+    pub fn new() -> Self {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("{}", debugger::HELP);
+        Self {
+            debugger: Debugger::new(),
+            lines,
+        }
+    }
+
+    /// Handles pending commands, then runs one frame unless paused.
+    /// Returns false when the user asked to quit.
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> bool {
+        if let Some(reason) = self.debugger.take_stop() {
+            println!("{}", reason);
+            println!("{}", debugger::format_state(chip8));
+            prompt();
+        }
+
+        while self.debugger.is_paused() {
+            let line = match self.lines.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            };
+
+            match self.debugger.command(&line, chip8) {
+                Action::Stay(output) => {
+                    if !output.is_empty() {
+                        println!("{}", output);
+                    }
+                    prompt();
+                }
+                Action::Resume => (),
+                Action::Quit => return false,
+            }
+        }
+
+        let debugger = &mut self.debugger;
+        if let Err(error) = chip8.run_frame_until(|chip8| debugger.check(chip8)) {
+            debugger.pause(StopReason::Fault(error));
+        }
+        if let Some(&hit) = chip8.take_watch_hits().first() {
+            debugger.pause(StopReason::Watch(hit));
+        }
+
+        true
+    }
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
@@ -0,0 +1,69 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sdl2::keyboard::Keycode;
+
+use chip8::Keymap;
+
+/// `$XDG_CONFIG_HOME/chip8-rs/config.toml`, falling back to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let config = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("chip8-rs").join("config.toml"))
+}
+
+/// Reads the config file. A missing file gives the default layout.
+pub fn load(path: &Path) -> Result<Keymap, String> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Keymap::default()),
+        Err(error) => return Err(format!("Error reading {}: {}", path.display(), error)),
+    };
+    match Keymap::parse(&source) {
+        Ok(keymap) => Ok(keymap),
+        Err(error) => Err(format!("{}:{}", path.display(), error)),
+    }
+}
+
+pub fn save(path: &Path, keymap: &Keymap) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        if let Err(error) = fs::create_dir_all(dir) {
+            return Err(format!("Error creating {}: {}", dir.display(), error));
+        }
+    }
+    match fs::write(path, keymap.to_toml()) {
+        Ok(()) => Ok(()),
+        Err(error) => Err(format!("Error writing {}: {}", path.display(), error)),
+    }
+}
+
+/// The hex keys each host key presses.
+pub struct Bindings {
+    keys: Vec<(Keycode, usize)>,
+}
+
+impl Bindings {
+    pub fn new(names: &[Vec<String>; 16]) -> Result<Self, String> {
+        let mut keys = vec![];
+        for (hex, names) in names.iter().enumerate() {
+            for name in names {
+                match Keycode::from_name(name) {
+                    Some(kc) => keys.push((kc, hex)),
+                    None => return Err(format!("Unknown key '{}' for key {:X}", name, hex)),
+                }
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn hex_keys(&self, kc: Keycode) -> impl Iterator<Item = usize> + '_ {
+        self.keys
+            .iter()
+            .filter(move |&&(k, _)| k == kc)
+            .map(|&(_, hex)| hex)
+    }
+}
@@ -0,0 +1,7 @@
+//! Pieces of the SDL frontend that live outside of `main.rs`.
+
+pub mod audio;
+pub mod controller;
+pub mod debug;
+pub mod keymap;
+pub mod savestate;
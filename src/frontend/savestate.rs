@@ -0,0 +1,36 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chip8::Chip8;
+
+/// Slot files live next to the ROM, e.g. `roms/BLITZ.ss3`.
+pub fn slot_path(rom: &str, slot: u8) -> PathBuf {
+    let rom = Path::new(rom);
+    let mut name = rom.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".ss{}", slot));
+    rom.with_file_name(name)
+}
+
+pub fn save_slot(chip8: &Chip8, rom: &str, slot: u8) -> Result<(), String> {
+    let path = slot_path(rom, slot);
+    match fs::write(&path, chip8.save_state()) {
+        Ok(()) => Ok(()),
+        Err(error) => Err(format!("Error writing {}: {}", path.display(), error)),
+    }
+}
+
+pub fn load_slot(chip8: &mut Chip8, rom: &str, slot: u8) -> Result<(), String> {
+    let path = slot_path(rom, slot);
+    let state = match fs::read(&path) {
+        Ok(state) => state,
+        Err(error) => return Err(format!("Error reading {}: {}", path.display(), error)),
+    };
+
+    match chip8.load_state(&state) {
+        Ok(()) => {
+            chip8.draw_flag = true;
+            Ok(())
+        }
+        Err(error) => Err(format!("Error loading {}: {}", path.display(), error)),
+    }
+}
@@ -0,0 +1,373 @@
+//! Instruction decoding shared by the interpreter and the tools.
+//!
+//! [`decode`] accepts every extension's instructions regardless of platform.
+//! The interpreter checks [`Instruction::platform`] before running one.
+//! [`Instruction::encode`] turns an instruction back into its word.
+
+use crate::platform::Platform;
+
+/// One decoded instruction word. `x` and `y` are register numbers, `n` a
+/// 4-bit immediate, `nn` an 8-bit immediate and `nnn` a 12-bit address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00E0
+    Clear,
+    /// 00EE
+    Return,
+    /// 00CN (SCHIP)
+    ScrollDown { n: u8 },
+    /// 00DN (XO-CHIP)
+    ScrollUp { n: u8 },
+    /// 00FB (SCHIP)
+    ScrollRight,
+    /// 00FC (SCHIP)
+    ScrollLeft,
+    /// 00FD (SCHIP)
+    Exit,
+    /// 00FE (SCHIP)
+    Lores,
+    /// 00FF (SCHIP)
+    Hires,
+    /// 0NNN, a call to native code on the original machines.
+    System { nnn: u16 },
+    /// 1NNN
+    Jump { nnn: u16 },
+    /// 2NNN
+    Call { nnn: u16 },
+    /// 3XNN
+    SkipEqImm { x: usize, nn: u8 },
+    /// 4XNN
+    SkipNeImm { x: usize, nn: u8 },
+    /// 5XY0
+    SkipEq { x: usize, y: usize },
+    /// 5XY2 (XO-CHIP)
+    SaveRange { x: usize, y: usize },
+    /// 5XY3 (XO-CHIP)
+    LoadRange { x: usize, y: usize },
+    /// 6XNN
+    LoadImm { x: usize, nn: u8 },
+    /// 7XNN
+    AddImm { x: usize, nn: u8 },
+    /// 8XY0
+    Move { x: usize, y: usize },
+    /// 8XY1
+    Or { x: usize, y: usize },
+    /// 8XY2
+    And { x: usize, y: usize },
+    /// 8XY3
+    Xor { x: usize, y: usize },
+    /// 8XY4
+    Add { x: usize, y: usize },
+    /// 8XY5
+    Sub { x: usize, y: usize },
+    /// 8XY6
+    ShiftRight { x: usize, y: usize },
+    /// 8XY7
+    SubReverse { x: usize, y: usize },
+    /// 8XYE
+    ShiftLeft { x: usize, y: usize },
+    /// 9XY0
+    SkipNe { x: usize, y: usize },
+    /// ANNN
+    LoadIndex { nnn: u16 },
+    /// BNNN
+    JumpOffset { nnn: u16 },
+    /// CXNN
+    Random { x: usize, nn: u8 },
+    /// DXYN
+    Draw { x: usize, y: usize, n: u8 },
+    /// EX9E
+    SkipKey { x: usize },
+    /// EXA1
+    SkipNotKey { x: usize },
+    /// F000 NNNN (XO-CHIP). The address is the following word.
+    LongLoadIndex,
+    /// FN01 (XO-CHIP)
+    Plane { n: u8 },
+    /// F002 (XO-CHIP)
+    LoadAudio,
+    /// FX07
+    GetDelay { x: usize },
+    /// FX0A
+    WaitKey { x: usize },
+    /// FX15
+    SetDelay { x: usize },
+    /// FX18
+    SetSound { x: usize },
+    /// FX1E
+    AddIndex { x: usize },
+    /// FX29
+    Font { x: usize },
+    /// FX30 (SCHIP)
+    BigFont { x: usize },
+    /// FX33
+    Bcd { x: usize },
+    /// FX3A (XO-CHIP)
+    Pitch { x: usize },
+    /// FX55
+    Store { x: usize },
+    /// FX65
+    Load { x: usize },
+    /// FX75 (SCHIP)
+    SaveFlags { x: usize },
+    /// FX85 (SCHIP)
+    LoadFlags { x: usize },
+    /// A word that isn't an instruction on any platform.
+    Unknown(u16),
+}
+
+/// Decodes one instruction word.
+pub fn decode(word: u16) -> Instruction {
+    use Instruction::*;
+
+    let x = ((word & 0x0F00) >> 8) as usize;
+    let y = ((word & 0x00F0) >> 4) as usize;
+    let n = (word & 0x000F) as u8;
+    let nn = (word & 0x00FF) as u8;
+    let nnn = word & 0x0FFF;
+
+    match word & 0xF000 {
+        0x0000 => match word {
+            0x00E0 => Clear,
+            0x00EE => Return,
+            0x00FB => ScrollRight,
+            0x00FC => ScrollLeft,
+            0x00FD => Exit,
+            0x00FE => Lores,
+            0x00FF => Hires,
+            _ if word & 0xFFF0 == 0x00C0 => ScrollDown { n },
+            _ if word & 0xFFF0 == 0x00D0 => ScrollUp { n },
+            _ => System { nnn },
+        },
+        0x1000 => Jump { nnn },
+        0x2000 => Call { nnn },
+        0x3000 => SkipEqImm { x, nn },
+        0x4000 => SkipNeImm { x, nn },
+        0x5000 => match n {
+            0x0 => SkipEq { x, y },
+            0x2 => SaveRange { x, y },
+            0x3 => LoadRange { x, y },
+            _ => Unknown(word),
+        },
+        0x6000 => LoadImm { x, nn },
+        0x7000 => AddImm { x, nn },
+        0x8000 => match n {
+            0x0 => Move { x, y },
+            0x1 => Or { x, y },
+            0x2 => And { x, y },
+            0x3 => Xor { x, y },
+            0x4 => Add { x, y },
+            0x5 => Sub { x, y },
+            0x6 => ShiftRight { x, y },
+            0x7 => SubReverse { x, y },
+            0xE => ShiftLeft { x, y },
+            _ => Unknown(word),
+        },
+        0x9000 if n == 0 => SkipNe { x, y },
+        0xA000 => LoadIndex { nnn },
+        0xB000 => JumpOffset { nnn },
+        0xC000 => Random { x, nn },
+        0xD000 => Draw { x, y, n },
+        0xE000 => match nn {
+            0x9E => SkipKey { x },
+            0xA1 => SkipNotKey { x },
+            _ => Unknown(word),
+        },
+        0xF000 => match nn {
+            0x00 if x == 0 => LongLoadIndex,
+            0x01 => Plane { n: x as u8 },
+            0x02 if x == 0 => LoadAudio,
+            0x07 => GetDelay { x },
+            0x0A => WaitKey { x },
+            0x15 => SetDelay { x },
+            0x18 => SetSound { x },
+            0x1E => AddIndex { x },
+            0x29 => Font { x },
+            0x30 => BigFont { x },
+            0x33 => Bcd { x },
+            0x3A => Pitch { x },
+            0x55 => Store { x },
+            0x65 => Load { x },
+            0x75 => SaveFlags { x },
+            0x85 => LoadFlags { x },
+            _ => Unknown(word),
+        },
+        _ => Unknown(word),
+    }
+}
+
+impl Instruction {
+    /// The first platform that has this instruction.
+    pub fn platform(&self) -> Platform {
+        use Instruction::*;
+
+        match self {
+            ScrollDown { .. }
+            | ScrollRight
+            | ScrollLeft
+            | Exit
+            | Lores
+            | Hires
+            | BigFont { .. }
+            | SaveFlags { .. }
+            | LoadFlags { .. } => Platform::SuperChip,
+            ScrollUp { .. }
+            | SaveRange { .. }
+            | LoadRange { .. }
+            | LongLoadIndex
+            | Plane { .. }
+            | LoadAudio
+            | Pitch { .. } => Platform::XoChip,
+            _ => Platform::Chip8,
+        }
+    }
+
+    /// Size in bytes, including the address word that follows F000.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LongLoadIndex => 4,
+            _ => 2,
+        }
+    }
+
+    /// The instruction word, the inverse of `decode`. Fields are masked to
+    /// their width.
+    pub fn encode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |op: u16, x: usize, y: usize, n: u16| {
+            op | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | n
+        };
+        let xnn = |op: u16, x: usize, nn: u8| op | (x as u16 & 0xF) << 8 | nn as u16;
+        let fx = |x: usize, nn: u16| 0xF000 | (x as u16 & 0xF) << 8 | nn;
+
+        match *self {
+            Clear => 0x00E0,
+            Return => 0x00EE,
+            ScrollDown { n } => 0x00C0 | (n as u16 & 0xF),
+            ScrollUp { n } => 0x00D0 | (n as u16 & 0xF),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Lores => 0x00FE,
+            Hires => 0x00FF,
+            System { nnn } => nnn & 0xFFF,
+            Jump { nnn } => 0x1000 | (nnn & 0xFFF),
+            Call { nnn } => 0x2000 | (nnn & 0xFFF),
+            SkipEqImm { x, nn } => xnn(0x3000, x, nn),
+            SkipNeImm { x, nn } => xnn(0x4000, x, nn),
+            SkipEq { x, y } => xy(0x5000, x, y, 0x0),
+            SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            LoadImm { x, nn } => xnn(0x6000, x, nn),
+            AddImm { x, nn } => xnn(0x7000, x, nn),
+            Move { x, y } => xy(0x8000, x, y, 0x0),
+            Or { x, y } => xy(0x8000, x, y, 0x1),
+            And { x, y } => xy(0x8000, x, y, 0x2),
+            Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Add { x, y } => xy(0x8000, x, y, 0x4),
+            Sub { x, y } => xy(0x8000, x, y, 0x5),
+            ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            SubReverse { x, y } => xy(0x8000, x, y, 0x7),
+            ShiftLeft { x, y } => xy(0x8000, x, y, 0xE),
+            SkipNe { x, y } => xy(0x9000, x, y, 0x0),
+            LoadIndex { nnn } => 0xA000 | (nnn & 0xFFF),
+            JumpOffset { nnn } => 0xB000 | (nnn & 0xFFF),
+            Random { x, nn } => xnn(0xC000, x, nn),
+            Draw { x, y, n } => xy(0xD000, x, y, n as u16 & 0xF),
+            SkipKey { x } => xnn(0xE000, x, 0x9E),
+            SkipNotKey { x } => xnn(0xE000, x, 0xA1),
+            LongLoadIndex => 0xF000,
+            Plane { n } => fx(n as usize, 0x01),
+            LoadAudio => 0xF002,
+            GetDelay { x } => fx(x, 0x07),
+            WaitKey { x } => fx(x, 0x0A),
+            SetDelay { x } => fx(x, 0x15),
+            SetSound { x } => fx(x, 0x18),
+            AddIndex { x } => fx(x, 0x1E),
+            Font { x } => fx(x, 0x29),
+            BigFont { x } => fx(x, 0x30),
+            Bcd { x } => fx(x, 0x33),
+            Pitch { x } => fx(x, 0x3A),
+            Store { x } => fx(x, 0x55),
+            Load { x } => fx(x, 0x65),
+            SaveFlags { x } => fx(x, 0x75),
+            LoadFlags { x } => fx(x, 0x85),
+            Unknown(word) => word,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_inverts_decode_for_every_word() {
+        for word in 0..=0xFFFF {
+            assert_eq!(decode(word).encode(), word, "{:04X}", word);
+        }
+    }
+
+    #[test]
+    fn decode_inverts_encode_for_every_instruction() {
+        for word in 0..=0xFFFF {
+            let instruction = decode(word);
+            assert_eq!(decode(instruction.encode()), instruction);
+        }
+    }
+
+    #[test]
+    fn decodes_operand_fields() {
+        assert_eq!(decode(0x7A05), Instruction::AddImm { x: 0xA, nn: 0x05 });
+        assert_eq!(decode(0xD12F), Instruction::Draw { x: 1, y: 2, n: 0xF });
+        assert_eq!(decode(0x8CE4), Instruction::Add { x: 0xC, y: 0xE });
+        assert_eq!(decode(0xB345), Instruction::JumpOffset { nnn: 0x345 });
+        assert_eq!(decode(0xF365), Instruction::Load { x: 3 });
+        assert_eq!(decode(0xF201), Instruction::Plane { n: 2 });
+        assert_eq!(decode(0x00C7), Instruction::ScrollDown { n: 7 });
+        assert_eq!(decode(0x0123), Instruction::System { nnn: 0x123 });
+    }
+
+    #[test]
+    fn unused_words_are_unknown() {
+        for &word in [
+            0x5001, 0x5004, 0x8008, 0x800F, 0x9001, 0xE09F, 0xF100, 0xF102, 0xF0FF,
+        ]
+        .iter()
+        {
+            assert_eq!(decode(word), Instruction::Unknown(word), "{:04X}", word);
+        }
+    }
+
+    #[test]
+    fn every_known_nibble_group_decodes() {
+        // Only the groups with sub-opcodes have unused words
+        for word in 0..=0xFFFF {
+            let unknown = decode(word) == Instruction::Unknown(word);
+            match word >> 12 {
+                0x5 | 0x8 | 0x9 | 0xE | 0xF => (),
+                _ => assert!(!unknown, "{:04X}", word),
+            }
+        }
+    }
+
+    #[test]
+    fn extensions_report_their_platform() {
+        assert_eq!(decode(0x00E0).platform(), Platform::Chip8);
+        assert_eq!(decode(0xD120).platform(), Platform::Chip8);
+        assert_eq!(decode(0x00FF).platform(), Platform::SuperChip);
+        assert_eq!(decode(0xF130).platform(), Platform::SuperChip);
+        assert_eq!(decode(0xF285).platform(), Platform::SuperChip);
+        assert_eq!(decode(0x00D1).platform(), Platform::XoChip);
+        assert_eq!(decode(0x5122).platform(), Platform::XoChip);
+        assert_eq!(decode(0xF000).platform(), Platform::XoChip);
+        assert_eq!(decode(0xF03A).platform(), Platform::XoChip);
+    }
+
+    #[test]
+    fn long_load_is_four_bytes() {
+        assert_eq!(decode(0xF000).size(), 4);
+        assert_eq!(decode(0xA000).size(), 2);
+    }
+}
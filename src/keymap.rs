@@ -0,0 +1,735 @@
+//! Keyboard and game controller layouts for the hex keypad, read from a
+//! small TOML file that also holds the display palette.
+//!
+//! Host keys are named the way SDL names them (`X`, `1`, `Up`, `Keypad 8`),
+//! matched without regard to case. A `[keys]` table binds each hex key to
+//! one or more host keys, and `[rom."NAME"]` tables override some of them
+//! for the ROM with that file name. Controller buttons use SDL's mapping
+//! names (`a`, `dpup`, `leftshoulder`) and are bound the other way around,
+//! each to one hex key, in `[buttons]` and `[rom."NAME".buttons]`:
+//!
+//! ```toml
+//! [keys]
+//! 4 = "Q"
+//! 6 = ["E", "Right"]
+//!
+//! [buttons]
+//! a = 6
+//!
+//! [rom."BRIX"]
+//! 4 = ["Left", "A"]
+//!
+//! [rom."BRIX".buttons]
+//! dpleft = 4
+//!
+//! [display]
+//! palette = "amber"
+//! ```
+//!
+//! Hex keys that aren't listed keep the QWERTY layout in `DEFAULT_KEYS`,
+//! and buttons keep `DEFAULT_BUTTONS`, with layouts for a few games whose
+//! controls don't fit it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::palette::Palette;
+
+/// The host key for each hex key, laid out like the COSMAC VIP keypad on
+/// the left of a QWERTY keyboard.
+pub const DEFAULT_KEYS: [&str; 16] = [
+    "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+];
+
+/// Controller buttons, by SDL mapping name.
+pub const BUTTONS: [&str; 15] = [
+    "a",
+    "b",
+    "x",
+    "y",
+    "back",
+    "guide",
+    "start",
+    "leftstick",
+    "rightstick",
+    "leftshoulder",
+    "rightshoulder",
+    "dpup",
+    "dpdown",
+    "dpleft",
+    "dpright",
+];
+
+/// The d-pad on 5 7 8 9 and the face buttons on 6 and 4, the same keys as
+/// W A S D, E and Q.
+pub const DEFAULT_BUTTONS: [(&str, usize); 6] = [
+    ("dpup", 0x5),
+    ("dpleft", 0x7),
+    ("dpdown", 0x8),
+    ("dpright", 0x9),
+    ("a", 0x6),
+    ("b", 0x4),
+];
+
+// Games in roms/ whose controls aren't on the default buttons.
+const ROM_BUTTONS: [(&str, &[(&str, usize)]); 3] = [
+    // The left paddle
+    ("PONG", &[("dpup", 0x1), ("dpdown", 0x4)]),
+    (
+        "TETRIS",
+        &[
+            ("dpleft", 0x5),
+            ("dpright", 0x6),
+            ("dpup", 0x4),
+            ("a", 0x4),
+            ("dpdown", 0x7),
+        ],
+    ),
+    ("INVADERS", &[("dpleft", 0x4), ("dpright", 0x6), ("a", 0x5)]),
+];
+
+/// A syntax error in a keymap file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapError {
+    /// 1-based line number.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for KeymapError {}
+
+// The table a binding belongs to.
+enum Table {
+    Keys,
+    Buttons,
+    RomKeys(String),
+    RomButtons(String),
+    Display,
+}
+
+/// Host key names for every hex key, and the hex key for every controller
+/// button, globally and per ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<String>; 16],
+    buttons: BTreeMap<String, usize>,
+    roms: BTreeMap<String, BTreeMap<usize, Vec<String>>>,
+    rom_buttons: BTreeMap<String, BTreeMap<String, usize>>,
+    /// The palette from `[display]`, if one is set.
+    pub palette: Option<Palette>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keys: [Vec<String>; 16] = Default::default();
+        for (names, default) in keys.iter_mut().zip(DEFAULT_KEYS.iter()) {
+            names.push(default.to_string());
+        }
+        let buttons = |layout: &[(&str, usize)]| {
+            layout
+                .iter()
+                .map(|&(button, key)| (button.to_string(), key))
+                .collect()
+        };
+        Self {
+            keys,
+            buttons: buttons(&DEFAULT_BUTTONS),
+            roms: BTreeMap::new(),
+            rom_buttons: ROM_BUTTONS
+                .iter()
+                .map(|&(rom, layout)| (rom.to_string(), buttons(layout)))
+                .collect(),
+            palette: None,
+        }
+    }
+}
+
+impl Keymap {
+    pub fn parse(source: &str) -> Result<Self, KeymapError> {
+        let mut keymap = Keymap::default();
+        // None until the first table header
+        let mut table = None;
+
+        for (n, line) in source.lines().enumerate() {
+            let error = |message: &str| KeymapError {
+                line: n + 1,
+                message: message.to_string(),
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .strip_suffix(']')
+                    .ok_or_else(|| error("expected ']'"))?
+                    .trim();
+                table = Some(match header {
+                    "keys" => Table::Keys,
+                    "buttons" => Table::Buttons,
+                    "display" => Table::Display,
+                    _ => {
+                        let rom = header
+                            .strip_prefix("rom.")
+                            .ok_or_else(|| error(&format!("unknown table [{}]", header)))?
+                            .trim();
+                        match rom.strip_suffix(".buttons").and_then(unquote) {
+                            Some(rom) => Table::RomButtons(rom),
+                            None => Table::RomKeys(
+                                unquote(rom).ok_or_else(|| error("invalid ROM name"))?,
+                            ),
+                        }
+                    }
+                });
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected NAME = VALUE"))?;
+            let (name, value) = (name.trim(), value.trim());
+
+            match &table {
+                Some(Table::Keys) | Some(Table::RomKeys(_)) => {
+                    let key = unquote(name)
+                        .and_then(|key| parse_key(&key))
+                        .ok_or_else(|| error(&format!("'{}' is not a hex key", name)))?;
+                    let names = parse_names(value).ok_or_else(|| error("invalid key names"))?;
+                    match &table {
+                        Some(Table::RomKeys(rom)) => keymap.bind(Some(rom), key, names),
+                        _ => keymap.bind(None, key, names),
+                    }
+                }
+                Some(Table::Buttons) | Some(Table::RomButtons(_)) => {
+                    let button = unquote(name)
+                        .map(|button| button.to_ascii_lowercase())
+                        .filter(|button| BUTTONS.contains(&button.as_str()))
+                        .ok_or_else(|| error(&format!("unknown button '{}'", name)))?;
+                    let key = unquote(value)
+                        .and_then(|key| parse_key(&key))
+                        .ok_or_else(|| error(&format!("'{}' is not a hex key", value)))?;
+                    match &table {
+                        Some(Table::RomButtons(rom)) => keymap.bind_button(Some(rom), &button, key),
+                        _ => keymap.bind_button(None, &button, key),
+                    }
+                }
+                Some(Table::Display) if name == "palette" => {
+                    let palette = unquote(value).filter(|_| value.starts_with('"'));
+                    let palette = palette.ok_or_else(|| error("expected a quoted palette"))?;
+                    keymap.palette = Some(palette.parse().map_err(|e| error(&format!("{}", e)))?);
+                }
+                Some(Table::Display) => {
+                    return Err(error(&format!("unknown display setting '{}'", name)))
+                }
+                None => return Err(error("binding outside of a table")),
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    /// The host keys for each hex key while `rom` is running, or for ROMs
+    /// without their own bindings if it's None. `rom` is the ROM's file name.
+    pub fn bindings(&self, rom: Option<&str>) -> [Vec<String>; 16] {
+        let mut keys = self.keys.clone();
+        if let Some(overrides) = rom.and_then(|rom| self.roms.get(rom)) {
+            for (&key, names) in overrides.iter() {
+                keys[key] = names.clone();
+            }
+        }
+        keys
+    }
+
+    /// The hex key for each bound controller button while `rom` is running.
+    pub fn buttons(&self, rom: Option<&str>) -> BTreeMap<String, usize> {
+        let mut buttons = self.buttons.clone();
+        if let Some(overrides) = rom.and_then(|rom| self.rom_buttons.get(rom)) {
+            buttons.extend(overrides.iter().map(|(button, &key)| (button.clone(), key)));
+        }
+        buttons
+    }
+
+    /// Replaces the host keys for a hex key, for one ROM or for all of them.
+    pub fn bind(&mut self, rom: Option<&str>, key: usize, names: Vec<String>) {
+        match rom {
+            Some(rom) => {
+                self.roms
+                    .entry(rom.to_string())
+                    .or_default()
+                    .insert(key, names);
+            }
+            None => self.keys[key] = names,
+        }
+    }
+
+    /// Binds a controller button to a hex key, for one ROM or for all of
+    /// them.
+    pub fn bind_button(&mut self, rom: Option<&str>, button: &str, key: usize) {
+        let buttons = match rom {
+            Some(rom) => self.rom_buttons.entry(rom.to_string()).or_default(),
+            None => &mut self.buttons,
+        };
+        buttons.insert(button.to_ascii_lowercase(), key);
+    }
+
+    /// Writes the keymap back out in the format `parse` reads.
+    pub fn to_toml(&self) -> String {
+        let mut toml = String::from("[keys]\n");
+        for (key, names) in self.keys.iter().enumerate() {
+            toml += &format!("{:X} = {}\n", key, format_names(names));
+        }
+        toml += "\n[buttons]\n";
+        for (button, key) in self.buttons.iter() {
+            toml += &format!("{} = \"{:X}\"\n", button, key);
+        }
+        for (rom, overrides) in self.roms.iter() {
+            toml += &format!("\n[rom.{}]\n", quote(rom));
+            for (key, names) in overrides.iter() {
+                toml += &format!("{:X} = {}\n", key, format_names(names));
+            }
+        }
+        for (rom, overrides) in self.rom_buttons.iter() {
+            toml += &format!("\n[rom.{}.buttons]\n", quote(rom));
+            for (button, key) in overrides.iter() {
+                toml += &format!("{} = \"{:X}\"\n", button, key);
+            }
+        }
+        if let Some(palette) = &self.palette {
+            toml += &format!("\n[display]\npalette = {}\n", quote(&palette.name));
+        }
+        toml
+    }
+}
+
+/// Tracks the buttons held on every connected controller, so that a hex
+/// key stays down while any button bound to it is held.
+#[derive(Debug, Clone, Default)]
+pub struct Gamepads {
+    buttons: BTreeMap<String, usize>,
+    // Controller and button
+    held: Vec<(u32, String)>,
+}
+
+impl Gamepads {
+    /// Uses the layout from `Keymap::buttons`.
+    pub fn new(buttons: BTreeMap<String, usize>) -> Self {
+        Self {
+            buttons,
+            held: vec![],
+        }
+    }
+
+    pub fn set_buttons(&mut self, buttons: BTreeMap<String, usize>) {
+        self.buttons = buttons;
+    }
+
+    /// Returns the hex key the button presses, if it's bound.
+    pub fn press(&mut self, controller: u32, button: &str) -> Option<usize> {
+        let button = button.to_ascii_lowercase();
+        let key = self.buttons.get(&button).copied();
+        if !self.held.contains(&(controller, button.clone())) {
+            self.held.push((controller, button));
+        }
+        key
+    }
+
+    /// Returns the hex key to release, unless another button still holds
+    /// it down.
+    pub fn release(&mut self, controller: u32, button: &str) -> Option<usize> {
+        let button = button.to_ascii_lowercase();
+        self.held
+            .retain(|held| held != &(controller, button.clone()));
+        let key = self.buttons.get(&button).copied()?;
+        let still_held = self
+            .held
+            .iter()
+            .any(|(_, held)| self.buttons.get(held) == Some(&key));
+        if still_held {
+            None
+        } else {
+            Some(key)
+        }
+    }
+
+    /// Lets go of every button on a controller that was unplugged, and
+    /// returns the hex keys to release.
+    pub fn disconnect(&mut self, controller: u32) -> Vec<usize> {
+        let buttons: Vec<String> = self
+            .held
+            .iter()
+            .filter(|(c, _)| *c == controller)
+            .map(|(_, button)| button.clone())
+            .collect();
+
+        let mut keys = vec![];
+        for button in buttons {
+            if let Some(key) = self.release(controller, &button) {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+        keys
+    }
+}
+
+/// What a key press did to a rebinding in progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Next,
+    Done,
+    Cancelled,
+}
+
+/// Asks for a new host key or controller button for each hex key in turn,
+/// from 0 to F.
+///
+/// Return keeps a key's current binding and Escape abandons the rebinding.
+/// Each hex key gets a single host key here; bind more than one in the
+/// config file.
+#[derive(Debug, Clone)]
+pub struct Rebinder {
+    rom: Option<String>,
+    key: usize,
+    names: [Vec<String>; 16],
+    changed: [bool; 16],
+    buttons: Vec<(String, usize)>,
+}
+
+impl Rebinder {
+    /// Rebinds the keys for `rom` only, or for every ROM if it's None.
+    /// `current` is the layout from `Keymap::bindings`.
+    pub fn new(rom: Option<String>, current: [Vec<String>; 16]) -> Self {
+        Self {
+            rom,
+            key: 0,
+            names: current,
+            changed: [false; 16],
+            buttons: vec![],
+        }
+    }
+
+    /// The hex key waiting for a binding.
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    pub fn prompt(&self) -> String {
+        let scope = match &self.rom {
+            Some(rom) => format!(" for {}", rom),
+            None => String::new(),
+        };
+        format!(
+            "Press a key or button for {:X}{} (Return keeps {}, Escape cancels)",
+            self.key,
+            scope,
+            self.names[self.key].join(" / ")
+        )
+    }
+
+    /// Binds the host key `name` to the hex key. A host key can only press
+    /// one hex key, so if another hex key had it, that one takes over this
+    /// key's old binding instead.
+    pub fn press(&mut self, name: &str) -> Step {
+        if name.eq_ignore_ascii_case("Escape") {
+            return Step::Cancelled;
+        }
+        if !name.eq_ignore_ascii_case("Return") {
+            let old = std::mem::replace(&mut self.names[self.key], vec![name.to_string()]);
+            self.changed[self.key] = true;
+
+            let other = (0..self.names.len()).find(|&key| {
+                key != self.key && self.names[key].iter().any(|n| n.eq_ignore_ascii_case(name))
+            });
+            if let Some(other) = other {
+                let names = &mut self.names[other];
+                names.retain(|n| !n.eq_ignore_ascii_case(name));
+                for n in old {
+                    if !n.eq_ignore_ascii_case(name) && !names.contains(&n) {
+                        names.push(n);
+                    }
+                }
+                self.changed[other] = true;
+            }
+        }
+        self.advance()
+    }
+
+    /// Binds a controller button, by SDL mapping name, to the hex key.
+    pub fn press_button(&mut self, button: &str) -> Step {
+        if self.buttons.iter().any(|(b, _)| b == button) {
+            return Step::Next;
+        }
+        self.buttons.push((button.to_string(), self.key));
+        self.advance()
+    }
+
+    fn advance(&mut self) -> Step {
+        self.key += 1;
+        if self.key == self.names.len() {
+            Step::Done
+        } else {
+            Step::Next
+        }
+    }
+
+    /// Stores the keys and buttons that were changed in `keymap`.
+    pub fn finish(&self, keymap: &mut Keymap) {
+        for (key, names) in self.names.iter().enumerate() {
+            if self.changed[key] {
+                keymap.bind(self.rom.as_deref(), key, names.clone());
+            }
+        }
+        for (button, key) in self.buttons.iter() {
+            keymap.bind_button(self.rom.as_deref(), button, *key);
+        }
+    }
+}
+
+// A hex digit, with or without `0x`.
+fn parse_key(s: &str) -> Option<usize> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    usize::from_str_radix(digits, 16)
+        .ok()
+        .filter(|&key| key < 16)
+}
+
+// Drops a `#` comment, unless it's inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+// A bare word or a basic string with `\"` and `\\` escapes.
+fn unquote(s: &str) -> Option<String> {
+    let inner = match s.strip_prefix('"') {
+        Some(rest) => rest.strip_suffix('"')?,
+        None if !s.is_empty() && !s.contains(|c: char| c.is_whitespace() || c == '"') => {
+            return Some(s.to_string())
+        }
+        None => return None,
+    };
+
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                c @ ('"' | '\\') => out.push(c),
+                _ => return None,
+            },
+            '"' => return None,
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+// `"NAME"` or `["NAME", ...]`.
+fn parse_names(value: &str) -> Option<Vec<String>> {
+    if !value.starts_with('"') {
+        let list = value.strip_prefix('[')?.strip_suffix(']')?;
+        return split_list(list)?
+            .iter()
+            .map(|name| unquote(name).filter(|_| name.starts_with('"')))
+            .collect();
+    }
+    Some(vec![unquote(value)?])
+}
+
+// Splits on commas outside of strings, allowing a trailing comma.
+fn split_list(list: &str) -> Option<Vec<&str>> {
+    let mut items = vec![];
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in list.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if in_string {
+        return None;
+    }
+    let last = list[start..].trim();
+    if !last.is_empty() {
+        items.push(last);
+    }
+    if items.iter().any(|item| item.is_empty()) {
+        return None;
+    }
+    Some(items)
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn format_names(names: &[String]) -> String {
+    match names {
+        [name] => quote(name),
+        _ => format!(
+            "[{}]",
+            names
+                .iter()
+                .map(|n| quote(n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tables_and_lists() {
+        let keymap = Keymap::parse(
+            "# AZERTY\n\
+             [keys]\n\
+             4 = \"A\"   # was Q\n\
+             5 = [\"Z\", \"Up\"]\n\
+             \n\
+             [rom.\"BRIX\"]\n\
+             4 = [\"Left\"]\n\
+             a = \"Keypad 0\"\n",
+        )
+        .unwrap();
+
+        let keys = keymap.bindings(Some("PONG"));
+        assert_eq!(keys[4], ["A"]);
+        assert_eq!(keys[5], ["Z", "Up"]);
+        assert_eq!(keys[6], ["E"]);
+
+        let keys = keymap.bindings(Some("BRIX"));
+        assert_eq!(keys[4], ["Left"]);
+        assert_eq!(keys[5], ["Z", "Up"]);
+        assert_eq!(keys[0xA], ["Keypad 0"]);
+    }
+
+    #[test]
+    fn parses_buttons() {
+        let keymap = Keymap::parse(
+            "[buttons]\n\
+             A = 5\n\
+             start = \"C\"\n\
+             \n\
+             [rom.\"PONG\".buttons]\n\
+             dpdown = 0xD\n",
+        )
+        .unwrap();
+        let buttons = keymap.buttons(None);
+        assert_eq!(buttons["a"], 0x5);
+        assert_eq!(buttons["start"], 0xC);
+        assert_eq!(buttons["dpup"], 0x5);
+
+        let buttons = keymap.buttons(Some("PONG"));
+        assert_eq!(buttons["dpup"], 0x1);
+        assert_eq!(buttons["dpdown"], 0xD);
+        assert!(Keymap::parse("[buttons]\ntrigger = 1").is_err());
+    }
+
+    #[test]
+    fn gamepads_hold_keys_until_the_last_button_is_released() {
+        let mut pads = Gamepads::new(Keymap::default().buttons(Some("TETRIS")));
+        assert_eq!(pads.press(0, "dpup"), Some(0x4));
+        assert_eq!(pads.press(1, "a"), Some(0x4));
+        assert_eq!(pads.press(1, "guide"), None);
+        assert_eq!(pads.release(0, "dpup"), None);
+
+        // Unplugging the second controller lets go of its buttons
+        assert_eq!(pads.press(1, "dpleft"), Some(0x5));
+        let mut released = pads.disconnect(1);
+        released.sort();
+        assert_eq!(released, [0x4, 0x5]);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut keymap = Keymap::default();
+        keymap.bind(None, 0, vec!["Space".to_string(), "X".to_string()]);
+        keymap.bind(Some("say \"hi\".ch8"), 0xF, vec!["#".to_string()]);
+        keymap.bind_button(Some("BRIX"), "dpleft", 0x4);
+        keymap.palette = Some("#102030,FFFFFF".parse().unwrap());
+        assert_eq!(Keymap::parse(&keymap.to_toml()).unwrap(), keymap);
+    }
+
+    #[test]
+    fn reports_errors_by_line() {
+        let error = Keymap::parse("[keys]\n1 = \"Q\"\nG = \"W\"\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert!(Keymap::parse("1 = \"Q\"").is_err());
+        assert!(Keymap::parse("[keys]\n1 = [\"Q\" \"W\"]").is_err());
+        assert!(Keymap::parse("[mouse]").is_err());
+        assert!(Keymap::parse("[display]\npalette = \"sepia\"").is_err());
+    }
+
+    #[test]
+    fn rebinding_a_taken_key_swaps_it() {
+        let mut keymap = Keymap::default();
+        let mut rebinder = Rebinder::new(None, keymap.bindings(None));
+        // W belongs to 5, which takes over X
+        assert_eq!(rebinder.press("W"), Step::Next);
+        for _ in 1..5 {
+            assert_eq!(rebinder.press("Return"), Step::Next);
+        }
+        assert!(
+            rebinder.prompt().contains("keeps X"),
+            "{}",
+            rebinder.prompt()
+        );
+        assert_eq!(rebinder.press("Return"), Step::Next);
+        // Taking W back from an earlier key, in another case, gives it E
+        assert_eq!(rebinder.press("w"), Step::Next);
+        for key in 7..16 {
+            let step = rebinder.press("Return");
+            assert_eq!(step, if key == 15 { Step::Done } else { Step::Next });
+        }
+
+        rebinder.finish(&mut keymap);
+        let bindings = keymap.bindings(None);
+        assert_eq!(bindings[0], ["E"]);
+        assert_eq!(bindings[5], ["X"]);
+        assert_eq!(bindings[6], ["w"]);
+        let mut names: Vec<String> = bindings.concat();
+        names.iter_mut().for_each(|n| n.make_ascii_uppercase());
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 16);
+    }
+
+    #[test]
+    fn rebinding_can_be_cancelled() {
+        let keymap = Keymap::default();
+        let mut rebinder = Rebinder::new(Some("PONG".to_string()), keymap.bindings(None));
+        assert!(rebinder.prompt().contains(" for PONG "));
+        assert_eq!(rebinder.press("Up"), Step::Next);
+        assert_eq!(rebinder.key(), 1);
+        assert_eq!(rebinder.press("Escape"), Step::Cancelled);
+    }
+}
@@ -0,0 +1,47 @@
+//! A CHIP-8 interpreter core with no frontend dependencies.
+//!
+//! The [`Chip8`] machine owns memory, registers, timers, the keypad and the
+//! display buffer. Frontends drive it by calling [`Chip8::run_frame`] 60 times
+//! a second, writing to [`Chip8::keypad`] and reading [`Chip8::gfx`] whenever
+//! [`Chip8::draw_flag`] is set.
+
+pub mod address;
+pub mod asm;
+pub mod audio;
+pub mod chip8;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod framebuffer;
+pub mod instruction;
+pub mod keymap;
+pub mod movie;
+pub mod octo;
+pub mod palette;
+pub mod platform;
+pub mod png;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod trace;
+pub mod watch;
+
+pub use crate::asm::{assemble, assemble_file, AsmError};
+pub use crate::audio::{pattern_rate, AudioOutput, NullAudio};
+pub use crate::chip8::{Chip8, DEFAULT_CYCLES_PER_FRAME, DEFAULT_LOAD_ADDRESS, DEFAULT_PITCH};
+pub use crate::debugger::Debugger;
+pub use crate::disasm::{disassemble, Syntax};
+pub use crate::error::{ExecError, ExecErrorKind, LoadError, MovieError, StateError};
+pub use crate::framebuffer::Framebuffer;
+pub use crate::instruction::{decode, Instruction};
+pub use crate::keymap::{Gamepads, Keymap};
+pub use crate::movie::Movie;
+pub use crate::octo::OctoError;
+pub use crate::palette::{Palette, DEFAULT_PALETTE};
+pub use crate::platform::Platform;
+pub use crate::quirks::{IndexIncrement, Quirks};
+pub use crate::rewind::Rewind;
+pub use crate::rng::{RandomSource, VipRandom, XorShift};
+pub use crate::trace::{TraceFormat, Tracer};
+pub use crate::watch::{WatchHit, Watchpoint};
@@ -1,55 +1,295 @@
-extern crate rand;
 extern crate sdl2;
 use sdl2::{
-    event::Event, keyboard::Keycode, pixels::Color, rect::Rect, render::WindowCanvas, EventPump,
-    IntegerOrSdlError::*, Sdl, VideoSubsystem,
+    event::Event,
+    keyboard::{Keycode, Mod},
+    pixels::Color,
+    rect::Rect,
+    render::WindowCanvas,
+    IntegerOrSdlError::*,
 };
 
 use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-mod chip8;
+use chip8::keymap::{Rebinder, Step};
+use chip8::trace::{self, DEFAULT_TRACE_SIZE};
+use chip8::{
+    AudioOutput, Chip8, Gamepads, Movie, NullAudio, Palette, Platform, Quirks, Rewind, TraceFormat,
+    Tracer, VipRandom,
+};
+
+mod frontend;
+
+use frontend::audio::SdlBeeper;
+use frontend::controller::Controllers;
+use frontend::debug::DebugConsole;
+use frontend::keymap::Bindings;
+use frontend::savestate;
 
+const TITLE: &str = "Alice's Chip-8 emulator";
 const WIDTH: u32 = 1024;
 const HEIGHT: u32 = 512;
 
-const KEYMAP: [Keycode; 16] = [
-    Keycode::X,
-    Keycode::Num1,
-    Keycode::Num2,
-    Keycode::Num3,
-    Keycode::Q,
-    Keycode::W,
-    Keycode::E,
-    Keycode::A,
-    Keycode::S,
-    Keycode::D,
-    Keycode::Z,
-    Keycode::C,
-    Keycode::Num4,
-    Keycode::R,
-    Keycode::F,
-    Keycode::V,
-];
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// How long a status message stays in the title bar
+const STATUS_DURATION: Duration = Duration::from_secs(3);
+
+const DEFAULT_REWIND_SECONDS: usize = 10;
+const REWIND_KEY: Keycode = Keycode::Backspace;
+
+// F10 rebinds the keypad, Shift+F10 just for the running ROM
+const REBIND_KEY: Keycode = Keycode::F10;
+const PALETTE_KEY: Keycode = Keycode::F11;
+
+const USAGE: &str =
+    "usage: chip8-rs [--ipf INSTRUCTIONS_PER_FRAME] [--platform chip8|schip|xochip] \
+                     [--quirks vip|chip48|schip|xochip] [--seed N] [--vip-rng] \
+                     [--rewind-seconds N] [--tone HZ] [--mute] [--debug] \
+                     [--trace FILE] [--trace-format text|jsonl] [--trace-range START-END] \
+                     [--trace-only MNEMONIC,...] [--trace-size N] [--record FILE] \
+                     [--replay FILE] [--config FILE] [--palette NAME|RRGGBB,...] \
+                     ROM|SOURCE.8o";
+
+struct Options {
+    rom: String,
+    cycles_per_frame: u32,
+    platform: Option<Platform>,
+    quirks: Option<Quirks>,
+    seed: u64,
+    vip_rng: bool,
+    rewind_seconds: usize,
+    tone: f32,
+    mute: bool,
+    debug: bool,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    trace_range: Option<(usize, usize)>,
+    trace_only: Vec<String>,
+    trace_size: usize,
+    record: Option<String>,
+    replay: Option<String>,
+    config: Option<PathBuf>,
+    palette: Option<Palette>,
+}
+
+fn parse_value<T>(flag: &str, value: Option<String>) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let value = value.ok_or(USAGE)?;
+    match value.parse() {
+        Ok(v) => Ok(v),
+        Err(error) => Err(format!("invalid {} value '{}': {}", flag, value, error)),
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut rom = None;
+    let mut cycles_per_frame = chip8::DEFAULT_CYCLES_PER_FRAME;
+    let mut platform = None;
+    let mut quirks = None;
+    let mut seed = None;
+    let mut vip_rng = false;
+    let mut rewind_seconds = DEFAULT_REWIND_SECONDS;
+    let mut tone = frontend::audio::DEFAULT_TONE;
+    let mut mute = false;
+    let mut debug = false;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::default();
+    let mut trace_range = None;
+    let mut trace_only = vec![];
+    let mut trace_size = DEFAULT_TRACE_SIZE;
+    let mut record = None;
+    let mut replay = None;
+    let mut config = None;
+    let mut palette = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ipf" => cycles_per_frame = parse_value(&arg, args.next())?,
+            "--platform" => platform = Some(parse_value(&arg, args.next())?),
+            "--quirks" => quirks = Some(parse_value(&arg, args.next())?),
+            "--seed" => seed = Some(parse_value(&arg, args.next())?),
+            "--vip-rng" => vip_rng = true,
+            "--rewind-seconds" => rewind_seconds = parse_value(&arg, args.next())?,
+            "--tone" => tone = parse_value(&arg, args.next())?,
+            "--mute" => mute = true,
+            "--debug" => debug = true,
+            "--trace" => trace = Some(args.next().ok_or(USAGE)?),
+            "--trace-format" => trace_format = parse_value(&arg, args.next())?,
+            "--trace-range" => {
+                let value = args.next().ok_or(USAGE)?;
+                let range = trace::parse_range(&value)
+                    .ok_or_else(|| format!("invalid --trace-range value '{}'", value))?;
+                trace_range = Some(range);
+            }
+            "--trace-only" => {
+                let value = args.next().ok_or(USAGE)?;
+                trace_only = value.split(',').map(|m| m.trim().to_string()).collect();
+            }
+            "--trace-size" => trace_size = parse_value(&arg, args.next())?,
+            "--record" => record = Some(args.next().ok_or(USAGE)?),
+            "--replay" => replay = Some(args.next().ok_or(USAGE)?),
+            "--config" => config = Some(PathBuf::from(args.next().ok_or(USAGE)?)),
+            "--palette" => palette = Some(parse_value(&arg, args.next())?),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    // The debug console runs partial frames, which a movie can't describe
+    if (record.is_some() || replay.is_some()) && debug {
+        return Err("--record and --replay can't be combined with --debug".to_string());
+    }
+    if record.is_some() && replay.is_some() {
+        return Err("--record and --replay can't be combined".to_string());
+    }
+    if replay.is_some() && (platform.is_some() || quirks.is_some() || seed.is_some() || vip_rng) {
+        return Err(
+            "--replay can't be combined with --platform, --quirks, --seed or --vip-rng".to_string(),
+        );
+    }
+
+    Ok(Options {
+        rom: rom.ok_or(USAGE)?,
+        cycles_per_frame,
+        platform,
+        quirks,
+        seed: seed.unwrap_or_else(clock_seed),
+        vip_rng,
+        rewind_seconds,
+        tone,
+        mute,
+        debug,
+        trace,
+        trace_format,
+        trace_range,
+        trace_only,
+        trace_size,
+        record,
+        replay,
+        config: config.or_else(frontend::keymap::default_path),
+        palette,
+    })
+}
+
+// Without --seed every run gets different random numbers, like the real thing.
+fn clock_seed() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_nanos() as u64,
+        Err(_) => chip8::rng::DEFAULT_SEED,
+    }
+}
 
 fn main() -> Result<(), String> {
+    let options = parse_args()?;
+
     let context = sdl2::init()?;
     let video = context.video()?;
-    let window = match {
-        let mut window_builder = video.window("Alice's Chip-8 emulator", WIDTH, HEIGHT);
-
-        window_builder.position_centered().build()
-    } {
+    let mut window_builder = video.window(TITLE, WIDTH, HEIGHT);
+    let window = match window_builder.position_centered().build() {
         Ok(window) => window,
         Err(error) => return Err(format!("Error building window: {}", error)),
     };
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("expected two arguments!");
+    let rom_name = match Path::new(&options.rom).file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => options.rom.clone(),
+    };
+    let mut keymap = match &options.config {
+        Some(path) => frontend::keymap::load(path)?,
+        None => chip8::Keymap::default(),
+    };
+    let mut bindings = Bindings::new(&keymap.bindings(Some(&rom_name)))?;
+    let mut gamepads = Gamepads::new(keymap.buttons(Some(&rom_name)));
+    let mut rebinding: Option<Rebinder> = None;
+
+    // The palette from the command line or config starts the F11 cycle
+    // through the presets
+    let mut palettes: Vec<Palette> = Palette::presets().collect();
+    let selected = match (&options.palette, &keymap.palette) {
+        (Some(palette), _) | (None, Some(palette)) => palette.clone(),
+        (None, None) => Palette::default(),
+    };
+    let mut palette = match palettes.iter().position(|p| *p == selected) {
+        Some(i) => i,
+        None => {
+            palettes.insert(0, selected);
+            0
+        }
+    };
+    let mut controllers = Controllers::new(context.game_controller()?);
+
+    let mut audio: Box<dyn AudioOutput> = if options.mute {
+        Box::new(NullAudio)
+    } else {
+        Box::new(SdlBeeper::new(&context.audio()?, options.tone)?)
+    };
+
+    let mut chip8 = Chip8::new();
+    chip8.set_cycles_per_frame(options.cycles_per_frame);
+    // Octo sources run on XO-CHIP with Octo's quirks unless told otherwise
+    let octo = options.rom.ends_with(".8o");
+    chip8.set_platform(match options.platform {
+        Some(platform) => platform,
+        None if octo => Platform::XoChip,
+        None => Platform::default(),
+    });
+    if let Some(quirks) = options.quirks {
+        chip8.set_quirks(quirks);
     }
+    if options.vip_rng {
+        chip8.set_rng(Box::new(VipRandom::new(options.seed)));
+    } else {
+        chip8.seed(options.seed);
+    }
+    if options.trace.is_some() {
+        let mut tracer = Tracer::new(options.trace_size);
+        if let Some((start, end)) = options.trace_range {
+            tracer.set_range(start, end);
+        }
+        tracer.set_only(&options.trace_only);
+        chip8.set_tracer(Some(tracer));
+    }
+    let rom = if octo {
+        let source = match fs::read_to_string(&options.rom) {
+            Ok(source) => source,
+            Err(error) => return Err(format!("Error loading {}: {}", options.rom, error)),
+        };
+        match chip8::octo::compile(&source) {
+            Ok(rom) => rom,
+            Err(error) => return Err(format!("{}:{}", options.rom, error)),
+        }
+    } else {
+        match fs::read(&options.rom) {
+            Ok(rom) => rom,
+            Err(error) => return Err(format!("Error loading {}: {}", options.rom, error)),
+        }
+    };
+
+    let mut replay = match &options.replay {
+        Some(path) => Some(read_movie(path, &rom)?),
+        None => None,
+    };
+    if let Some(movie) = &replay {
+        movie.apply(&mut chip8);
+    }
+    let mut recording = options
+        .record
+        .as_ref()
+        .map(|_| Movie::new(&chip8, &rom, options.seed, options.vip_rng));
 
-    let mut chip8 = chip8::Chip8::new();
-    chip8.load(&args[1]);
+    if let Err(error) = chip8.load_bytes(&rom) {
+        return Err(format!("Error loading {}: {}", options.rom, error));
+    }
 
     let mut canvas = match window.into_canvas().build() {
         Ok(canvas) => canvas,
@@ -59,23 +299,86 @@ fn main() -> Result<(), String> {
         Err(SdlError(error)) => return Err(error),
     };
 
-    let mut pixels: [[u8; 2048]; 2048] = [[0; 2048]; 2048];
-
     let mut event_pump = context.event_pump()?;
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_draw_color(color(&palettes[palette], 0));
 
     canvas.clear();
     canvas.present();
 
-    let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture_streaming(sdl2::pixels::PixelFormatEnum::ARGB8888, 64, 32)
-        .unwrap();
+    let mut next_frame = Instant::now();
+    let mut rewind = Rewind::new(options.rewind_seconds * 60);
+    let mut rewinding = false;
+    // Frames run since power-on, less any rewound
+    let mut frame = 0;
+    // When to take the status message back out of the title
+    let mut status: Option<Instant> = None;
+    let mut console = if options.debug {
+        Some(DebugConsole::new())
+    } else {
+        None
+    };
 
     'running: loop {
-        chip8.emulate_cycle();
-
         for event in event_pump.poll_iter() {
+            // Controllers can come and go at any time, even while rebinding
+            match event {
+                Event::ControllerDeviceAdded { which, .. } => match controllers.connect(which) {
+                    Ok(Some(name)) => {
+                        let message = format!("Controller connected: {}", name);
+                        status = show_status(&mut canvas, &message);
+                    }
+                    Ok(None) => (),
+                    Err(error) => eprintln!("{}", error),
+                },
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(name) = controllers.disconnect(which) {
+                        let message = format!("Controller disconnected: {}", name);
+                        status = show_status(&mut canvas, &message);
+                    }
+                    for i in gamepads.disconnect(which) {
+                        chip8.keypad[i] = 0;
+                    }
+                }
+                _ => (),
+            }
+
+            if let Some(rebinder) = rebinding.as_mut() {
+                let step = match event {
+                    Event::Quit { .. } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(kc),
+                        repeat: false,
+                        ..
+                    } => rebinder.press(&kc.name()),
+                    Event::ControllerButtonDown { button, .. } => {
+                        rebinder.press_button(&button.string())
+                    }
+                    _ => continue,
+                };
+                match step {
+                    Step::Next => set_title(&mut canvas, &rebinder.prompt()),
+                    Step::Done => {
+                        rebinder.finish(&mut keymap);
+                        bindings = Bindings::new(&keymap.bindings(Some(&rom_name)))?;
+                        gamepads.set_buttons(keymap.buttons(Some(&rom_name)));
+                        let saved = match &options.config {
+                            Some(path) => frontend::keymap::save(path, &keymap),
+                            None => Err("No config directory to save the keymap in".to_string()),
+                        };
+                        if let Err(error) = saved {
+                            eprintln!("{}", error);
+                        }
+                        rebinding = None;
+                    }
+                    Step::Cancelled => rebinding = None,
+                }
+                if rebinding.is_none() {
+                    set_title(&mut canvas, TITLE);
+                    chip8.draw_flag = true;
+                }
+                continue;
+            }
+
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
@@ -83,11 +386,51 @@ fn main() -> Result<(), String> {
                     ..
                 } => break 'running,
 
+                // Hold Backspace to play the game backwards
                 Event::KeyDown {
-                    keycode: Some(kc), ..
+                    keycode: Some(REWIND_KEY),
+                    ..
+                } => rewinding = true,
+
+                Event::KeyUp {
+                    keycode: Some(REWIND_KEY),
+                    ..
+                } => rewinding = false,
+
+                // F1-F9 load a save state slot, Shift+F1-F9 save to it
+                Event::KeyDown {
+                    keycode: Some(kc),
+                    keymod,
+                    ..
                 } => {
-                    for i in 0..16 {
-                        if kc == KEYMAP[i] {
+                    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    if kc == PALETTE_KEY {
+                        palette = (palette + 1) % palettes.len();
+                        status =
+                            show_status(&mut canvas, &format!("Palette: {}", palettes[palette]));
+                        chip8.draw_flag = true;
+                    } else if kc == REBIND_KEY {
+                        let rom = if shift { Some(rom_name.clone()) } else { None };
+                        let current = keymap.bindings(rom.as_deref());
+                        let rebinder = Rebinder::new(rom, current);
+                        set_title(&mut canvas, &rebinder.prompt());
+                        rebinding = Some(rebinder);
+                        chip8.keypad = [0; 16];
+                    } else if let Some(slot) = slot_key(kc) {
+                        let result = if shift {
+                            savestate::save_slot(&chip8, &options.rom, slot)
+                        } else if recording.is_some() || replay.is_some() {
+                            // A movie only holds input, so it can't jump to a state
+                            Err("Can't load a save state during a movie".to_string())
+                        } else {
+                            rewind.clear();
+                            savestate::load_slot(&mut chip8, &options.rom, slot)
+                        };
+                        if let Err(error) = result {
+                            eprintln!("{}", error);
+                        }
+                    } else {
+                        for i in bindings.hex_keys(kc) {
                             chip8.keypad[i] = 1;
                         }
                     }
@@ -96,42 +439,204 @@ fn main() -> Result<(), String> {
                 Event::KeyUp {
                     keycode: Some(kc), ..
                 } => {
-                    for i in 0..16 {
-                        if kc == KEYMAP[i] {
-                            chip8.keypad[i] = 0;
-                        }
+                    for i in bindings.hex_keys(kc) {
+                        chip8.keypad[i] = 0;
+                    }
+                }
+
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let Some(i) = gamepads.press(which, &button.string()) {
+                        chip8.keypad[i] = 1;
+                    }
+                }
+
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let Some(i) = gamepads.release(which, &button.string()) {
+                        chip8.keypad[i] = 0;
                     }
                 }
                 _ => (),
             }
         }
 
-        // If draw occurred, redraw SDL screen
-        if chip8.draw_flag {
+        if status.is_some_and(|until| Instant::now() >= until) {
+            status = None;
+            match &rebinding {
+                Some(rebinder) => set_title(&mut canvas, &rebinder.prompt()),
+                None => set_title(&mut canvas, TITLE),
+            }
+        }
+
+        if rebinding.is_some() {
+            // The machine is paused while the keys are rebound
+        } else if rewinding {
+            // Keep the keys the player is holding now, not the ones recorded
+            let keypad = chip8.keypad;
+            if rewind.rewind(&mut chip8) {
+                chip8.draw_flag = true;
+                frame -= 1;
+                if let Some(recording) = recording.as_mut() {
+                    recording.truncate(frame);
+                }
+            }
+            chip8.keypad = keypad;
+        } else if let Some(console) = console.as_mut() {
+            if !console.run_frame(&mut chip8) {
+                break 'running;
+            }
+        } else {
+            if let Some(movie) = &replay {
+                match movie.keypad(frame) {
+                    Some(keypad) => chip8.keypad = keypad,
+                    None => {
+                        let message = format!("Movie finished after {} frames", frame);
+                        status = show_status(&mut canvas, &message);
+                        chip8.keypad = [0; 16];
+                        replay = None;
+                    }
+                }
+            }
+            if let Some(recording) = recording.as_mut() {
+                recording.record(&chip8.keypad);
+            }
+
+            if let Err(error) = chip8.run_frame() {
+                write_trace(&chip8, &options)?;
+                write_movie(recording.as_ref(), &options)?;
+                return Err(format!("Emulation halted: {}", error));
+            }
+            rewind.push(&chip8);
+            frame += 1;
+        }
+
+        if let Some(pattern) = chip8.audio_pattern() {
+            audio.set_pattern(pattern, chip8.pitch());
+        }
+        audio.set_beeping(chip8.beeping() && !rewinding && rebinding.is_none());
+
+        if chip8.exited() {
+            break 'running;
+        }
+
+        if let Some(rebinder) = &rebinding {
+            draw_digit(&mut canvas, &palettes[palette], rebinder.key())?;
+        } else if chip8.draw_flag {
+            // If draw occurred, redraw SDL screen
             chip8.draw_flag = false;
             canvas.clear();
 
-            // Update SDL texture
-            for (y, row) in chip8.gfx.iter().enumerate() {
-                for (x, &col) in row.iter().enumerate() {
-                    let x = (x as u32) * 30;
-                    let y = (y as u32) * 30;
+            // Scale each CHIP-8 pixel up to fill the window
+            let scale = WIDTH / chip8.gfx.width() as u32;
+            for (x, y, col) in chip8.gfx.pixels() {
+                let x = (x as u32) * scale;
+                let y = (y as u32) * scale;
 
-                    canvas.set_draw_color(color(col));
-                    canvas.fill_rect(Rect::new(x as i32, y as i32, 20, 20))?;
-                }
+                canvas.set_draw_color(color(&palettes[palette], col));
+                canvas.fill_rect(Rect::new(x as i32, y as i32, scale, scale))?;
             }
             canvas.present();
         }
+
+        // Sleep until the next 60 Hz frame is due. If we fell behind, don't
+        // try to catch up with a burst of frames.
+        next_frame += FRAME_DURATION;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
     }
 
+    write_trace(&chip8, &options)?;
+    write_movie(recording.as_ref(), &options)
+}
+
+fn read_movie(path: &str, rom: &[u8]) -> Result<Movie, String> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) => return Err(format!("Error reading {}: {}", path, error)),
+    };
+    match Movie::from_bytes(&bytes).and_then(|movie| movie.check_rom(rom).map(|_| movie)) {
+        Ok(movie) => Ok(movie),
+        Err(error) => Err(format!("Error loading {}: {}", path, error)),
+    }
+}
+
+// Writes out the input recorded with --record, if any.
+fn write_movie(recording: Option<&Movie>, options: &Options) -> Result<(), String> {
+    if let (Some(path), Some(movie)) = (&options.record, recording) {
+        if let Err(error) = fs::write(path, movie.to_bytes()) {
+            return Err(format!("Error writing {}: {}", path, error));
+        }
+    }
     Ok(())
 }
 
-fn color(v: u8) -> Color {
-    if v == 0 {
-        Color::RGB(0, 0, 0)
-    } else {
-        Color::RGB(0, 250, 0)
+// Writes out the instructions recorded with --trace, if any.
+fn write_trace(chip8: &Chip8, options: &Options) -> Result<(), String> {
+    if let (Some(path), Some(tracer)) = (&options.trace, chip8.tracer()) {
+        let file = File::create(path).map_err(|e| format!("Error writing {}: {}", path, e))?;
+        tracer
+            .write(&mut BufWriter::new(file), options.trace_format)
+            .map_err(|e| format!("Error writing {}: {}", path, e))?;
     }
+    Ok(())
+}
+
+fn set_title(canvas: &mut WindowCanvas, title: &str) {
+    if let Err(error) = canvas.window_mut().set_title(title) {
+        eprintln!("Error setting the window title: {}", error);
+    }
+}
+
+// Shows a message such as a controller being plugged in after the title,
+// and returns when to take it down again.
+fn show_status(canvas: &mut WindowCanvas, message: &str) -> Option<Instant> {
+    set_title(canvas, &format!("{} - {}", TITLE, message));
+    Some(Instant::now() + STATUS_DURATION)
+}
+
+// Shows the hex key being rebound, drawn large with the built-in font.
+fn draw_digit(canvas: &mut WindowCanvas, palette: &Palette, key: usize) -> Result<(), String> {
+    const SCALE: u32 = 64;
+    let glyph = &chip8::chip8::CHIP8_FONTSET[key * 5..key * 5 + 5];
+    let left = (WIDTH - 4 * SCALE) as i32 / 2;
+    let top = (HEIGHT - 5 * SCALE) as i32 / 2;
+
+    canvas.set_draw_color(color(palette, 0));
+    canvas.clear();
+    canvas.set_draw_color(color(palette, 1));
+    for (y, row) in glyph.iter().enumerate() {
+        for x in 0..4 {
+            if row & (0x80 >> x) != 0 {
+                let (x, y) = (left + x * SCALE as i32, top + y as i32 * SCALE as i32);
+                canvas.fill_rect(Rect::new(x, y, SCALE, SCALE))?;
+            }
+        }
+    }
+    canvas.present();
+    Ok(())
+}
+
+fn slot_key(kc: Keycode) -> Option<u8> {
+    let slots = [
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+        Keycode::F6,
+        Keycode::F7,
+        Keycode::F8,
+        Keycode::F9,
+    ];
+    slots.iter().position(|&k| k == kc).map(|i| i as u8 + 1)
+}
+
+// Pixel values are bitplane combinations: 1 is the only plane on CHIP-8 and
+// SUPER-CHIP, and XO-CHIP adds plane 2 and both planes overlapping.
+fn color(palette: &Palette, v: u8) -> Color {
+    let [r, g, b] = palette.colour(v);
+    Color::RGB(r, g, b)
 }
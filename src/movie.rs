@@ -0,0 +1,158 @@
+//! Keypad recordings that replay a run exactly.
+//!
+//! The machine is deterministic given its configuration, RNG seed and the
+//! keys held during each frame, so a movie stores only those: the
+//! configuration at power-on, the seed, a CRC-32 of the ROM it was recorded
+//! with, and one 16-bit keypad mask per frame.
+//!
+//! Movie files use the save state layout with the magic `C8MV`.
+
+use crate::chip8::Chip8;
+use crate::error::MovieError;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::rng::{VipRandom, XorShift};
+use crate::savestate::{crc32, StateReader, StateWriter};
+
+pub const MAGIC: &[u8; 4] = b"C8MV";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_crc: u32,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub cycles_per_frame: u32,
+    pub load_address: usize,
+    pub seed: u64,
+    /// Whether CXNN draws from `VipRandom` rather than `XorShift`.
+    pub vip_rng: bool,
+    frames: Vec<u16>,
+}
+
+impl Movie {
+    /// Starts an empty movie of `rom` running on a machine configured like
+    /// `chip8`, with its RNG seeded from `seed`.
+    pub fn new(chip8: &Chip8, rom: &[u8], seed: u64, vip_rng: bool) -> Self {
+        Self {
+            rom_crc: crc32(rom),
+            platform: chip8.platform(),
+            quirks: *chip8.quirks(),
+            cycles_per_frame: chip8.cycles_per_frame(),
+            load_address: chip8.load_address(),
+            seed,
+            vip_rng,
+            frames: vec![],
+        }
+    }
+
+    /// The number of frames recorded.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Appends the keys held during the next frame.
+    pub fn record(&mut self, keypad: &[u8; 16]) {
+        let mask = (0..16)
+            .filter(|&key| keypad[key] != 0)
+            .fold(0u16, |mask, key| mask | 1 << key);
+        self.frames.push(mask);
+    }
+
+    /// Drops every frame after the first `frames`, e.g. after rewinding.
+    pub fn truncate(&mut self, frames: usize) {
+        self.frames.truncate(frames);
+    }
+
+    /// The keypad to hold during `frame`, or None past the end.
+    pub fn keypad(&self, frame: usize) -> Option<[u8; 16]> {
+        let mask = *self.frames.get(frame)?;
+        let mut keypad = [0; 16];
+        for (key, state) in keypad.iter_mut().enumerate() {
+            *state = (mask >> key & 1) as u8;
+        }
+        Some(keypad)
+    }
+
+    /// Checks that `rom` is the ROM the movie was recorded with.
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), MovieError> {
+        let found = crc32(rom);
+        if found == self.rom_crc {
+            Ok(())
+        } else {
+            Err(MovieError::RomMismatch {
+                expected: self.rom_crc,
+                found,
+            })
+        }
+    }
+
+    /// Configures `chip8` the way it was when recording started. Call this
+    /// on a fresh machine, before loading the ROM.
+    pub fn apply(&self, chip8: &mut Chip8) {
+        chip8.set_platform(self.platform);
+        chip8.set_quirks(self.quirks);
+        chip8.set_cycles_per_frame(self.cycles_per_frame);
+        chip8.set_load_address(self.load_address);
+        if self.vip_rng {
+            chip8.set_rng(Box::new(VipRandom::new(self.seed)));
+        } else {
+            chip8.set_rng(Box::new(XorShift::new(self.seed)));
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::with_header(MAGIC, VERSION);
+        w.u32(self.rom_crc);
+        w.platform(self.platform);
+        w.quirks(&self.quirks);
+        w.u32(self.cycles_per_frame);
+        w.u32(self.load_address as u32);
+        w.u64(self.seed);
+        w.bool(self.vip_rng);
+
+        w.u32(self.frames.len() as u32);
+        for &mask in self.frames.iter() {
+            w.u16(mask);
+        }
+        w.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        let mut r = StateReader::with_header(bytes, MAGIC, VERSION)?;
+        let rom_crc = r.u32()?;
+        let platform = r.platform()?;
+        let quirks = r.quirks()?;
+        let cycles_per_frame = r.u32()?;
+        let load_address = r.u32()? as usize;
+        let seed = r.u64()?;
+        let vip_rng = r.bool()?;
+
+        let len = r.u32()? as usize;
+        let frames = r
+            .bytes(len.checked_mul(2).ok_or(MovieError::Corrupt)?)?
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        r.finish()?;
+
+        if load_address >= platform.memory_size() {
+            return Err(MovieError::Corrupt);
+        }
+
+        Ok(Self {
+            rom_crc,
+            platform,
+            quirks,
+            cycles_per_frame,
+            load_address,
+            seed,
+            vip_rng,
+            frames,
+        })
+    }
+}
@@ -0,0 +1,1363 @@
+//! A compiler for Octo, the assembly language most CHIP-8 homebrew is
+//! written in.
+//!
+//! It covers labels, `:const`, `:alias`, `:calc`, `:macro`, the data
+//! directives (`:byte`, `:pointer`, `:org`, `:unpack`, `:next`), and the
+//! structured `if ... then`, `if ... begin ... else ... end` and
+//! `loop ... while ... again` forms. `:stringmode` is not supported.
+//!
+//! As in Octo, the program runs from 0x200: if it doesn't start with
+//! `: main`, the first instruction is a jump to `main`.
+
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts;
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::DEFAULT_LOAD_ADDRESS;
+
+/// A compile error and where in the source it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoError {
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column of the offending token.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for OctoError {}
+
+// Octo keeps comparison results in VE, unless `compare-temp` is aliased.
+const COMPARE_TEMP: usize = 0xE;
+
+// Guards against macros that expand themselves forever.
+const MAX_EXPANSIONS: usize = 100_000;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PatchKind {
+    /// The NNN of the instruction at the address.
+    Address,
+    /// A 16-bit big-endian word.
+    Word,
+    /// The byte `nibble << 4 | address >> 8`, for `:unpack`.
+    UnpackHigh(u8),
+    /// The low byte of the address, for `:unpack`.
+    UnpackLow,
+}
+
+// A reference to a label that wasn't defined yet.
+#[derive(Debug)]
+struct Patch {
+    addr: usize,
+    kind: PatchKind,
+    name: Token,
+}
+
+#[derive(Debug)]
+enum Flow {
+    /// `begin`, with the address of the jump to the `else` or `end`.
+    Begin(usize, Token),
+    /// `else`, with the address of the jump to the `end`.
+    Else(usize, Token),
+    /// `loop`, with its start and the jumps out of it made by `while`.
+    Loop(usize, Vec<usize>, Token),
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    // Which bytes of `rom` have been emitted, to catch overlapping `:org`s
+    used: Vec<bool>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, usize>,
+    macros: HashMap<String, Macro>,
+    patches: Vec<Patch>,
+    flow: Vec<Flow>,
+    next: Option<Token>,
+    expansions: usize,
+    last: Token,
+}
+
+/// Compiles an Octo program into a ROM image to be loaded at 0x200.
+pub fn compile(source: &str) -> Result<Vec<u8>, OctoError> {
+    let tokens = tokenize(source);
+    let starts_with_main = tokens.len() >= 2 && tokens[0].text == ":" && tokens[1].text == "main";
+
+    let mut compiler = Compiler::new(tokens);
+    if !starts_with_main {
+        // Filled in once `main` is defined
+        let main = Token {
+            text: "main".to_string(),
+            line: 1,
+            column: 1,
+        };
+        compiler.patches.push(Patch {
+            addr: DEFAULT_LOAD_ADDRESS,
+            kind: PatchKind::Address,
+            name: main,
+        });
+        compiler.emit(Instruction::Jump { nnn: 0 })?;
+    }
+
+    while !compiler.tokens.is_empty() {
+        compiler.statement()?;
+    }
+    compiler.finish()
+}
+
+impl Compiler {
+    fn new(tokens: Vec<Token>) -> Self {
+        let last = Token {
+            text: String::new(),
+            line: tokens.last().map_or(1, |t| t.line),
+            column: 1,
+        };
+        Self {
+            tokens: tokens.into(),
+            rom: vec![],
+            used: vec![],
+            here: DEFAULT_LOAD_ADDRESS,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            patches: vec![],
+            flow: vec![],
+            next: None,
+            expansions: 0,
+            last,
+        }
+    }
+
+    fn error(token: &Token, message: String) -> OctoError {
+        OctoError {
+            line: token.line,
+            column: token.column,
+            message,
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, OctoError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            }
+            None => Err(Self::error(
+                &self.last,
+                "unexpected end of program".to_string(),
+            )),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|t| t.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, OctoError> {
+        let token = self.next_token()?;
+        if token.text == text {
+            Ok(token)
+        } else {
+            Err(Self::error(
+                &token,
+                format!("expected '{}', found '{}'", text, token.text),
+            ))
+        }
+    }
+
+    fn write(&mut self, addr: usize, bytes: &[u8], token: &Token) -> Result<(), OctoError> {
+        let offset = addr - DEFAULT_LOAD_ADDRESS;
+        if addr + bytes.len() > 0x10000 {
+            return Err(Self::error(
+                token,
+                "program is larger than 64 KiB".to_string(),
+            ));
+        }
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+            self.used.resize(offset + bytes.len(), false);
+        }
+        if let Some(i) = self.used[offset..offset + bytes.len()]
+            .iter()
+            .position(|&u| u)
+        {
+            return Err(Self::error(
+                token,
+                format!("address {:#X} is already in use", addr + i),
+            ));
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        for used in self.used[offset..offset + bytes.len()].iter_mut() {
+            *used = true;
+        }
+        Ok(())
+    }
+
+    fn emit_bytes(&mut self, bytes: &[u8]) -> Result<(), OctoError> {
+        let token = self.last.clone();
+        self.write(self.here, bytes, &token)?;
+        self.here += bytes.len();
+        Ok(())
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<(), OctoError> {
+        if let Some(name) = self.next.take() {
+            self.define(&name, self.here + 1)?;
+        }
+        self.emit_bytes(&instruction.encode().to_be_bytes())
+    }
+
+    fn define(&mut self, name: &Token, addr: usize) -> Result<(), OctoError> {
+        if self.register_named(&name.text).is_some() || parse_number(&name.text).is_some() {
+            return Err(Self::error(name, format!("invalid label '{}'", name.text)));
+        }
+        if self.labels.insert(name.text.clone(), addr).is_some() {
+            return Err(Self::error(
+                name,
+                format!("'{}' is already defined", name.text),
+            ));
+        }
+        Ok(())
+    }
+
+    fn register_named(&self, name: &str) -> Option<usize> {
+        if let Some(&x) = self.aliases.get(name) {
+            return Some(x);
+        }
+        let mut chars = name.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+                digit.to_digit(16).map(|x| x as usize)
+            }
+            _ => None,
+        }
+    }
+
+    fn is_register(&self) -> bool {
+        self.peek().and_then(|t| self.register_named(t)).is_some()
+    }
+
+    fn register(&mut self) -> Result<usize, OctoError> {
+        let token = self.next_token()?;
+        self.register_named(&token.text).ok_or_else(|| {
+            Self::error(
+                &token,
+                format!("expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    // A number, constant or defined label.
+    fn known_value(&self, token: &Token) -> Option<f64> {
+        if let Some(value) = parse_number(&token.text) {
+            return Some(value as f64);
+        }
+        if let Some(&value) = self.constants.get(&token.text) {
+            return Some(value);
+        }
+        self.labels.get(&token.text).map(|&addr| addr as f64)
+    }
+
+    fn value(&mut self, min: i64, max: i64) -> Result<i64, OctoError> {
+        let token = self.next_token()?;
+        let value = match self.known_value(&token) {
+            Some(value) => value.floor() as i64,
+            None => {
+                return Err(Self::error(
+                    &token,
+                    format!("undefined name '{}'", token.text),
+                ))
+            }
+        };
+        if value < min || value > max {
+            return Err(Self::error(
+                &token,
+                format!("{} is out of range {}..{}", value, min, max),
+            ));
+        }
+        Ok(value)
+    }
+
+    fn byte(&mut self) -> Result<u8, OctoError> {
+        self.value(-128, 255).map(|v| v as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, OctoError> {
+        self.value(0, 15).map(|v| v as u8)
+    }
+
+    // An address that may be a label defined further down. If so, the
+    // location `at` is patched once it is known.
+    fn address(&mut self, kind: PatchKind, at: usize, max: i64) -> Result<u16, OctoError> {
+        let token = self.next_token()?;
+        match self.known_value(&token) {
+            Some(value) => {
+                let value = value.floor() as i64;
+                if value < 0 || value > max {
+                    return Err(Self::error(
+                        &token,
+                        format!("address {} is out of range", value),
+                    ));
+                }
+                Ok(value as u16)
+            }
+            None if self.register_named(&token.text).is_none() && is_name(&token.text) => {
+                self.patches.push(Patch {
+                    addr: at,
+                    kind,
+                    name: token,
+                });
+                Ok(0)
+            }
+            None => Err(Self::error(
+                &token,
+                format!("expected an address, found '{}'", token.text),
+            )),
+        }
+    }
+
+    fn nnn(&mut self) -> Result<u16, OctoError> {
+        let at = self.here;
+        self.address(PatchKind::Address, at, 0xFFF)
+    }
+
+    fn statement(&mut self) -> Result<(), OctoError> {
+        use Instruction::*;
+
+        let token = self.next_token()?;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next_token()?;
+                self.define(&name, self.here)?;
+            }
+            ":const" => {
+                let name = self.next_token()?;
+                let value = self.value(i64::MIN, i64::MAX)?;
+                self.constant(&name, value as f64)?;
+            }
+            ":alias" => {
+                let name = self.next_token()?;
+                let x = self.register()?;
+                self.aliases.insert(name.text, x);
+            }
+            ":calc" => {
+                let name = self.next_token()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constant(&name, value)?;
+            }
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let addr = self.value(DEFAULT_LOAD_ADDRESS as i64, 0xFFFF)?;
+                self.here = addr as usize;
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    let brace = self.next_token()?;
+                    let value = self.calc()?.floor() as i64;
+                    if !(-128..=255).contains(&value) {
+                        return Err(Self::error(
+                            &brace,
+                            format!("{} is out of range -128..255", value),
+                        ));
+                    }
+                    value
+                } else {
+                    self.value(-128, 255)?
+                };
+                self.emit_bytes(&[value as u8])?;
+            }
+            ":pointer" => {
+                let at = self.here;
+                let addr = self.address(PatchKind::Word, at, 0xFFFF)?;
+                self.emit_bytes(&addr.to_be_bytes())?;
+            }
+            ":unpack" => {
+                let nibble = self.nibble()?;
+                let at = self.here;
+                let pending = self.patches.len();
+                let addr = self.address(PatchKind::UnpackHigh(nibble), at + 1, 0xFFF)?;
+                if self.patches.len() > pending {
+                    let name = self.last.clone();
+                    self.patches.push(Patch {
+                        addr: at + 3,
+                        kind: PatchKind::UnpackLow,
+                        name,
+                    });
+                }
+                self.emit(LoadImm {
+                    x: 0,
+                    nn: nibble << 4 | (addr >> 8) as u8,
+                })?;
+                self.emit(LoadImm {
+                    x: 1,
+                    nn: addr as u8,
+                })?;
+            }
+            ":next" => self.next = Some(self.next_token()?),
+            ":call" => {
+                let nnn = self.nnn()?;
+                self.emit(Call { nnn })?;
+            }
+            ":breakpoint" => {
+                self.next_token()?;
+            }
+            ":monitor" => {
+                self.next_token()?;
+                self.next_token()?;
+            }
+            ";" | "return" => self.emit(Return)?,
+            "clear" => self.emit(Clear)?,
+            "hires" => self.emit(Hires)?,
+            "lores" => self.emit(Lores)?,
+            "exit" => self.emit(Exit)?,
+            "scroll-left" => self.emit(ScrollLeft)?,
+            "scroll-right" => self.emit(ScrollRight)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(ScrollDown { n })?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(ScrollUp { n })?;
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(Plane { n })?;
+            }
+            "audio" => self.emit(LoadAudio)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Bcd { x })?;
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(SaveFlags { x })?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(LoadFlags { x })?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let save = token.text == "save";
+                if self.peek() == Some("-") {
+                    self.next_token()?;
+                    let y = self.register()?;
+                    self.emit(if save {
+                        SaveRange { x, y }
+                    } else {
+                        LoadRange { x, y }
+                    })?;
+                } else {
+                    self.emit(if save { Store { x } } else { Load { x } })?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(Draw { x, y, n })?;
+            }
+            "jump" => {
+                let nnn = self.nnn()?;
+                self.emit(Jump { nnn })?;
+            }
+            "jump0" => {
+                let nnn = self.nnn()?;
+                self.emit(JumpOffset { nnn })?;
+            }
+            "native" => {
+                let nnn = self.nnn()?;
+                self.emit(System { nnn })?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(match token.text.as_str() {
+                    "delay" => SetDelay { x },
+                    "buzzer" => SetSound { x },
+                    _ => Pitch { x },
+                })?;
+            }
+            "i" => self.index()?,
+            "if" => self.conditional_statement()?,
+            "else" => match self.flow.pop() {
+                Some(Flow::Begin(jump, _)) => {
+                    let end = self.here;
+                    self.emit(Jump { nnn: 0 })?;
+                    self.patch_jump(jump, self.here, &token)?;
+                    self.flow.push(Flow::Else(end, token));
+                }
+                _ => return Err(Self::error(&token, "'else' without 'begin'".to_string())),
+            },
+            "end" => match self.flow.pop() {
+                Some(Flow::Begin(jump, _)) | Some(Flow::Else(jump, _)) => {
+                    self.patch_jump(jump, self.here, &token)?;
+                }
+                _ => return Err(Self::error(&token, "'end' without 'begin'".to_string())),
+            },
+            "loop" => self.flow.push(Flow::Loop(self.here, vec![], token)),
+            "while" => {
+                if !self.flow.iter().any(|f| matches!(f, Flow::Loop(..))) {
+                    return Err(Self::error(&token, "'while' outside of a loop".to_string()));
+                }
+                self.conditional(true)?;
+                let jump = self.here;
+                self.emit(Jump { nnn: 0 })?;
+                if let Some(Flow::Loop(_, whiles, _)) = self
+                    .flow
+                    .iter_mut()
+                    .rev()
+                    .find(|f| matches!(f, Flow::Loop(..)))
+                {
+                    whiles.push(jump);
+                }
+            }
+            "again" => match self.flow.pop() {
+                Some(Flow::Loop(start, whiles, _)) => {
+                    let nnn = Self::jump_target(start, &token)?;
+                    self.emit(Jump { nnn })?;
+                    for jump in whiles {
+                        self.patch_jump(jump, self.here, &token)?;
+                    }
+                }
+                _ => return Err(Self::error(&token, "'again' without 'loop'".to_string())),
+            },
+            text if self.register_named(text).is_some() => {
+                self.tokens.push_front(token);
+                self.register_statement()?;
+            }
+            text if self.macros.contains_key(text) => self.expand(&token)?,
+            _ => match self.known_value(&token) {
+                // Numbers and constants are data
+                Some(value) if !self.labels.contains_key(&token.text) => {
+                    let value = value.floor() as i64;
+                    if !(-128..=255).contains(&value) {
+                        return Err(Self::error(
+                            &token,
+                            format!("{} doesn't fit in a byte", value),
+                        ));
+                    }
+                    self.emit_bytes(&[value as u8])?;
+                }
+                // Anything else is a call to a subroutine
+                _ => {
+                    self.tokens.push_front(token);
+                    let nnn = self.nnn()?;
+                    self.emit(Call { nnn })?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn constant(&mut self, name: &Token, value: f64) -> Result<(), OctoError> {
+        if !is_name(&name.text) || self.register_named(&name.text).is_some() {
+            return Err(Self::error(
+                name,
+                format!("invalid constant name '{}'", name.text),
+            ));
+        }
+        self.constants.insert(name.text.clone(), value);
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<(), OctoError> {
+        use Instruction::*;
+
+        let op = self.next_token()?;
+        match op.text.as_str() {
+            ":=" => match self.peek() {
+                Some("long") => {
+                    self.next_token()?;
+                    let at = self.here + 2;
+                    let addr = self.address(PatchKind::Word, at, 0xFFFF)?;
+                    self.emit(LongLoadIndex)?;
+                    self.emit_bytes(&addr.to_be_bytes())?;
+                }
+                Some("hex") => {
+                    self.next_token()?;
+                    let x = self.register()?;
+                    self.emit(Font { x })?;
+                }
+                Some("bighex") => {
+                    self.next_token()?;
+                    let x = self.register()?;
+                    self.emit(BigFont { x })?;
+                }
+                _ => {
+                    let nnn = self.nnn()?;
+                    self.emit(LoadIndex { nnn })?;
+                }
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.emit(AddIndex { x })?;
+            }
+            _ => {
+                return Err(Self::error(
+                    &op,
+                    format!("expected ':=' or '+=' after i, found '{}'", op.text),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self) -> Result<(), OctoError> {
+        use Instruction::*;
+
+        let x = self.register()?;
+        let op = self.next_token()?;
+        let instruction = match op.text.as_str() {
+            ":=" => match self.peek() {
+                _ if self.is_register() => Move {
+                    x,
+                    y: self.register()?,
+                },
+                Some("random") => {
+                    self.next_token()?;
+                    Random {
+                        x,
+                        nn: self.byte()?,
+                    }
+                }
+                Some("key") => {
+                    self.next_token()?;
+                    WaitKey { x }
+                }
+                Some("delay") => {
+                    self.next_token()?;
+                    GetDelay { x }
+                }
+                _ => LoadImm {
+                    x,
+                    nn: self.byte()?,
+                },
+            },
+            "+=" if self.is_register() => Add {
+                x,
+                y: self.register()?,
+            },
+            "+=" => AddImm {
+                x,
+                nn: self.byte()?,
+            },
+            "-=" if self.is_register() => Sub {
+                x,
+                y: self.register()?,
+            },
+            "-=" => AddImm {
+                x,
+                nn: self.byte()?.wrapping_neg(),
+            },
+            "=-" => SubReverse {
+                x,
+                y: self.register()?,
+            },
+            "|=" => Or {
+                x,
+                y: self.register()?,
+            },
+            "&=" => And {
+                x,
+                y: self.register()?,
+            },
+            "^=" => Xor {
+                x,
+                y: self.register()?,
+            },
+            ">>=" => ShiftRight {
+                x,
+                y: self.register()?,
+            },
+            "<<=" => ShiftLeft {
+                x,
+                y: self.register()?,
+            },
+            _ => {
+                return Err(Self::error(
+                    &op,
+                    format!("unknown register operator '{}'", op.text),
+                ))
+            }
+        };
+        self.emit(instruction)
+    }
+
+    fn conditional_statement(&mut self) -> Result<(), OctoError> {
+        // The condition comes before 'then' or 'begin', so look ahead
+        let keyword = self
+            .tokens
+            .iter()
+            .take(5)
+            .find(|t| t.text == "then" || t.text == "begin")
+            .map(|t| t.text.clone());
+
+        match keyword.as_deref() {
+            Some("then") => {
+                self.conditional(false)?;
+                self.expect("then")?;
+            }
+            Some(_) => {
+                self.conditional(true)?;
+                let begin = self.expect("begin")?;
+                let jump = self.here;
+                self.emit(Instruction::Jump { nnn: 0 })?;
+                self.flow.push(Flow::Begin(jump, begin));
+            }
+            None => {
+                return Err(Self::error(
+                    &self.last.clone(),
+                    "expected 'then' or 'begin' after the condition".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // Emits the instructions that skip the next one unless the condition
+    // holds, or with `negated`, skip it if the condition holds.
+    fn conditional(&mut self, negated: bool) -> Result<(), OctoError> {
+        use Instruction::*;
+
+        let x = self.register()?;
+        let op = self.next_token()?;
+        let mut text = op.text.as_str();
+        if negated {
+            text = match text {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">" => "<=",
+                "<=" => ">",
+                ">=" => "<",
+                other => other,
+            };
+        }
+        let temp = self
+            .aliases
+            .get("compare-temp")
+            .copied()
+            .unwrap_or(COMPARE_TEMP);
+
+        match text {
+            "==" if self.is_register() => {
+                let y = self.register()?;
+                self.emit(SkipNe { x, y })?;
+            }
+            "==" => {
+                let nn = self.byte()?;
+                self.emit(SkipNeImm { x, nn })?;
+            }
+            "!=" if self.is_register() => {
+                let y = self.register()?;
+                self.emit(SkipEq { x, y })?;
+            }
+            "!=" => {
+                let nn = self.byte()?;
+                self.emit(SkipEqImm { x, nn })?;
+            }
+            "key" => self.emit(SkipNotKey { x })?,
+            "-key" => self.emit(SkipKey { x })?,
+            "<" | ">" | "<=" | ">=" => {
+                // VF is 1 after subtracting when there was no borrow
+                if self.is_register() {
+                    let y = self.register()?;
+                    self.emit(Move { x: temp, y })?;
+                } else {
+                    let nn = self.byte()?;
+                    self.emit(LoadImm { x: temp, nn })?;
+                }
+                match text {
+                    ">" => self.emit(Sub { x: temp, y: x })?,
+                    "<" => self.emit(SubReverse { x: temp, y: x })?,
+                    ">=" => self.emit(SubReverse { x: temp, y: x })?,
+                    _ => self.emit(Sub { x: temp, y: x })?,
+                }
+                let nn = 1;
+                match text {
+                    ">" | "<" => self.emit(SkipEqImm { x: 0xF, nn })?,
+                    _ => self.emit(SkipNeImm { x: 0xF, nn })?,
+                }
+            }
+            _ => {
+                return Err(Self::error(
+                    &op,
+                    format!("unknown conditional operator '{}'", op.text),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // Points the jump at `addr`, emitted for a block that `token` closes,
+    // at `target`.
+    fn patch_jump(&mut self, addr: usize, target: usize, token: &Token) -> Result<(), OctoError> {
+        let nnn = Self::jump_target(target, token)?;
+        let offset = addr - DEFAULT_LOAD_ADDRESS;
+        self.rom[offset..offset + 2]
+            .copy_from_slice(&Instruction::Jump { nnn }.encode().to_be_bytes());
+        Ok(())
+    }
+
+    // Jumps only reach the first 4 KiB.
+    fn jump_target(target: usize, token: &Token) -> Result<u16, OctoError> {
+        if target > 0xFFF {
+            return Err(Self::error(
+                token,
+                format!(
+                    "'{}' needs a jump to {:#X}, above 0xFFF",
+                    token.text, target
+                ),
+            ));
+        }
+        Ok(target as u16)
+    }
+
+    fn define_macro(&mut self) -> Result<(), OctoError> {
+        let name = self.next_token()?;
+        let mut args = vec![];
+        loop {
+            let token = self.next_token()?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+
+        let mut body = vec![];
+        let mut depth = 1;
+        loop {
+            let token = self.next_token()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &Token) -> Result<(), OctoError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(Self::error(
+                name,
+                "too many macro expansions, does a macro expand itself?".to_string(),
+            ));
+        }
+
+        let definition = self.macros[&name.text].clone();
+        let mut bindings = HashMap::new();
+        for arg in definition.args.iter() {
+            bindings.insert(arg.clone(), self.next_token()?.text);
+        }
+
+        for token in definition.body.iter().rev() {
+            let mut token = token.clone();
+            if let Some(value) = bindings.get(&token.text) {
+                token.text = value.clone();
+            }
+            // Errors inside the expansion point at the invocation
+            token.line = name.line;
+            token.column = name.column;
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    // Evaluates a `:calc` expression up to the closing brace. As in Octo,
+    // binary operators have no precedence and group from the right, so
+    // `2 * 3 + 1` is 8.
+    fn calc(&mut self) -> Result<f64, OctoError> {
+        let value = self.calc_expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn calc_expression(&mut self) -> Result<f64, OctoError> {
+        let lhs = self.calc_term()?;
+        let op = match self.peek() {
+            Some(op) if is_binary_operator(op) => self.next_token()?.text,
+            _ => return Ok(lhs),
+        };
+        let rhs = self.calc_expression()?;
+
+        Ok(match op.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => ((lhs as i64) & (rhs as i64)) as f64,
+            "|" => ((lhs as i64) | (rhs as i64)) as f64,
+            "^" => ((lhs as i64) ^ (rhs as i64)) as f64,
+            "<<" => ((lhs as i64) << (rhs as i64 & 63)) as f64,
+            ">>" => ((lhs as i64) >> (rhs as i64 & 63)) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => (lhs < rhs) as i64 as f64,
+            ">" => (lhs > rhs) as i64 as f64,
+            "<=" => (lhs <= rhs) as i64 as f64,
+            ">=" => (lhs >= rhs) as i64 as f64,
+            "==" => (lhs == rhs) as i64 as f64,
+            _ => (lhs != rhs) as i64 as f64,
+        })
+    }
+
+    fn calc_term(&mut self) -> Result<f64, OctoError> {
+        let token = self.next_token()?;
+        let value = match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression()?;
+                self.expect(")")?;
+                value
+            }
+            "-" => -self.calc_term()?,
+            "~" => !(self.calc_term()? as i64) as f64,
+            "!" => (self.calc_term()? == 0.0) as i64 as f64,
+            "abs" => self.calc_term()?.abs(),
+            "sqrt" => self.calc_term()?.sqrt(),
+            "floor" => self.calc_term()?.floor(),
+            "ceil" => self.calc_term()?.ceil(),
+            "sin" => self.calc_term()?.sin(),
+            "cos" => self.calc_term()?.cos(),
+            "log" => self.calc_term()?.ln(),
+            "@" => {
+                let addr = self.calc_term()? as usize;
+                let offset = addr.wrapping_sub(DEFAULT_LOAD_ADDRESS);
+                *self.rom.get(offset).unwrap_or(&0) as f64
+            }
+            "PI" => consts::PI,
+            "E" => consts::E,
+            "HERE" => self.here as f64,
+            _ => match self.known_value(&token) {
+                Some(value) => value,
+                None => {
+                    return Err(Self::error(
+                        &token,
+                        format!("undefined name '{}' in :calc", token.text),
+                    ))
+                }
+            },
+        };
+        Ok(value)
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, OctoError> {
+        if let Some(flow) = self.flow.last() {
+            let (token, message) = match flow {
+                Flow::Begin(_, token) | Flow::Else(_, token) => (token, "'begin' without 'end'"),
+                Flow::Loop(_, _, token) => (token, "'loop' without 'again'"),
+            };
+            return Err(Self::error(token, message.to_string()));
+        }
+
+        for patch in std::mem::take(&mut self.patches) {
+            let addr = match self.labels.get(&patch.name.text) {
+                Some(&addr) => addr,
+                None => {
+                    return Err(Self::error(
+                        &patch.name,
+                        format!("undefined name '{}'", patch.name.text),
+                    ))
+                }
+            };
+
+            let offset = patch.addr - DEFAULT_LOAD_ADDRESS;
+            match patch.kind {
+                PatchKind::Address | PatchKind::UnpackHigh(_) if addr > 0xFFF => {
+                    return Err(Self::error(
+                        &patch.name,
+                        format!("'{}' is above 0xFFF, use 'i := long'", patch.name.text),
+                    ));
+                }
+                PatchKind::Address => {
+                    self.rom[offset] = self.rom[offset] & 0xF0 | (addr >> 8) as u8;
+                    self.rom[offset + 1] = addr as u8;
+                }
+                PatchKind::Word => {
+                    self.rom[offset..offset + 2].copy_from_slice(&(addr as u16).to_be_bytes());
+                }
+                PatchKind::UnpackHigh(nibble) => self.rom[offset] = nibble << 4 | (addr >> 8) as u8,
+                PatchKind::UnpackLow => self.rom[offset] = addr as u8,
+            }
+        }
+
+        Ok(self.rom)
+    }
+}
+
+fn is_binary_operator(op: &str) -> bool {
+    [
+        "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=",
+        ">=", "==", "!=",
+    ]
+    .contains(&op)
+}
+
+fn is_name(text: &str) -> bool {
+    text.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Parses decimal, `0x` hex and `0b` binary, with an optional minus sign.
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+// Octo tokens are separated by whitespace, and `#` starts a comment.
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    for (i, line) in source.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+
+        let mut start = None;
+        for (column, c) in line
+            .char_indices()
+            .chain(std::iter::once((line.len(), ' ')))
+        {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(s)) => {
+                    tokens.push(Token {
+                        text: line[s..column].to_string(),
+                        line: i + 1,
+                        column: line[..s].chars().count() + 1,
+                    });
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8;
+
+    fn words(program: &[u16]) -> Vec<u8> {
+        program.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let error = compile(source).unwrap_err();
+        (error.line, error.column, error.message)
+    }
+
+    #[test]
+    fn jumps_to_main_unless_it_comes_first() {
+        assert_eq!(compile(": main clear").unwrap(), words(&[0x00E0]));
+        assert_eq!(
+            compile(": sub return : main sub").unwrap(),
+            words(&[0x1204, 0x00EE, 0x2202])
+        );
+        assert_eq!(
+            error(": sub return"),
+            (1, 1, "undefined name 'main'".to_string())
+        );
+    }
+
+    #[test]
+    fn forward_references() {
+        let source = "
+            : main
+                :call sub
+                i := data
+                jump main
+            : sub
+                return
+            : data
+                1 2
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            [0x22, 0x06, 0xA2, 0x08, 0x12, 0x00, 0x00, 0xEE, 1, 2]
+        );
+        assert_eq!(
+            error(": main jump nowhere"),
+            (1, 13, "undefined name 'nowhere'".to_string())
+        );
+    }
+
+    #[test]
+    fn macros_and_calc() {
+        let source = "
+            :calc seven { 2 * 3 + 1 }
+            :macro twice reg { reg += 1 reg += 1 }
+            : main
+                twice v3
+                :byte seven
+                :byte { HERE - 0x200 }
+                :byte { @ 0x203 }
+        ";
+        // Operators group from the right, so 2 * 3 + 1 is 8
+        assert_eq!(
+            compile(source).unwrap(),
+            [0x12, 0x02, 0x73, 0x01, 0x73, 0x01, 8, 7, 0x01]
+        );
+        assert_eq!(
+            error(":macro forever { forever } : main forever").2,
+            "too many macro expansions, does a macro expand itself?"
+        );
+    }
+
+    #[test]
+    fn bytes_must_fit() {
+        assert_eq!(
+            compile(": main :byte -128 :byte { 255 } :byte { 0 - 128 }").unwrap(),
+            [0x80, 0xFF, 0x80]
+        );
+        assert_eq!(
+            error(": main :byte 256"),
+            (1, 14, "256 is out of range -128..255".to_string())
+        );
+        assert_eq!(
+            error(": main :byte { 100 * 3 }"),
+            (1, 14, "300 is out of range -128..255".to_string())
+        );
+        assert_eq!(
+            error(": main :byte { 0 - 200 }"),
+            (1, 14, "-200 is out of range -128..255".to_string())
+        );
+    }
+
+    #[test]
+    fn next_labels_the_operand_byte() {
+        let source = ": main :next target v0 := 5 i := target";
+        assert_eq!(compile(source).unwrap(), words(&[0x6005, 0xA201]));
+    }
+
+    #[test]
+    fn unpack_splits_an_address() {
+        let source = ": main :unpack 0xA data : data 0x12";
+        assert_eq!(compile(source).unwrap(), [0x60, 0xA2, 0x61, 0x04, 0x12]);
+        let source = ": main : data :unpack 0xA data";
+        assert_eq!(compile(source).unwrap(), [0x60, 0xA2, 0x61, 0x00]);
+    }
+
+    #[test]
+    fn org_moves_output() {
+        let rom = compile(": main jump far :org 0x300 : far return").unwrap();
+        assert_eq!(rom.len(), 0x102);
+        assert_eq!(rom[..2], [0x13, 0x00]);
+        assert_eq!(rom[0x100..], [0x00, 0xEE]);
+        assert_eq!(
+            error(": main clear :org 0x200 return"),
+            (1, 25, "address 0x200 is already in use".to_string())
+        );
+    }
+
+    #[test]
+    fn if_then_skips_one_instruction() {
+        assert_eq!(
+            compile(": main if v1 == 2 then v2 := 3").unwrap(),
+            words(&[0x4102, 0x6203])
+        );
+        assert_eq!(
+            compile(": main if v1 != v2 then v2 := 3").unwrap(),
+            words(&[0x5120, 0x6203])
+        );
+        assert_eq!(
+            compile(": main if v1 key then v2 := 3").unwrap(),
+            words(&[0xE1A1, 0x6203])
+        );
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let source = ": main if v1 == 2 begin v2 := 3 else v2 := 4 end";
+        assert_eq!(
+            compile(source).unwrap(),
+            words(&[0x3102, 0x1208, 0x6203, 0x120A, 0x6204])
+        );
+        let source = ": main if v1 == 2 begin v2 := 3 end";
+        assert_eq!(compile(source).unwrap(), words(&[0x3102, 0x1206, 0x6203]));
+        assert_eq!(
+            error(": main if v1 == 2 begin clear"),
+            (1, 19, "'begin' without 'end'".to_string())
+        );
+        assert_eq!(
+            error(": main else"),
+            (1, 8, "'else' without 'begin'".to_string())
+        );
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let source = ": main loop v0 += 1 while v0 != 5 again";
+        assert_eq!(
+            compile(source).unwrap(),
+            words(&[0x7001, 0x4005, 0x1208, 0x1200])
+        );
+        assert_eq!(
+            error(": main while v0 == 1"),
+            (1, 8, "'while' outside of a loop".to_string())
+        );
+        assert_eq!(
+            error(": main loop clear"),
+            (1, 8, "'loop' without 'again'".to_string())
+        );
+    }
+
+    #[test]
+    fn comparisons_use_compare_temp() {
+        assert_eq!(
+            compile(": main if v1 < 5 then v2 := 1").unwrap(),
+            words(&[0x6E05, 0x8E17, 0x3F01, 0x6201])
+        );
+        assert_eq!(
+            compile(":alias compare-temp v5 : main if v1 >= v3 then v2 := 1").unwrap(),
+            words(&[0x1202, 0x8530, 0x8517, 0x4F01, 0x6201])
+        );
+    }
+
+    // Runs `if va OP b then ...` with every other register holding a marker,
+    // and returns whether the condition held and the registers after.
+    fn compare(op: &str, a: u8, b: &str, temp: usize) -> (bool, [u8; 16]) {
+        let mut source = String::new();
+        if temp != COMPARE_TEMP {
+            source += &format!(":alias compare-temp v{:x}\n", temp);
+        }
+        source += ": main\n";
+        for x in 0..16 {
+            source += &format!("v{:x} := {}\n", x, 0x40 + x);
+        }
+        source += &format!(
+            "va := {} vb := 2
+            if va {} {} then i := 0xBB
+            : halt jump halt",
+            a, op, b
+        );
+
+        let mut chip8 = Chip8::new();
+        chip8.load_bytes(&compile(&source).unwrap()).unwrap();
+        for _ in 0..40 {
+            chip8.emulate_cycle().unwrap();
+        }
+        (chip8.index() == 0xBB, *chip8.registers())
+    }
+
+    #[test]
+    fn comparisons_clobber_only_compare_temp_and_vf() {
+        // Whether each operator holds for 1, 2 and 3 against 2
+        let cases = [
+            ("<", [true, false, false]),
+            (">", [false, false, true]),
+            ("<=", [true, true, false]),
+            (">=", [false, true, true]),
+            ("==", [false, true, false]),
+            ("!=", [true, false, true]),
+        ];
+        for &temp in [COMPARE_TEMP, 5].iter() {
+            for (op, holds) in cases.iter() {
+                for (&a, &holds) in [1, 2, 3].iter().zip(holds.iter()) {
+                    for b in ["vb", "2"].iter() {
+                        let (taken, registers) = compare(op, a, b, temp);
+                        let case = format!("v{:x} temp, {} {} {}", temp, a, op, b);
+                        assert_eq!(taken, holds, "{}", case);
+
+                        let mut expected: Vec<u8> = (0x40..0x50).collect();
+                        expected[0xA] = a;
+                        expected[0xB] = 2;
+                        for x in (0..16).filter(|&x| x != temp && x != 0xF) {
+                            assert_eq!(registers[x], expected[x], "V{:X}, {}", x, case);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn jumps_past_0xfff_are_errors() {
+        assert_eq!(
+            error(": main if v0 == 1 begin :org 0xFFE clear end"),
+            (
+                1,
+                42,
+                "'end' needs a jump to 0x1000, above 0xFFF".to_string()
+            )
+        );
+        assert_eq!(
+            error(": main :org 0x1000 loop clear again").2,
+            "'again' needs a jump to 0x1000, above 0xFFF"
+        );
+        assert_eq!(
+            error(": main loop while v0 == 1 :org 0xFFE clear again").2,
+            "'again' needs a jump to 0x1002, above 0xFFF"
+        );
+        assert_eq!(
+            error(": main jump far :org 0x1000 : far return"),
+            (1, 13, "'far' is above 0xFFF, use 'i := long'".to_string())
+        );
+        assert_eq!(
+            error(": main jump 0x1000"),
+            (1, 13, "address 4096 is out of range".to_string())
+        );
+    }
+}
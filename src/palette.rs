@@ -0,0 +1,127 @@
+//! Colour palettes for the four pixel values: off, plane 1, plane 2 and
+//! both planes.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The colours of the four pixel values: off, plane 1, plane 2 and both.
+pub const DEFAULT_PALETTE: [[u8; 3]; 4] = [[0, 0, 0], [0, 250, 0], [250, 150, 0], [250, 250, 250]];
+
+/// A named set of colours, either a preset or custom colours written
+/// `RRGGBB,RRGGBB[,RRGGBB,RRGGBB]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    /// The preset name, or the colours for a custom palette, in a form
+    /// `FromStr` reads back.
+    pub name: String,
+    pub colours: [[u8; 3]; 4],
+}
+
+impl Palette {
+    /// The named palettes accepted by `FromStr`, in the order the frontend
+    /// cycles through them.
+    pub const PRESETS: [(&'static str, [[u8; 3]; 4]); 5] = [
+        ("classic", DEFAULT_PALETTE),
+        (
+            "amber",
+            [[26, 16, 0], [255, 176, 0], [204, 102, 0], [255, 224, 153]],
+        ),
+        // The four greens of the original Game Boy screen
+        (
+            "lcd",
+            [[155, 188, 15], [15, 56, 15], [139, 172, 15], [48, 98, 48]],
+        ),
+        (
+            "octo",
+            [[153, 102, 0], [255, 204, 0], [255, 102, 0], [102, 34, 0]],
+        ),
+        (
+            "high-contrast",
+            [[0, 0, 0], [255, 255, 255], [255, 255, 0], [0, 255, 255]],
+        ),
+    ];
+
+    pub fn presets() -> impl Iterator<Item = Palette> {
+        Palette::PRESETS.iter().map(|(name, colours)| Palette {
+            name: name.to_string(),
+            colours: *colours,
+        })
+    }
+
+    /// The colour of a pixel value.
+    pub fn colour(&self, pixel: u8) -> [u8; 3] {
+        self.colours[pixel as usize & 3]
+    }
+
+    // Two colours are off and on, which then also serves planes 2 and 3.
+    fn custom(s: &str) -> Option<Palette> {
+        let colours: Vec<[u8; 3]> = s.split(',').map(parse_rgb).collect::<Option<_>>()?;
+        let colours = match *colours.as_slice() {
+            [off, on] => [off, on, on, on],
+            [off, one, two, both] => [off, one, two, both],
+            _ => return None,
+        };
+
+        let name: Vec<String> = colours
+            .iter()
+            .map(|[r, g, b]| format!("{:02X}{:02X}{:02X}", r, g, b))
+            .collect();
+        Some(Palette {
+            name: name.join(","),
+            colours,
+        })
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::presets().next().unwrap()
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// `RRGGBB`, optionally starting with `#`.
+fn parse_rgb(s: &str) -> Option<[u8; 3]> {
+    let s = s.trim();
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Returned when parsing a palette that is neither a preset nor valid
+/// colours.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPalette(pub String);
+
+impl fmt::Display for InvalidPalette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = Palette::PRESETS.iter().map(|(name, _)| *name).collect();
+        write!(
+            f,
+            "invalid palette '{}', expected one of {} or 2 or 4 colours written RRGGBB,RRGGBB",
+            self.0,
+            names.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for InvalidPalette {}
+
+impl FromStr for Palette {
+    type Err = InvalidPalette;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Palette::presets()
+            .find(|palette| palette.name.eq_ignore_ascii_case(s.trim()))
+            .or_else(|| Palette::custom(s))
+            .ok_or_else(|| InvalidPalette(s.to_string()))
+    }
+}
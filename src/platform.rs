@@ -0,0 +1,99 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::quirks::Quirks;
+
+/// The instruction set the interpreter accepts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// The original CHIP-8 instruction set.
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: high resolution, scrolling, big sprites and fonts.
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bitplanes and
+    /// programmable audio.
+    XoChip,
+}
+
+impl Platform {
+    /// The named platforms accepted by `FromStr`.
+    pub const NAMES: [(&'static str, Platform); 3] = [
+        ("chip8", Platform::Chip8),
+        ("schip", Platform::SuperChip),
+        ("xochip", Platform::XoChip),
+    ];
+
+    /// The quirks most ROMs written for this platform expect.
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::VIP,
+            Platform::SuperChip => Quirks::SCHIP,
+            Platform::XoChip => Quirks::XOCHIP,
+        }
+    }
+
+    /// Bytes of addressable memory.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => 0x1000,
+            Platform::XoChip => 0x10000,
+        }
+    }
+
+    /// Whether the SUPER-CHIP instructions are available.
+    pub fn has_schip(self) -> bool {
+        self != Platform::Chip8
+    }
+
+    /// Whether the XO-CHIP instructions are available.
+    pub fn has_xochip(self) -> bool {
+        self == Platform::XoChip
+    }
+
+    /// Whether this platform runs everything `other` does.
+    pub fn includes(self, other: Platform) -> bool {
+        match other {
+            Platform::Chip8 => true,
+            Platform::SuperChip => self.has_schip(),
+            Platform::XoChip => self.has_xochip(),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match Platform::NAMES.iter().find(|(_, p)| p == self) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Returned when parsing a platform name that doesn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPlatform(pub String);
+
+impl fmt::Display for UnknownPlatform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown platform '{}', expected one of:", self.0)?;
+        for (name, _) in Platform::NAMES.iter() {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnknownPlatform {}
+
+impl FromStr for Platform {
+    type Err = UnknownPlatform;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalized = name.to_ascii_lowercase().replace('-', "");
+        match Platform::NAMES.iter().find(|(n, _)| *n == normalized) {
+            Some((_, platform)) => Ok(*platform),
+            None => Err(UnknownPlatform(name.to_string())),
+        }
+    }
+}
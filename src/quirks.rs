@@ -0,0 +1,123 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How FX55 and FX65 leave I after copying registers to or from memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// I is left unchanged (SUPER-CHIP).
+    Unchanged,
+    /// I is incremented by X (CHIP-48).
+    ByX,
+    /// I is incremented by X + 1 (COSMAC VIP, XO-CHIP).
+    ByXPlusOne,
+}
+
+/// Behaviour of the opcodes that interpreters disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VY into VX instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// What FX55 and FX65 do to I.
+    pub load_store: IndexIncrement,
+    /// BNNN is read as BXNN and jumps to XNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// FX1E sets VF to 1 when I overflows past 0xFFF.
+    pub index_overflow_sets_vf: bool,
+    /// DXYN waits for the next frame, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub const VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        vf_reset: true,
+        index_overflow_sets_vf: false,
+        display_wait: true,
+        clip_sprites: true,
+    };
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub const CHIP48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store: IndexIncrement::ByX,
+        jump_uses_vx: true,
+        vf_reset: false,
+        index_overflow_sets_vf: false,
+        display_wait: false,
+        clip_sprites: true,
+    };
+
+    /// SUPER-CHIP 1.1.
+    pub const SCHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store: IndexIncrement::Unchanged,
+        jump_uses_vx: true,
+        vf_reset: false,
+        index_overflow_sets_vf: false,
+        display_wait: false,
+        clip_sprites: true,
+    };
+
+    /// XO-CHIP, as implemented by Octo.
+    pub const XOCHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        vf_reset: false,
+        index_overflow_sets_vf: false,
+        display_wait: false,
+        clip_sprites: false,
+    };
+
+    /// The named presets accepted by `FromStr`.
+    pub const PRESETS: [(&'static str, Quirks); 4] = [
+        ("vip", Quirks::VIP),
+        ("chip48", Quirks::CHIP48),
+        ("schip", Quirks::SCHIP),
+        ("xochip", Quirks::XOCHIP),
+    ];
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::VIP
+    }
+}
+
+/// Returned when parsing a preset name that doesn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPreset(pub String);
+
+impl fmt::Display for UnknownPreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown quirks preset '{}', expected one of:", self.0)?;
+        for (name, _) in Quirks::PRESETS.iter() {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnknownPreset {}
+
+impl FromStr for Quirks {
+    type Err = UnknownPreset;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalized = name.to_ascii_lowercase().replace('-', "");
+        match Quirks::PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == normalized)
+        {
+            Some((_, quirks)) => Ok(*quirks),
+            None => Err(UnknownPreset(name.to_string())),
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! A bounded history of recent machine states for rewinding.
+//!
+//! Only the newest state is kept in full. Every older frame is stored as the
+//! XOR of its state with the one after it, run-length encoded. Consecutive
+//! frames rarely differ by more than a few registers and some pixels, so
+//! most deltas are a handful of bytes.
+
+use std::collections::VecDeque;
+
+use crate::Chip8;
+
+pub struct Rewind {
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    // deltas.back() turns `newest` into the frame before it.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Creates a history that keeps at most `capacity` frames to rewind to.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// How many frames can currently be rewound.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    /// Records the machine's current state as the newest frame.
+    pub fn push(&mut self, chip8: &Chip8) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let state = chip8.save_state();
+        if let Some(newest) = self.newest.take() {
+            self.deltas.push_back(encode_delta(&state, &newest));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.newest = Some(state);
+    }
+
+    /// Steps the machine back one frame. Returns false once the history
+    /// is exhausted.
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> bool {
+        let (newest, delta) = match (self.newest.as_ref(), self.deltas.pop_back()) {
+            (Some(newest), Some(delta)) => (newest, delta),
+            _ => return false,
+        };
+
+        let previous = match apply_delta(newest, &delta) {
+            Some(previous) if chip8.load_state(&previous).is_ok() => previous,
+            _ => {
+                self.clear();
+                return false;
+            }
+        };
+        self.newest = Some(previous);
+        true
+    }
+
+    /// Total bytes held by the history, for diagnostics.
+    pub fn memory_usage(&self) -> usize {
+        self.newest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+// A delta is the length of the older state followed by runs of
+// (unchanged byte count, changed byte count, changed bytes XORed), with
+// counts stored as LEB128 varints.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let len = from.len().max(to.len());
+    let xor = |i: usize| from.get(i).unwrap_or(&0) ^ to.get(i).unwrap_or(&0);
+
+    let mut delta = vec![];
+    write_varint(&mut delta, to.len());
+
+    let mut i = 0;
+    while i < len {
+        let start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+        let same = i - start;
+
+        let start = i;
+        while i < len && xor(i) != 0 {
+            i += 1;
+        }
+        write_varint(&mut delta, same);
+        write_varint(&mut delta, i - start);
+        delta.extend((start..i).map(xor));
+    }
+
+    delta
+}
+
+// Returns None if the delta is malformed.
+fn apply_delta(from: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos)?;
+
+    let mut to = from.to_vec();
+    to.resize(len.max(from.len()), 0);
+
+    let mut i = 0usize;
+    while pos < delta.len() {
+        i = i.checked_add(read_varint(delta, &mut pos)?)?;
+        let changed = read_varint(delta, &mut pos)?;
+        let bytes = delta.get(pos..pos.checked_add(changed)?)?;
+        for byte in bytes {
+            *to.get_mut(i)? ^= byte;
+            i += 1;
+        }
+        pos += changed;
+    }
+
+    to.truncate(len);
+    Some(to)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut value = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        if shift >= usize::BITS {
+            return None;
+        }
+        value |= ((byte & 0x7F) as usize).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(from: &[u8], to: &[u8]) {
+        let delta = encode_delta(from, to);
+        assert_eq!(apply_delta(from, &delta).as_deref(), Some(to));
+    }
+
+    #[test]
+    fn deltas_round_trip() {
+        round_trip(&[], &[]);
+        round_trip(&[1, 2, 3], &[1, 2, 3]);
+        round_trip(&[1, 2, 3, 4, 5], &[1, 9, 3, 4, 7]);
+        let big: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut changed = big.clone();
+        changed[0] = 0xFF;
+        changed[500..700].iter_mut().for_each(|b| *b ^= 0x55);
+        round_trip(&big, &changed);
+        assert!(encode_delta(&big, &big).len() < 8);
+    }
+
+    #[test]
+    fn deltas_between_states_of_different_lengths() {
+        round_trip(&[1, 2, 3], &[1, 2, 3, 0, 0, 6]);
+        round_trip(&[1, 2, 3, 4, 5, 6], &[1, 2]);
+        round_trip(&[], &[7, 0, 7]);
+        round_trip(&[7, 0, 7], &[]);
+    }
+
+    #[test]
+    fn malformed_deltas_are_rejected() {
+        let from = [1, 2, 3, 4];
+        let delta = encode_delta(&from, &[1, 2, 9, 9, 9, 9]);
+        // Cut-off deltas may or may not be valid, but never panic
+        for len in 0..delta.len() {
+            let _ = apply_delta(&from, &delta[..len]);
+        }
+        assert_eq!(apply_delta(&from, &[]), None);
+        assert_eq!(apply_delta(&from, &[0x80]), None);
+        assert_eq!(apply_delta(&from, &[0xFF; 16]), None);
+        // Runs past the end of the state or of the delta
+        assert_eq!(apply_delta(&from, &[4, 9, 1, 0xAA]), None);
+        assert_eq!(apply_delta(&from, &[4, 0, 3, 0xAA]), None);
+    }
+
+    fn counter(frames: u8) -> Chip8 {
+        // Counts frames in V0
+        let mut chip8 = Chip8::new();
+        chip8.load_bytes(&[0x60, frames]).unwrap();
+        chip8.emulate_cycle().unwrap();
+        chip8
+    }
+
+    #[test]
+    fn rewinds_each_frame_until_empty() {
+        let mut rewind = Rewind::new(10);
+        for frame in 0..4 {
+            rewind.push(&counter(frame));
+        }
+        assert_eq!(rewind.len(), 3);
+
+        let mut chip8 = counter(3);
+        for frame in (0..3).rev() {
+            assert!(rewind.rewind(&mut chip8));
+            assert_eq!(chip8.save_state(), counter(frame).save_state());
+        }
+        assert!(rewind.is_empty());
+        assert!(!rewind.rewind(&mut chip8));
+        assert_eq!(chip8.save_state(), counter(0).save_state());
+    }
+
+    #[test]
+    fn oldest_frames_are_evicted_at_capacity() {
+        let mut rewind = Rewind::new(2);
+        for frame in 0..6 {
+            rewind.push(&counter(frame));
+        }
+        assert_eq!(rewind.len(), 2);
+
+        let mut chip8 = counter(5);
+        assert!(rewind.rewind(&mut chip8));
+        assert!(rewind.rewind(&mut chip8));
+        assert_eq!(chip8.save_state(), counter(3).save_state());
+        assert!(!rewind.rewind(&mut chip8));
+
+        let mut rewind = Rewind::new(0);
+        rewind.push(&counter(0));
+        rewind.push(&counter(1));
+        assert!(rewind.is_empty());
+    }
+}
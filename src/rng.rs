@@ -0,0 +1,202 @@
+//! Random number sources for CXNN.
+//!
+//! The machine's generator is part of its state, so runs started from the
+//! same seed, or restored from the same save state, produce the same values.
+
+/// A generator that CXNN draws bytes from.
+pub trait RandomSource {
+    fn next_u8(&mut self) -> u8;
+
+    /// The generator's complete internal state, for save states.
+    fn state(&self) -> Vec<u8>;
+
+    /// Restores a state returned by `state`. Returns false, leaving the
+    /// generator unchanged, if the bytes aren't a valid state for it.
+    fn set_state(&mut self, state: &[u8]) -> bool;
+}
+
+/// The seed used when none is given.
+pub const DEFAULT_SEED: u64 = 0x0C8;
+
+/// A small xorshift64* generator. This is the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero, so nudge a zero seed.
+        Self {
+            state: if seed == 0 {
+                0x9E37_79B9_7F4A_7C15
+            } else {
+                seed
+            },
+        }
+    }
+}
+
+impl Default for XorShift {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
+
+impl RandomSource for XorShift {
+    fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn state(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> bool {
+        let mut bytes = [0; 8];
+        if state.len() != bytes.len() {
+            return false;
+        }
+        bytes.copy_from_slice(state);
+
+        let state = u64::from_le_bytes(bytes);
+        if state == 0 {
+            return false;
+        }
+        self.state = state;
+        true
+    }
+}
+
+/// A short-period generator in the style of the COSMAC VIP interpreter's
+/// random routine.
+///
+/// The VIP stepped a pointer through its own interpreter code and added the
+/// byte found there to a running sum. We don't ship the VIP interpreter, so
+/// the pointer cycles through the built-in font instead. This does not
+/// reproduce the VIP's values; it gives a generator with a similarly short
+/// period, 80 steps of the pointer times the cycles the sum takes to return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VipRandom {
+    pointer: u8,
+    sum: u8,
+}
+
+impl VipRandom {
+    const TABLE: &'static [u8] = &crate::chip8::CHIP8_FONTSET;
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            pointer: (seed as u8 as usize % Self::TABLE.len()) as u8,
+            sum: (seed >> 8) as u8,
+        }
+    }
+}
+
+impl RandomSource for VipRandom {
+    fn next_u8(&mut self) -> u8 {
+        self.pointer = ((self.pointer as usize + 1) % Self::TABLE.len()) as u8;
+        self.sum = self.sum.wrapping_add(Self::TABLE[self.pointer as usize]);
+        self.sum
+    }
+
+    fn state(&self) -> Vec<u8> {
+        vec![self.pointer, self.sum]
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> bool {
+        match state {
+            &[pointer, sum] if (pointer as usize) < Self::TABLE.len() => {
+                self.pointer = pointer;
+                self.sum = sum;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(rng: &mut dyn RandomSource, n: usize) -> Vec<u8> {
+        (0..n).map(|_| rng.next_u8()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_values() {
+        assert_eq!(
+            take(&mut XorShift::new(42), 64),
+            take(&mut XorShift::new(42), 64)
+        );
+        assert_ne!(
+            take(&mut XorShift::new(42), 64),
+            take(&mut XorShift::new(43), 64)
+        );
+        assert_eq!(
+            take(&mut VipRandom::new(42), 64),
+            take(&mut VipRandom::new(42), 64)
+        );
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut rng = XorShift::new(7);
+        take(&mut rng, 5);
+        let mut copy = XorShift::default();
+        assert!(copy.set_state(&rng.state()));
+        assert_eq!(take(&mut copy, 16), take(&mut rng, 16));
+
+        let mut rng = VipRandom::new(0x1234);
+        take(&mut rng, 100);
+        let mut copy = VipRandom::new(0);
+        assert!(copy.set_state(&rng.state()));
+        assert_eq!(take(&mut copy, 200), take(&mut rng, 200));
+    }
+
+    #[test]
+    fn set_state_rejects_invalid_states() {
+        let mut rng = XorShift::new(7);
+        assert!(!rng.set_state(&[0; 8]));
+        assert!(!rng.set_state(&[1; 7]));
+        assert_eq!(rng, XorShift::new(7));
+
+        let mut rng = VipRandom::new(7);
+        assert!(!rng.set_state(&[80, 0]));
+        assert!(!rng.set_state(&[1]));
+        assert!(!rng.set_state(&[1, 2, 3]));
+        assert_eq!(rng, VipRandom::new(7));
+    }
+
+    #[test]
+    fn vip_random_sums_the_font() {
+        // The font starts F0 90 90 90 F0 20 and the pointer starts past 0
+        let mut rng = VipRandom::new(0);
+        assert_eq!(take(&mut rng, 5), [0x90, 0x20, 0xB0, 0xA0, 0xC0]);
+    }
+
+    #[test]
+    fn vip_random_period() {
+        let mut rng = VipRandom::new(0x0305);
+        let start = rng;
+        let sum: u32 = VipRandom::TABLE.iter().map(|&b| b as u32).sum();
+        let laps = 256 / gcd(sum % 256, 256);
+        let period = (1..=80 * 256).find(|_| {
+            rng.next_u8();
+            rng == start
+        });
+        assert_eq!(period, Some(80 * laps as usize));
+    }
+
+    fn gcd(a: u32, b: u32) -> u32 {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! The binary save state format.
+//!
+//! A state is the 4-byte magic `C8SS`, a little-endian `u16` format version,
+//! the machine state written field by field by
+//! [`Chip8::save_state`](crate::Chip8::save_state), and finally a CRC-32 of
+//! everything before it.
+
+use crate::error::StateError;
+use crate::platform::Platform;
+use crate::quirks::{IndexIncrement, Quirks};
+
+pub const MAGIC: &[u8; 4] = b"C8SS";
+pub const VERSION: u16 = 2;
+
+/// Appends little-endian fields to a state being saved.
+pub(crate) struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::with_header(MAGIC, VERSION)
+    }
+
+    /// Starts a file in the same layout as a state, but with its own magic
+    /// and version.
+    pub fn with_header(magic: &[u8; 4], version: u16) -> Self {
+        let mut buf = magic.to_vec();
+        buf.extend_from_slice(&version.to_le_bytes());
+        Self { buf }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.buf.extend_from_slice(value);
+    }
+
+    /// Writes a length-prefixed byte string.
+    pub fn blob(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.bytes(value);
+    }
+
+    pub fn platform(&mut self, platform: Platform) {
+        self.u8(match platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+    }
+
+    pub fn quirks(&mut self, quirks: &Quirks) {
+        self.bool(quirks.shift_uses_vy);
+        self.u8(match quirks.load_store {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => 1,
+            IndexIncrement::ByXPlusOne => 2,
+        });
+        self.bool(quirks.jump_uses_vx);
+        self.bool(quirks.vf_reset);
+        self.bool(quirks.index_overflow_sets_vf);
+        self.bool(quirks.display_wait);
+        self.bool(quirks.clip_sprites);
+    }
+
+    /// Appends the checksum and returns the finished state.
+    pub fn finish(mut self) -> Vec<u8> {
+        let crc = crc32(&self.buf);
+        self.u32(crc);
+        self.buf
+    }
+}
+
+/// Reads fields back out of a saved state, in the order they were written.
+pub(crate) struct StateReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the header and checksum and positions the reader at the first
+    /// field.
+    pub fn new(state: &'a [u8]) -> Result<Self, StateError> {
+        Self::with_header(state, MAGIC, VERSION)
+    }
+
+    /// Like `new`, for files written by `StateWriter::with_header`.
+    pub fn with_header(
+        state: &'a [u8],
+        magic: &[u8; 4],
+        expected_version: u16,
+    ) -> Result<Self, StateError> {
+        if state.len() < magic.len() + 2 + 4 {
+            return Err(StateError::Truncated);
+        }
+        if &state[..4] != magic {
+            return Err(StateError::BadMagic);
+        }
+
+        let version = u16::from_le_bytes([state[4], state[5]]);
+        if version != expected_version {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let (body, crc) = state.split_at(state.len() - 4);
+        if crc32(body) != u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            return Err(StateError::ChecksumMismatch);
+        }
+
+        Ok(Self { buf: &body[6..] })
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.buf.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn blob(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    pub fn platform(&mut self) -> Result<Platform, StateError> {
+        match self.u8()? {
+            0 => Ok(Platform::Chip8),
+            1 => Ok(Platform::SuperChip),
+            2 => Ok(Platform::XoChip),
+            _ => Err(StateError::Invalid("platform")),
+        }
+    }
+
+    pub fn quirks(&mut self) -> Result<Quirks, StateError> {
+        Ok(Quirks {
+            shift_uses_vy: self.bool()?,
+            load_store: match self.u8()? {
+                0 => IndexIncrement::Unchanged,
+                1 => IndexIncrement::ByX,
+                2 => IndexIncrement::ByXPlusOne,
+                _ => return Err(StateError::Invalid("load/store quirk")),
+            },
+            jump_uses_vx: self.bool()?,
+            vf_reset: self.bool()?,
+            index_overflow_sets_vf: self.bool()?,
+            display_wait: self.bool()?,
+            clip_sprites: self.bool()?,
+        })
+    }
+
+    /// Fails unless every field has been read.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid("trailing data"))
+        }
+    }
+}
+
+/// CRC-32 (IEEE), as used by zlib and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
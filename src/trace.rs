@@ -0,0 +1,458 @@
+//! Execution tracing: a log of every instruction run, with the registers
+//! it changed.
+//!
+//! Traces contain no timing or addresses of host memory, so two runs of
+//! the same ROM with the same seed and inputs give identical output, and
+//! a trace can be diffed against another emulator's.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::address::parse_address;
+use crate::disasm::{self, Syntax};
+use crate::instruction::decode;
+
+/// Entries kept when no size is given, about a minute at the default speed.
+pub const DEFAULT_TRACE_SIZE: usize = 100_000;
+
+/// How `Tracer::write` lays out entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// One aligned line per instruction, for reading and `diff`.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl TraceFormat {
+    pub const NAMES: [(&'static str, TraceFormat); 2] = [
+        ("text", TraceFormat::Text),
+        ("jsonl", TraceFormat::JsonLines),
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTraceFormat(String);
+
+impl fmt::Display for UnknownTraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = TraceFormat::NAMES.iter().map(|(name, _)| *name).collect();
+        write!(
+            f,
+            "unknown trace format '{}', expected one of: {}",
+            self.0,
+            names.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownTraceFormat {}
+
+impl FromStr for TraceFormat {
+    type Err = UnknownTraceFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TraceFormat::NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(_, format)| format)
+            .ok_or_else(|| UnknownTraceFormat(s.to_string()))
+    }
+}
+
+/// A value an instruction changed, with its old and new contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    V(usize, u8, u8),
+    I(usize, usize),
+    Sp(usize, usize),
+    Delay(u8, u8),
+    Sound(u8, u8),
+}
+
+impl Change {
+    fn name(&self) -> String {
+        match self {
+            Change::V(x, ..) => format!("V{:X}", x),
+            Change::I(..) => "I".to_string(),
+            Change::Sp(..) => "SP".to_string(),
+            Change::Delay(..) => "DT".to_string(),
+            Change::Sound(..) => "ST".to_string(),
+        }
+    }
+
+    fn values(&self) -> (usize, usize) {
+        match *self {
+            Change::V(_, old, new) | Change::Delay(old, new) | Change::Sound(old, new) => {
+                (old as usize, new as usize)
+            }
+            Change::I(old, new) | Change::Sp(old, new) => (old, new),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (old, new) = self.values();
+        match self {
+            Change::I(..) => write!(f, "{} {:04X}->{:04X}", self.name(), old, new),
+            _ => write!(f, "{} {:02X}->{:02X}", self.name(), old, new),
+        }
+    }
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// How many instructions ran before this one since tracing started,
+    /// counting the ones filtered out.
+    pub cycle: u64,
+    pub pc: usize,
+    pub opcode: u16,
+    /// The word after the opcode, which F000 NNNN uses as its address.
+    pub operand: u16,
+    pub changes: Vec<Change>,
+}
+
+impl TraceEntry {
+    fn mnemonic(&self) -> String {
+        disasm::mnemonic(decode(self.opcode), self.operand, Syntax::Octo)
+    }
+
+    fn text(&self) -> String {
+        let changes: Vec<String> = self.changes.iter().map(|c| c.to_string()).collect();
+        let line = format!(
+            "{:>10}  {:04X}  {:04X}  {:<24}  {}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            self.mnemonic(),
+            changes.join("  ")
+        );
+        line.trim_end().to_string()
+    }
+
+    fn json(&self) -> String {
+        let changes: Vec<String> = self
+            .changes
+            .iter()
+            .map(|c| {
+                let (old, new) = c.values();
+                format!("\"{}\":[{},{}]", c.name(), old, new)
+            })
+            .collect();
+        format!(
+            "{{\"cycle\":{},\"pc\":{},\"opcode\":\"{:04X}\",\"asm\":\"{}\",\"changes\":{{{}}}}}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            escape(&self.mnemonic()),
+            changes.join(",")
+        )
+    }
+}
+
+// Escapes `s` for use inside a JSON string.
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The parts of the machine an instruction's changes are reported for.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Snapshot {
+    pub registers: [u8; 16],
+    pub index: usize,
+    pub sp: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+/// Records executed instructions into a ring buffer, keeping the most
+/// recent `capacity` entries.
+///
+/// Entries can be limited to an address range and to instruction types,
+/// given as Cowgod mnemonics such as `DRW` or `CALL`. Attach a tracer
+/// with `Chip8::set_tracer`.
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    capacity: usize,
+    range: Option<RangeInclusive<usize>>,
+    only: Vec<String>,
+    entries: VecDeque<TraceEntry>,
+    cycle: u64,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRACE_SIZE)
+    }
+}
+
+impl Tracer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            range: None,
+            only: vec![],
+            entries: VecDeque::new(),
+            cycle: 0,
+        }
+    }
+
+    /// Only records instructions at addresses from `start` to `end`.
+    pub fn set_range(&mut self, start: usize, end: usize) {
+        self.range = Some(start..=end);
+    }
+
+    /// Only records instructions with these Cowgod mnemonics, matched
+    /// without regard to case. An empty list records everything.
+    pub fn set_only<S: AsRef<str>>(&mut self, mnemonics: &[S]) {
+        self.only = mnemonics
+            .iter()
+            .map(|m| m.as_ref().to_ascii_uppercase())
+            .collect();
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Writes every entry, oldest first.
+    pub fn write(&self, out: &mut dyn Write, format: TraceFormat) -> io::Result<()> {
+        for entry in self.entries.iter() {
+            match format {
+                TraceFormat::Text => writeln!(out, "{}", entry.text())?,
+                TraceFormat::JsonLines => writeln!(out, "{}", entry.json())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn wants(&self, pc: usize, opcode: u16) -> bool {
+        if let Some(range) = &self.range {
+            if !range.contains(&pc) {
+                return false;
+            }
+        }
+        if self.only.is_empty() {
+            return true;
+        }
+
+        let mnemonic = disasm::mnemonic(decode(opcode), 0, Syntax::Cowgod);
+        let name = mnemonic.split_whitespace().next().unwrap_or("");
+        self.only.iter().any(|only| only == name)
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        pc: usize,
+        opcode: u16,
+        operand: u16,
+        before: &Snapshot,
+        after: &Snapshot,
+    ) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if !self.wants(pc, opcode) {
+            return;
+        }
+
+        let mut changes: Vec<Change> = (0..16)
+            .filter(|&x| before.registers[x] != after.registers[x])
+            .map(|x| Change::V(x, before.registers[x], after.registers[x]))
+            .collect();
+        if before.index != after.index {
+            changes.push(Change::I(before.index, after.index));
+        }
+        if before.sp != after.sp {
+            changes.push(Change::Sp(before.sp, after.sp));
+        }
+        if before.delay_timer != after.delay_timer {
+            changes.push(Change::Delay(before.delay_timer, after.delay_timer));
+        }
+        if before.sound_timer != after.sound_timer {
+            changes.push(Change::Sound(before.sound_timer, after.sound_timer));
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry {
+            cycle,
+            pc,
+            opcode,
+            operand,
+            changes,
+        });
+    }
+}
+
+/// Parses an address range written `START-END`, in hex.
+pub fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (start, end) = s.split_once('-')?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    if start <= end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            registers: [0; 16],
+            index: 0,
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+        }
+    }
+
+    // Records each instruction at consecutive addresses from 0x200 with
+    // nothing changed.
+    fn record(tracer: &mut Tracer, opcodes: &[u16]) {
+        for (i, &opcode) in opcodes.iter().enumerate() {
+            tracer.record(0x200 + i * 2, opcode, 0, &snapshot(), &snapshot());
+        }
+    }
+
+    fn cycles(tracer: &Tracer) -> Vec<(u64, usize)> {
+        tracer.entries().map(|e| (e.cycle, e.pc)).collect()
+    }
+
+    fn written(tracer: &Tracer, format: TraceFormat) -> String {
+        let mut out = vec![];
+        tracer.write(&mut out, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn keeps_the_most_recent_entries() {
+        let mut tracer = Tracer::new(2);
+        record(&mut tracer, &[0x6001, 0x6002, 0x6003]);
+        assert_eq!(cycles(&tracer), [(1, 0x202), (2, 0x204)]);
+
+        let mut tracer = Tracer::new(0);
+        record(&mut tracer, &[0x6001, 0x6002]);
+        assert_eq!(cycles(&tracer), [(1, 0x202)]);
+
+        tracer.clear();
+        assert_eq!(tracer.entries().count(), 0);
+    }
+
+    #[test]
+    fn filters_by_address_range() {
+        let mut tracer = Tracer::new(10);
+        tracer.set_range(0x202, 0x204);
+        record(&mut tracer, &[0x6001, 0x6002, 0x6003, 0x6004]);
+        // Cycles still count the instructions filtered out
+        assert_eq!(cycles(&tracer), [(1, 0x202), (2, 0x204)]);
+    }
+
+    #[test]
+    fn filters_by_mnemonic() {
+        let mut tracer = Tracer::new(10);
+        tracer.set_only(&["drw", "CALL"]);
+        record(&mut tracer, &[0x6001, 0xD015, 0x2300, 0x00EE]);
+        assert_eq!(cycles(&tracer), [(1, 0x202), (2, 0x204)]);
+
+        tracer.set_only::<&str>(&[]);
+        record(&mut tracer, &[0x00EE]);
+        assert_eq!(tracer.entries().count(), 3);
+    }
+
+    #[test]
+    fn captures_what_changed() {
+        let before = Snapshot {
+            registers: [1; 16],
+            index: 0x300,
+            sp: 1,
+            delay_timer: 9,
+            sound_timer: 4,
+        };
+        let mut after = before;
+        after.registers[0xF] = 0;
+        after.registers[2] = 7;
+        after.index = 0x123;
+        after.sp = 0;
+        after.delay_timer = 8;
+        after.sound_timer = 0;
+
+        let mut tracer = Tracer::new(10);
+        tracer.record(0x200, 0x00EE, 0, &before, &after);
+        tracer.record(0x202, 0x1202, 0, &after, &after);
+        let changes: Vec<&[Change]> = tracer.entries().map(|e| &e.changes[..]).collect();
+        assert_eq!(
+            changes,
+            [
+                &[
+                    Change::V(2, 1, 7),
+                    Change::V(0xF, 1, 0),
+                    Change::I(0x300, 0x123),
+                    Change::Sp(1, 0),
+                    Change::Delay(9, 8),
+                    Change::Sound(4, 0),
+                ][..],
+                &[][..],
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_text_and_json_lines() {
+        let mut after = snapshot();
+        after.registers[1] = 0x2A;
+        after.index = 0x10;
+        let mut tracer = Tracer::new(10);
+        tracer.record(0x200, 0x612A, 0, &snapshot(), &after);
+        tracer.record(0x202, 0xF000, 0x1234, &after, &after);
+
+        assert_eq!(
+            written(&tracer, TraceFormat::Text),
+            "         0  0200  612A  v1 := 0x2A                V1 00->2A  I 0000->0010\n\
+             \x20        1  0202  F000  i := long 0x1234\n"
+        );
+        assert_eq!(
+            written(&tracer, TraceFormat::JsonLines),
+            "{\"cycle\":0,\"pc\":512,\"opcode\":\"612A\",\"asm\":\"v1 := 0x2A\",\
+             \"changes\":{\"V1\":[0,42],\"I\":[0,16]}}\n\
+             {\"cycle\":1,\"pc\":514,\"opcode\":\"F000\",\"asm\":\"i := long 0x1234\",\
+             \"changes\":{}}\n"
+        );
+        assert_eq!(escape("say \"hi\"\\\n"), "say \\\"hi\\\"\\\\\\u000a");
+    }
+
+    #[test]
+    fn parses_ranges_and_formats() {
+        assert_eq!(parse_range("200-2FF"), Some((0x200, 0x2FF)));
+        assert_eq!(parse_range("0x300-$300"), Some((0x300, 0x300)));
+        assert_eq!(parse_range("300-200"), None);
+        assert_eq!(parse_range("200"), None);
+        assert_eq!("JSONL".parse(), Ok(TraceFormat::JsonLines));
+        assert_eq!(
+            "csv".parse::<TraceFormat>().unwrap_err().to_string(),
+            "unknown trace format 'csv', expected one of: text, jsonl"
+        );
+    }
+}
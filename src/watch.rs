@@ -0,0 +1,253 @@
+//! Watchpoints: stop when memory is touched or a register changes.
+//!
+//! Memory watchpoints only see data accesses made by instructions (FX33,
+//! FX55, FX65, DXYN and the XO-CHIP equivalents), not instruction fetches.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::address::parse_address;
+
+/// A value a condition can test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    V(usize),
+    I,
+    Memory(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn test(self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// An instruction reads the byte at this address.
+    Read(usize),
+    /// An instruction writes the byte at this address.
+    Write(usize),
+    /// VX changes value. Only the low four bits of X are used.
+    Register(usize),
+    /// I changes value.
+    Index,
+    /// The condition becomes true. It doesn't fire again until it has been
+    /// false in between.
+    Condition(Operand, Comparison, u16),
+}
+
+/// A watchpoint firing, reported after the instruction that triggered it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// The address of the instruction that triggered the watchpoint.
+    pub pc: usize,
+    pub watchpoint: Watchpoint,
+    /// The value before and after the instruction. For reads, both hold
+    /// the value read.
+    pub old: u16,
+    pub new: u16,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::V(x) => write!(f, "V{:X}", x),
+            Operand::I => write!(f, "I"),
+            Operand::Memory(addr) => write!(f, "[{:#05X}]", addr),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watchpoint::Read(addr) => write!(f, "read {:#05X}", addr),
+            Watchpoint::Write(addr) => write!(f, "write {:#05X}", addr),
+            Watchpoint::Register(x) => write!(f, "V{:X}", x),
+            Watchpoint::Index => write!(f, "I"),
+            Watchpoint::Condition(lhs, cmp, rhs) => {
+                write!(f, "{} {} {:#X}", lhs, cmp.symbol(), rhs)
+            }
+        }
+    }
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "watch {} at {:#05X}: {:#X} -> {:#X}",
+            self.watchpoint, self.pc, self.old, self.new
+        )
+    }
+}
+
+fn parse_operand(s: &str) -> Option<Operand> {
+    let upper = s.to_ascii_uppercase();
+    if upper == "I" {
+        return Some(Operand::I);
+    }
+    if let Some(reg) = upper.strip_prefix('V') {
+        let x = usize::from_str_radix(reg, 16).ok()?;
+        return if x < 16 { Some(Operand::V(x)) } else { None };
+    }
+    if let Some(addr) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        return parse_address(addr).map(Operand::Memory);
+    }
+    None
+}
+
+fn parse_comparison(s: &str) -> Option<Comparison> {
+    Some(match s {
+        "==" => Comparison::Eq,
+        "!=" => Comparison::Ne,
+        "<" => Comparison::Lt,
+        "<=" => Comparison::Le,
+        ">" => Comparison::Gt,
+        ">=" => Comparison::Ge,
+        _ => return None,
+    })
+}
+
+/// Returned when a watchpoint expression can't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadWatchpoint(pub String);
+
+impl fmt::Display for BadWatchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "bad watchpoint '{}', expected e.g. 'read 300', 'write 300', 'V3', 'I' or 'V3 == 10'",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for BadWatchpoint {}
+
+/// Parses `read ADDR`, `write ADDR`, `VX`, `I` or `OPERAND CMP VALUE`, where
+/// an operand is `VX`, `I` or `[ADDR]` and all numbers are hex.
+impl FromStr for Watchpoint {
+    type Err = BadWatchpoint;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let watchpoint = match words.as_slice() {
+            ["read", addr] | ["r", addr] => parse_address(addr).map(Watchpoint::Read),
+            ["write", addr] | ["w", addr] => parse_address(addr).map(Watchpoint::Write),
+            [operand] => match parse_operand(operand) {
+                Some(Operand::V(x)) => Some(Watchpoint::Register(x)),
+                Some(Operand::I) => Some(Watchpoint::Index),
+                _ => None,
+            },
+            [lhs, cmp, rhs] => match (parse_operand(lhs), parse_comparison(cmp)) {
+                (Some(lhs), Some(cmp)) => parse_address(rhs)
+                    .filter(|&rhs| rhs <= 0xFFFF)
+                    .map(|rhs| Watchpoint::Condition(lhs, cmp, rhs as u16)),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        watchpoint.ok_or_else(|| BadWatchpoint(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<Watchpoint> {
+        s.parse().ok()
+    }
+
+    #[test]
+    fn parses_watchpoints() {
+        assert_eq!(parse("read 300"), Some(Watchpoint::Read(0x300)));
+        assert_eq!(parse("r $300"), Some(Watchpoint::Read(0x300)));
+        assert_eq!(parse("  write   0x2A0 "), Some(Watchpoint::Write(0x2A0)));
+        assert_eq!(parse("w 2a0"), Some(Watchpoint::Write(0x2A0)));
+        assert_eq!(parse("V3"), Some(Watchpoint::Register(3)));
+        assert_eq!(parse("vf"), Some(Watchpoint::Register(0xF)));
+        assert_eq!(parse("i"), Some(Watchpoint::Index));
+        assert_eq!(
+            parse("V3 == 10"),
+            Some(Watchpoint::Condition(Operand::V(3), Comparison::Eq, 0x10))
+        );
+        assert_eq!(
+            parse("I != 0x200"),
+            Some(Watchpoint::Condition(Operand::I, Comparison::Ne, 0x200))
+        );
+        assert_eq!(
+            parse("[300] >= ff"),
+            Some(Watchpoint::Condition(
+                Operand::Memory(0x300),
+                Comparison::Ge,
+                0xFF
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_watchpoints() {
+        for s in [
+            "",
+            "read",
+            "read zz",
+            "write 300 301",
+            "V10",
+            "VG",
+            "[300]",
+            "V3 =~ 1",
+            "V3 == 10000",
+            "[300 == 1",
+            "3 == V3",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                s.parse::<Watchpoint>(),
+                Err(BadWatchpoint(s.to_string())),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn display_parses_back() {
+        for s in ["read 300", "write 2A0", "VA", "I", "[300] < 7", "I > 0x200"].iter() {
+            let watchpoint = parse(s).unwrap();
+            assert_eq!(parse(&watchpoint.to_string()), Some(watchpoint));
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Runs ROMs headlessly and compares the final screen against golden
+//! hashes.
+//!
+//! The conformance ROMs in `tests/roms` are our own Octo sources, written
+//! to cover the same ground as the community opcode, flags, quirks and
+//! keypad test ROMs. All but `quirks.8o` start with `check.8o`, and draw a
+//! tick for every check that passes and a cross for every one that fails,
+//! so their golden screens are all ticks. The games in `roms/` are
+//! regression tests: the hashes are whatever they showed when the goldens
+//! were recorded.
+//!
+//! `community_suite` runs the community test ROMs themselves once they are
+//! vendored in `tests/roms/community`, see the README there. Until then it
+//! is ignored.
+//!
+//! On a mismatch the test prints the screen and its hash, so a golden can
+//! be updated after checking the picture by eye.
+
+use std::fs;
+
+use chip8::{octo, Chip8, Movie, Platform, Quirks};
+
+// Screens with a tick for every check.
+const OPCODES_PASSED: u64 = 0x6242_195D_30D5_70A2;
+const FLAGS_PASSED: u64 = 0x9FD4_1E4F_0AF1_EE77;
+const KEYPAD_PASSED: u64 = 0xD52F_0669_C8F6_C6E4;
+
+const BLANK_SCREEN: u64 = 0x0B8B_5650_919E_108D;
+
+/// Holds a key down for some frames: (first frame, key, frames).
+type Press = (u64, usize, u64);
+
+fn compile(files: &[&str]) -> Vec<u8> {
+    let mut source = String::new();
+    for file in files {
+        let path = format!("tests/roms/{}", file);
+        source += &fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    }
+    octo::compile(&source).unwrap_or_else(|e| panic!("{}: {}", files.join(" + "), e))
+}
+
+fn run(
+    rom: &[u8],
+    platform: Platform,
+    quirks: Option<Quirks>,
+    frames: u64,
+    presses: &[Press],
+) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.set_platform(platform);
+    if let Some(quirks) = quirks {
+        chip8.set_quirks(quirks);
+    }
+    chip8.load_bytes(rom).unwrap();
+
+    for frame in 0..frames {
+        for (key, state) in chip8.keypad.iter_mut().enumerate() {
+            let held = presses
+                .iter()
+                .any(|&(start, k, len)| k == key && (start..start + len).contains(&frame));
+            *state = held as u8;
+        }
+        chip8
+            .run_frame()
+            .unwrap_or_else(|e| panic!("frame {}: {}", frame, e));
+    }
+    chip8
+}
+
+// 64-bit FNV-1a over the screen size and pixels.
+fn screen_hash(chip8: &Chip8) -> u64 {
+    let mut hash = 0xCBF2_9CE4_8422_2325u64;
+    let size = [chip8.gfx.width() as u8, chip8.gfx.height() as u8];
+    for &byte in size.iter().chain(chip8.gfx.as_slice()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash
+}
+
+fn assert_screen(name: &str, chip8: &Chip8, golden: u64) {
+    let hash = screen_hash(chip8);
+    assert!(
+        hash == golden,
+        "{}: screen hash {:#018X}, expected {:#018X}\n{}",
+        name,
+        hash,
+        golden,
+        chip8.gfx.to_ascii()
+    );
+}
+
+#[test]
+fn opcodes() {
+    let rom = compile(&["check.8o", "opcodes.8o"]);
+    for &platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip].iter() {
+        let chip8 = run(&rom, platform, None, 60, &[]);
+        assert_screen(&format!("opcodes on {}", platform), &chip8, OPCODES_PASSED);
+    }
+}
+
+#[test]
+fn flags() {
+    let rom = compile(&["check.8o", "flags.8o"]);
+    for &platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip].iter() {
+        let chip8 = run(&rom, platform, None, 60, &[]);
+        assert_screen(&format!("flags on {}", platform), &chip8, FLAGS_PASSED);
+    }
+}
+
+#[test]
+fn quirks() {
+    let rom = compile(&["quirks.8o"]);
+    // The digits are 0 1 2 1 0 0 for VIP, 7 4 1 2 0 0 for CHIP-48,
+    // 7 4 0 2 0 0 for SUPER-CHIP and 7 1 2 1 1 0 for XO-CHIP
+    let cases = [
+        ("vip", Quirks::VIP, 0x9DEA_0020_FCBD_B605),
+        ("chip48", Quirks::CHIP48, 0x3F68_A78B_9935_F33F),
+        ("schip", Quirks::SCHIP, 0x5612_0C6C_D8B8_6557),
+        ("xochip", Quirks::XOCHIP, 0xAEEF_0EC8_F97B_C5B5),
+    ];
+    for &(name, quirks, golden) in cases.iter() {
+        let chip8 = run(&rom, Platform::Chip8, Some(quirks), 60, &[]);
+        assert_screen(&format!("{} quirks", name), &chip8, golden);
+    }
+}
+
+#[test]
+fn keypad() {
+    let rom = compile(&["check.8o", "keypad.8o"]);
+
+    // Nothing happens until a key is pressed
+    let chip8 = run(&rom, Platform::Chip8, None, 30, &[]);
+    assert_screen("keypad before a press", &chip8, BLANK_SCREEN);
+
+    let chip8 = run(&rom, Platform::Chip8, None, 60, &[(10, 5, 20)]);
+    assert_screen("keypad", &chip8, KEYPAD_PASSED);
+}
+
+#[test]
+fn games() {
+    let games = [
+        ("15PUZZLE", 0x3241_FA93_628E_5916),
+        ("BLINKY", 0x616C_86BA_CF22_5E04),
+        ("BLITZ", 0xD11B_D36D_C310_041D),
+        ("BRIX", 0xC8E2_C533_85D2_6FC2),
+        ("CONNECT4", 0xF22C_2E21_E9BC_10D3),
+        ("GUESS", 0x9C75_7EE7_D226_3CD5),
+        ("HIDDEN", 0xEFF7_6D1A_63E1_3681),
+        ("INVADERS", 0x339A_E3CB_6B67_7404),
+        ("KALEID", 0x63DB_E016_842B_4D29),
+        ("MAZE", 0x31F1_2EB7_0CA8_108D),
+        ("MERLIN", 0x2B28_3D6D_8F24_95E0),
+        ("MISSILE", 0x9E09_DFA5_E7F6_0E9D),
+        ("PONG", 0x5DC1_C2EB_FC74_8641),
+        ("PONG2", 0x5110_4254_9448_5AD2),
+        ("PUZZLE", 0x4A99_8574_82DA_C76C),
+        ("SYZYGY", 0xE273_7D38_38CA_7E99),
+        ("TANK", 0x0052_406E_8F65_D4AC),
+        ("TETRIS", 0x61B1_8268_2E0E_1655),
+        ("TICTAC", 0xC9E1_9268_F97E_99E6),
+        ("UFO", 0x6370_A964_81E0_AEF5),
+        ("VBRIX", 0x7998_BC61_4FC3_0C81),
+        ("VERS", 0xD126_47A3_4A2D_02F7),
+        ("WIPEOFF", 0x85AF_C771_2277_8695),
+    ];
+    for &(name, golden) in games.iter() {
+        let rom = fs::read(format!("roms/{}", name)).unwrap();
+        let chip8 = run(&rom, Platform::Chip8, None, 600, &[]);
+        assert_screen(name, &chip8, golden);
+    }
+}
+
+#[test]
+fn movie_replay() {
+    let rom = fs::read("roms/BRIX").unwrap();
+    let presses = [(5, 4, 30), (60, 6, 40), (120, 4, 15)];
+
+    // Record a run with a VIP random generator and some input
+    let mut chip8 = Chip8::new();
+    chip8.set_quirks(Quirks::VIP);
+    chip8.set_rng(Box::new(chip8::VipRandom::new(1234)));
+    let mut movie = Movie::new(&chip8, &rom, 1234, true);
+    chip8.load_bytes(&rom).unwrap();
+    for frame in 0..300 {
+        for (key, state) in chip8.keypad.iter_mut().enumerate() {
+            let held = presses
+                .iter()
+                .any(|&(start, k, len)| k == key && (start..start + len).contains(&frame));
+            *state = held as u8;
+        }
+        movie.record(&chip8.keypad);
+        chip8.run_frame().unwrap();
+    }
+
+    let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+    assert_eq!(movie.len(), 300);
+    movie.check_rom(&rom).unwrap();
+    assert!(movie.check_rom(b"\x12\x00").is_err());
+
+    let mut replay = Chip8::new();
+    movie.apply(&mut replay);
+    replay.load_bytes(&rom).unwrap();
+    for frame in 0..movie.len() {
+        replay.keypad = movie.keypad(frame).unwrap();
+        replay.run_frame().unwrap();
+    }
+    assert_eq!(replay.save_state(), chip8.save_state());
+}
+
+#[test]
+#[ignore = "the community test ROMs are not vendored yet, see tests/roms/community/README"]
+fn community_suite() {
+    let dir = "tests/roms/community";
+    let golden = fs::read_to_string(format!("{}/golden.txt", dir)).unwrap();
+    let mut checked = 0;
+    for line in golden.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (name, platform, frames, mode, hash) = match *fields.as_slice() {
+            [name, platform, frames, mode, hash] => (name, platform, frames, mode, hash),
+            _ => panic!("golden.txt: bad line '{}'", line),
+        };
+        let platform: Platform = platform.parse().unwrap();
+        let frames: u64 = frames.parse().unwrap();
+        let mode: u8 = mode.parse().unwrap();
+        let hash = u64::from_str_radix(hash.trim_start_matches("0x"), 16).unwrap();
+
+        let rom = fs::read(format!("{}/{}", dir, name))
+            .unwrap_or_else(|e| panic!("{}/{}: {}", dir, name, e));
+        // The byte before the program picks a test without the menu
+        let image: Vec<u8> = std::iter::once(mode).chain(rom).collect();
+        let mut chip8 = Chip8::new();
+        chip8.set_platform(platform);
+        chip8.set_load_address(0x1FF);
+        chip8.load_bytes(&image).unwrap();
+        chip8.set_load_address(0x200);
+        for frame in 0..frames {
+            chip8
+                .run_frame()
+                .unwrap_or_else(|e| panic!("{} frame {}: {}", name, frame, e));
+        }
+        assert_screen(&format!("{} on {}", name, platform), &chip8, hash);
+        checked += 1;
+    }
+    assert!(
+        checked > 0,
+        "no community ROMs listed in {}/golden.txt",
+        dir
+    );
+}